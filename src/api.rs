@@ -1,111 +1,631 @@
+use crate::chunking::ChunkingConfig;
+use crate::crawl::{self, CrawlOptions};
+use crate::extract::{extract, FileKind};
+use crate::filter::MetadataFilter;
 use crate::generation::generate_answer;
-use crate::ingestion::{segment_text, store_passage};
-use crate::retrieval::search_top_k;
-use crate::types::{IngestRequest, IngestResponse, QuestionRequest};
-use crate::utils::compute_text_embedding;
+use crate::ingestion::{
+    self, create_document, create_file_document, validate_text, NewDocuments, Progress,
+};
+use crate::retrieval::{search_top_k, SearchOptions};
+use crate::sources;
+use crate::types::{
+    Callback, ChunkingOptions, Document, FieldValue, IngestRequest, IngestResponse, JobAccepted,
+    JobResponse, Metadata, Passage, PassageListQuery, PassageListResponse, PassageQuery,
+    PassageResponse, QuestionRequest, SourceRequest, SourceResponse, UrlIngestRequest,
+};
+use crate::webhook;
 use crate::AppState;
-use actix_web::{post, web, HttpResponse, Responder};
-use futures::stream::{FuturesUnordered, StreamExt};
-use std::sync::Arc;
+use actix_multipart::Multipart;
+use actix_web::{delete, get, patch, post, put, web, HttpResponse, Responder};
+use futures::stream::StreamExt;
+use mongodb::bson::oid::ObjectId;
+use mongodb::bson::DateTime;
+
+/// Chunking of a request: its overrides on top of the server defaults.
+fn resolve_chunking(
+    state: &AppState,
+    options: Option<ChunkingOptions>,
+) -> Result<ChunkingConfig, String> {
+    options.unwrap_or_default().resolve(state.config.chunking)
+}
+
+/// Summary of a synchronous ingestion, answered with `207 Multi-Status` when some
+/// passages could not be stored.
+fn ingested(document: Document, progress: &Progress) -> HttpResponse {
+    let response = IngestResponse {
+        document_id: document.id.map(|id| id.to_hex()).unwrap_or_default(),
+        count: document.passage_ids.len(),
+        passage_ids: document.passage_ids,
+        near_duplicates: progress.near_duplicates(),
+        passages: progress.outcomes(),
+    };
+
+    if response.is_partial() {
+        HttpResponse::MultiStatus().json(response)
+    } else {
+        HttpResponse::Ok().json(response)
+    }
+}
 
 #[post("/ingest")]
 pub async fn ingest(state: web::Data<AppState>, req: web::Json<IngestRequest>) -> impl Responder {
-    let db_name = &state.config.database_name;
-    let collection_name = &state.config.collection_name;
-    let client = &state.db_client;
-
-    if req.text.len() > 1_000_000 {
-        return HttpResponse::BadRequest().json("Texte trop volumineux");
+    if let Err(e) = validate_text(&req.text) {
+        return HttpResponse::BadRequest().json(e);
     }
 
-    if req.text.trim().is_empty() {
-        return HttpResponse::BadRequest().json("Texte vide");
+    let chunking = match resolve_chunking(&state, req.chunking) {
+        Ok(chunking) => chunking,
+        Err(e) => return HttpResponse::BadRequest().json(e),
+    };
+
+    let req = req.into_inner();
+    let callback = match req.callback_url {
+        Some(_) if !req.background => {
+            return HttpResponse::BadRequest().json("callback_url requiert \"async\": true");
+        }
+        Some(url) => {
+            if let Err(e) = webhook::validate_url(&url) {
+                return HttpResponse::BadRequest().json(e);
+            }
+            Some(Callback {
+                url,
+                signed: req.callback_secret.is_some(),
+                delivery: None,
+            })
+        }
+        None => None,
+    };
+
+    if req.background {
+        return match state
+            .jobs
+            .submit(
+                &state,
+                req.text,
+                req.metadata,
+                chunking,
+                callback,
+                req.callback_secret,
+            )
+            .await
+        {
+            Ok(job_id) => HttpResponse::Accepted()
+                .insert_header(("Location", format!("/jobs/{}", job_id)))
+                .json(JobAccepted { job_id }),
+            Err(e) => HttpResponse::InternalServerError()
+                .json(format!("Erreur lors de la création de la tâche: {}", e)),
+        };
     }
 
-    let passages = segment_text(&req.text, req.metadata.clone(), &state.tokenizer);
+    let progress = Progress::default();
+    let document = match create_document(&state, &req.text, req.metadata, chunking, &progress).await
+    {
+        Ok(document) => document,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(format!(
+                "Erreur lors de l'enregistrement du document: {}",
+                e
+            ));
+        }
+    };
+
+    ingested(document, &progress)
+}
+
+/// Upper bound on an uploaded file, before extraction.
+pub const MAX_UPLOAD_BYTES: usize = 20 * 1024 * 1024;
+
+/// Ingests a `.md`, `.html`, `.txt`, `.pdf`, `.docx` or `.odt` file sent as the `file`
+/// part of a multipart form.
+///
+/// An optional `metadata` part holds the same JSON as in `/ingest`; a missing
+/// `title` or `source` is taken from the file.
+#[post("/ingest/file")]
+pub async fn ingest_file(state: web::Data<AppState>, mut payload: Multipart) -> impl Responder {
+    let mut file: Option<(String, Vec<u8>)> = None;
+    let mut metadata: Option<Metadata> = None;
+    let mut chunking: Option<ChunkingOptions> = None;
+
+    while let Some(field) = payload.next().await {
+        let mut field = match field {
+            Ok(field) => field,
+            Err(e) => {
+                return HttpResponse::BadRequest().json(format!("Formulaire invalide: {}", e))
+            }
+        };
 
-    let tasks = FuturesUnordered::new();
-    let model = Arc::new(&state.model);
-    let tokenizer = Arc::new(state.tokenizer.clone());
-    let device = Arc::new(state.device.clone());
-    let client_arc = Arc::new(client.clone());
+        let name = field.name().unwrap_or_default().to_string();
+        let file_name = field
+            .content_disposition()
+            .and_then(|cd| cd.get_filename())
+            .map(str::to_string);
 
-    for mut p in passages {
-        let model = Arc::clone(&model);
-        let tokenizer = Arc::clone(&tokenizer);
-        let device = Arc::clone(&device);
-        let client = Arc::clone(&client_arc);
+        let mut data = Vec::new();
+        while let Some(chunk) = field.next().await {
+            match chunk {
+                Ok(chunk) if data.len() + chunk.len() <= MAX_UPLOAD_BYTES => {
+                    data.extend_from_slice(&chunk)
+                }
+                Ok(_) => return HttpResponse::PayloadTooLarge().json("Fichier trop volumineux"),
+                Err(e) => {
+                    return HttpResponse::BadRequest().json(format!("Formulaire invalide: {}", e));
+                }
+            }
+        }
 
-        tasks.push(async move {
-            let embedding = match compute_text_embedding(&model, &tokenizer, &device, &p.text).await
-            {
-                Ok(emb) => emb,
+        match name.as_str() {
+            "file" => {
+                let Some(file_name) = file_name else {
+                    return HttpResponse::BadRequest().json("Nom de fichier manquant");
+                };
+                file = Some((file_name, data));
+            }
+            "metadata" => match serde_json::from_slice(&data) {
+                Ok(m) => metadata = m,
                 Err(e) => {
-                    eprintln!(
-                        "Impossible de calculer l'embedding pour le passage {:?}: {}",
-                        &p.id, e
-                    );
-                    return None;
+                    return HttpResponse::BadRequest()
+                        .json(format!("Métadonnées invalides: {}", e));
                 }
-            };
+            },
+            "chunking" => match serde_json::from_slice(&data) {
+                Ok(c) => chunking = c,
+                Err(e) => {
+                    return HttpResponse::BadRequest()
+                        .json(format!("Options de découpage invalides: {}", e));
+                }
+            },
+            _ => {}
+        }
+    }
 
-            let vec2 = embedding.to_vec2().ok()?;
-            p.embedding = vec2[0].clone();
+    let Some((file_name, data)) = file else {
+        return HttpResponse::BadRequest().json("Champ 'file' manquant");
+    };
 
-            store_passage(p, &client, db_name, collection_name)
-                .await
-                .ok()
-        });
+    let chunking = match resolve_chunking(&state, chunking) {
+        Ok(chunking) => chunking,
+        Err(e) => return HttpResponse::BadRequest().json(e),
+    };
+
+    let Some(kind) = FileKind::from_name(&file_name) else {
+        return HttpResponse::UnsupportedMediaType().json(format!(
+            "Format de fichier non pris en charge: {}",
+            file_name
+        ));
+    };
+
+    let extracted = match extract(kind, &data) {
+        Ok(extracted) => extracted,
+        Err(e) => return HttpResponse::BadRequest().json(e.to_string()),
+    };
+
+    if let Err(e) = validate_text(&extracted.text) {
+        return HttpResponse::BadRequest().json(e);
+    }
+
+    let progress = Progress::default();
+    let document = create_file_document(
+        &state, &file_name, kind, extracted, metadata, chunking, &progress,
+    )
+    .await;
+
+    let document = match document {
+        Ok(document) => document,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(format!(
+                "Erreur lors de l'enregistrement du document: {}",
+                e
+            ));
+        }
+    };
+
+    ingested(document, &progress)
+}
+
+/// Crawls a web page or sitemap and the same-domain pages it links to, ingesting
+/// each page as a document.
+#[post("/ingest/url")]
+pub async fn ingest_url(
+    state: web::Data<AppState>,
+    req: web::Json<UrlIngestRequest>,
+) -> impl Responder {
+    let req = req.into_inner();
+    let start = match crawl::parse_start_url(&req.url) {
+        Ok(start) => start,
+        Err(e) => return HttpResponse::BadRequest().json(e),
+    };
+
+    let options = CrawlOptions {
+        depth: req.depth.unwrap_or(crawl::DEFAULT_DEPTH),
+        max_pages: req.max_pages.unwrap_or(crawl::DEFAULT_MAX_PAGES),
+    };
+    if let Err(e) = options.validate() {
+        return HttpResponse::BadRequest().json(e);
+    }
+
+    let chunking = match resolve_chunking(&state, req.chunking) {
+        Ok(chunking) => chunking,
+        Err(e) => return HttpResponse::BadRequest().json(e),
+    };
+
+    let mut sink = NewDocuments(chunking);
+    match crawl::crawl(&state, start, options, req.metadata, &mut sink).await {
+        Ok(response) => HttpResponse::Ok().json(response),
+        Err(e) => {
+            HttpResponse::InternalServerError().json(format!("Erreur lors de l'exploration: {}", e))
+        }
+    }
+}
+
+#[get("/jobs/{id}")]
+pub async fn get_job(state: web::Data<AppState>, id: web::Path<String>) -> impl Responder {
+    if ObjectId::parse_str(id.as_str()).is_err() {
+        return HttpResponse::BadRequest().json("Identifiant de tâche invalide");
+    }
+
+    let job = match state.store.get_job(&id).await {
+        Ok(Some(job)) => job,
+        Ok(None) => return HttpResponse::NotFound().json("Tâche introuvable"),
+        Err(e) => {
+            return HttpResponse::InternalServerError()
+                .json(format!("Erreur lors de la lecture de la tâche: {}", e));
+        }
+    };
+
+    let mut response = JobResponse::from(job);
+    if let Some((progress, errors)) = state.jobs.progress(&id) {
+        response.progress = progress;
+        response.errors = errors;
+    }
+    HttpResponse::Ok().json(response)
+}
+
+#[post("/sources")]
+pub async fn create_source(
+    state: web::Data<AppState>,
+    req: web::Json<SourceRequest>,
+) -> impl Responder {
+    let mut source = match sources::new_source(&state, req.into_inner()) {
+        Ok(source) => source,
+        Err(e) => return HttpResponse::BadRequest().json(e),
+    };
+
+    match state.store.insert_source(source.clone()).await {
+        Ok(id) => {
+            source.id = id.parse().ok();
+            HttpResponse::Created()
+                .insert_header(("Location", format!("/sources/{}", id)))
+                .json(SourceResponse::from(source))
+        }
+        Err(e) => HttpResponse::InternalServerError().json(format!(
+            "Erreur lors de l'enregistrement de la source: {}",
+            e
+        )),
+    }
+}
+
+#[get("/sources")]
+pub async fn list_sources(state: web::Data<AppState>) -> impl Responder {
+    match state.store.list_sources().await {
+        Ok(sources) => HttpResponse::Ok().json(
+            sources
+                .into_iter()
+                .map(SourceResponse::from)
+                .collect::<Vec<_>>(),
+        ),
+        Err(e) => HttpResponse::InternalServerError()
+            .json(format!("Erreur lors de la lecture des sources: {}", e)),
+    }
+}
+
+#[get("/sources/{id}")]
+pub async fn get_source(state: web::Data<AppState>, id: web::Path<String>) -> impl Responder {
+    if ObjectId::parse_str(id.as_str()).is_err() {
+        return HttpResponse::BadRequest().json("Identifiant de source invalide");
+    }
+
+    match state.store.get_source(&id).await {
+        Ok(Some(source)) => HttpResponse::Ok().json(SourceResponse::from(source)),
+        Ok(None) => HttpResponse::NotFound().json("Source introuvable"),
+        Err(e) => HttpResponse::InternalServerError()
+            .json(format!("Erreur lors de la lecture de la source: {}", e)),
+    }
+}
+
+/// Unregisters a source; the documents it ingested are kept.
+#[delete("/sources/{id}")]
+pub async fn delete_source(state: web::Data<AppState>, id: web::Path<String>) -> impl Responder {
+    if ObjectId::parse_str(id.as_str()).is_err() {
+        return HttpResponse::BadRequest().json("Identifiant de source invalide");
+    }
+
+    match state.store.delete_source(&id).await {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().json("Source introuvable"),
+        Err(e) => HttpResponse::InternalServerError()
+            .json(format!("Erreur lors de la suppression de la source: {}", e)),
+    }
+}
+
+/// Makes a source due, so that the scheduler synchronizes it at its next round.
+#[post("/sources/{id}/sync")]
+pub async fn sync_source(state: web::Data<AppState>, id: web::Path<String>) -> impl Responder {
+    if ObjectId::parse_str(id.as_str()).is_err() {
+        return HttpResponse::BadRequest().json("Identifiant de source invalide");
+    }
+
+    let mut source = match state.store.get_source(&id).await {
+        Ok(Some(source)) => source,
+        Ok(None) => return HttpResponse::NotFound().json("Source introuvable"),
+        Err(e) => {
+            return HttpResponse::InternalServerError()
+                .json(format!("Erreur lors de la lecture de la source: {}", e));
+        }
+    };
+
+    source.next_sync_at = DateTime::now();
+    match state.store.replace_source(&source).await {
+        Ok(_) => HttpResponse::Accepted().json(SourceResponse::from(source)),
+        Err(e) => HttpResponse::InternalServerError().json(format!(
+            "Erreur lors de l'enregistrement de la source: {}",
+            e
+        )),
+    }
+}
+
+#[get("/documents/{id}")]
+pub async fn get_document(state: web::Data<AppState>, id: web::Path<String>) -> impl Responder {
+    if ObjectId::parse_str(id.as_str()).is_err() {
+        return HttpResponse::BadRequest().json("Identifiant de document invalide");
+    }
+
+    match state.store.get_document(&id).await {
+        Ok(Some(document)) => HttpResponse::Ok().json(document),
+        Ok(None) => HttpResponse::NotFound().json("Document introuvable"),
+        Err(e) => HttpResponse::InternalServerError()
+            .json(format!("Erreur lors de la lecture du document: {}", e)),
+    }
+}
+
+#[put("/documents/{id}")]
+pub async fn update_document(
+    state: web::Data<AppState>,
+    id: web::Path<String>,
+    req: web::Json<IngestRequest>,
+) -> impl Responder {
+    if ObjectId::parse_str(id.as_str()).is_err() {
+        return HttpResponse::BadRequest().json("Identifiant de document invalide");
+    }
+
+    if let Err(e) = validate_text(&req.text) {
+        return HttpResponse::BadRequest().json(e);
+    }
+
+    let chunking = match req.chunking.map(|c| resolve_chunking(&state, Some(c))) {
+        Some(Ok(chunking)) => Some(chunking),
+        Some(Err(e)) => return HttpResponse::BadRequest().json(e),
+        None => None,
+    };
+
+    let req = req.into_inner();
+    match ingestion::update_document(&state, &id, &req.text, req.metadata, chunking).await {
+        Ok(Some(updated)) if updated.is_partial() => HttpResponse::MultiStatus().json(updated),
+        Ok(Some(updated)) => HttpResponse::Ok().json(updated),
+        Ok(None) => HttpResponse::NotFound().json("Document introuvable"),
+        Err(e) => HttpResponse::InternalServerError()
+            .json(format!("Erreur lors de la mise à jour du document: {}", e)),
+    }
+}
+
+#[delete("/documents/{id}")]
+pub async fn delete_document(state: web::Data<AppState>, id: web::Path<String>) -> impl Responder {
+    if ObjectId::parse_str(id.as_str()).is_err() {
+        return HttpResponse::BadRequest().json("Identifiant de document invalide");
+    }
+
+    match ingestion::delete_document(state.store.as_ref(), &id).await {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().json("Document introuvable"),
+        Err(e) => HttpResponse::InternalServerError()
+            .json(format!("Erreur lors de la suppression du document: {}", e)),
+    }
+}
+
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 500;
+
+#[get("/passages")]
+pub async fn list_passages(
+    state: web::Data<AppState>,
+    query: web::Query<PassageListQuery>,
+) -> impl Responder {
+    if let Some(cursor) = &query.cursor
+        && ObjectId::parse_str(cursor).is_err()
+    {
+        return HttpResponse::BadRequest().json("Curseur invalide");
     }
 
-    let passage_ids: Vec<String> = tasks
-        .collect::<Vec<_>>()
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let filter = query
+        .source
+        .clone()
+        .map(|source| MetadataFilter::field_equals("source", FieldValue::Text(source)));
+
+    let passages = match state
+        .store
+        .list(query.cursor.as_deref(), limit, filter.as_ref())
         .await
-        .into_iter()
-        .filter_map(|res| res)
-        .collect();
+    {
+        Ok(passages) => passages,
+        Err(e) => {
+            return HttpResponse::InternalServerError()
+                .json(format!("Erreur lors de la lecture des passages: {}", e));
+        }
+    };
 
-    HttpResponse::Ok().json(IngestResponse {
-        count: passage_ids.len(),
-        passage_ids,
+    let next_cursor = if passages.len() == limit {
+        passages.last().and_then(|p| p.id).map(|id| id.to_hex())
+    } else {
+        None
+    };
+
+    HttpResponse::Ok().json(PassageListResponse {
+        passages: passages
+            .into_iter()
+            .map(|p| PassageResponse::new(p, query.include_embedding))
+            .collect(),
+        next_cursor,
     })
 }
 
+#[get("/passages/{id}")]
+pub async fn get_passage(
+    state: web::Data<AppState>,
+    id: web::Path<String>,
+    query: web::Query<PassageQuery>,
+) -> impl Responder {
+    if ObjectId::parse_str(id.as_str()).is_err() {
+        return HttpResponse::BadRequest().json("Identifiant de passage invalide");
+    }
+
+    match state.store.get_many(&[id.into_inner()]).await {
+        Ok(passages) => match passages.into_iter().next() {
+            Some(passage) => {
+                HttpResponse::Ok().json(PassageResponse::new(passage, query.include_embedding))
+            }
+            None => HttpResponse::NotFound().json("Passage introuvable"),
+        },
+        Err(e) => HttpResponse::InternalServerError()
+            .json(format!("Erreur lors de la lecture du passage: {}", e)),
+    }
+}
+
+#[delete("/passages/{id}")]
+pub async fn delete_passage(state: web::Data<AppState>, id: web::Path<String>) -> impl Responder {
+    if ObjectId::parse_str(id.as_str()).is_err() {
+        return HttpResponse::BadRequest().json("Identifiant de passage invalide");
+    }
+
+    match ingestion::delete_passage(state.store.as_ref(), &id).await {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().json("Passage introuvable"),
+        Err(e) => HttpResponse::InternalServerError()
+            .json(format!("Erreur lors de la suppression du passage: {}", e)),
+    }
+}
+
+/// Applies a JSON merge patch (RFC 7386): fields set to `null` are removed, objects
+/// are merged recursively and any other value replaces the current one.
+fn merge_patch(target: &mut serde_json::Value, patch: serde_json::Value) {
+    let serde_json::Value::Object(fields) = patch else {
+        *target = patch;
+        return;
+    };
+    if !target.is_object() {
+        *target = serde_json::Value::Object(Default::default());
+    }
+    let object = target.as_object_mut().unwrap();
+    for (key, value) in fields {
+        if value.is_null() {
+            object.remove(&key);
+        } else {
+            merge_patch(object.entry(key).or_insert(serde_json::Value::Null), value);
+        }
+    }
+}
+
+/// Updates the metadata fields given in the body, as a JSON merge patch; `null`
+/// clears a field, or the whole metadata.
+#[patch("/passages/{id}/metadata")]
+pub async fn update_passage_metadata(
+    state: web::Data<AppState>,
+    id: web::Path<String>,
+    req: web::Json<serde_json::Value>,
+) -> impl Responder {
+    if ObjectId::parse_str(id.as_str()).is_err() {
+        return HttpResponse::BadRequest().json("Identifiant de passage invalide");
+    }
+
+    let store = state.store.as_ref();
+    let passage = match store.get_many(&[id.to_string()]).await {
+        Ok(passages) => match passages.into_iter().next() {
+            Some(passage) => passage,
+            None => return HttpResponse::NotFound().json("Passage introuvable"),
+        },
+        Err(e) => {
+            return HttpResponse::InternalServerError()
+                .json(format!("Erreur lors de la lecture du passage: {}", e));
+        }
+    };
+
+    let mut metadata = match serde_json::to_value(&passage.metadata) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            return HttpResponse::InternalServerError()
+                .json(format!("Erreur lors de la lecture du passage: {}", e));
+        }
+    };
+    merge_patch(&mut metadata, req.into_inner());
+    let metadata: Option<Metadata> = match serde_json::from_value(metadata) {
+        Ok(metadata) => metadata,
+        Err(e) => return HttpResponse::BadRequest().json(format!("Métadonnées invalides: {}", e)),
+    };
+
+    match store.set_metadata(&id, metadata.clone()).await {
+        Ok(true) => HttpResponse::Ok().json(PassageResponse::new(
+            Passage {
+                metadata,
+                ..passage
+            },
+            false,
+        )),
+        Ok(false) => HttpResponse::NotFound().json("Passage introuvable"),
+        Err(e) => HttpResponse::InternalServerError()
+            .json(format!("Erreur lors de la mise à jour du passage: {}", e)),
+    }
+}
+
 #[post("/ask")]
 pub async fn ask(state: web::Data<AppState>, req: web::Json<QuestionRequest>) -> impl Responder {
-    let db_name = &state.config.database_name;
-    let collection_name = &state.config.collection_name;
-    let client = &state.db_client;
+    let store = state.store.as_ref();
 
-    let question_embedding_tensor =
-        match compute_text_embedding(&state.model, &state.tokenizer, &state.device, &req.question)
-            .await
-        {
-            Ok(t) => t,
-            Err(e) => {
-                return HttpResponse::InternalServerError()
-                    .json(format!("Erreur embedding question: {}", e));
-            }
-        };
+    if let Some(lambda) = req.mmr_lambda
+        && !(0.0..=1.0).contains(&lambda)
+    {
+        return HttpResponse::BadRequest().json("mmr_lambda doit être compris entre 0 et 1");
+    }
 
-    let question_embedding: Vec<f32> = match question_embedding_tensor.to_vec2() {
-        Ok(v) => v[0].clone(),
+    if let Some(filter) = &req.filter
+        && let Err(e) = filter.validate()
+    {
+        return HttpResponse::BadRequest().json(e);
+    }
+
+    let question_embedding = match state.embedder.embed(&req.question).await {
+        Ok(embedding) => embedding,
         Err(e) => {
             return HttpResponse::InternalServerError()
-                .json(format!("Erreur conversion Tensor -> Vec: {}", e));
+                .json(format!("Erreur embedding question: {}", e));
         }
     };
 
-    let top_k = 6;
-    let max_char_limit = Some(800);
+    let options = SearchOptions {
+        k: 6,
+        mode: req.mode,
+        mmr_lambda: req.mmr_lambda,
+        filter: req.filter.as_ref(),
+        fetch_limit: Some(800),
+    };
 
     let passages = match search_top_k(
+        &req.question,
         &question_embedding,
-        top_k,
-        client,
-        db_name,
-        collection_name,
-        max_char_limit,
+        &options,
+        store,
+        state.reranker.as_ref(),
     )
     .await
     {
@@ -147,3 +667,42 @@ pub async fn ask(state: web::Data<AppState>, req: web::Json<QuestionRequest>) ->
         .append_header(("Content-Type", "text/event-stream"))
         .streaming(sse_stream)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn merge_patch_keeps_fields_not_given() {
+        let mut metadata = json!({
+            "title": "Proxy",
+            "source": "manuel-v2",
+            "tags": ["réseau"],
+            "extra": { "version": 3, "team": "infra" }
+        });
+        merge_patch(
+            &mut metadata,
+            json!({ "title": "Proxy HTTP", "tags": ["réseau", "http"], "extra": { "team": null } }),
+        );
+        assert_eq!(
+            metadata,
+            json!({
+                "title": "Proxy HTTP",
+                "source": "manuel-v2",
+                "tags": ["réseau", "http"],
+                "extra": { "version": 3 }
+            })
+        );
+    }
+
+    #[test]
+    fn merge_patch_on_missing_or_cleared_metadata() {
+        let mut metadata = serde_json::Value::Null;
+        merge_patch(&mut metadata, json!({ "source": "faq", "date": null }));
+        assert_eq!(metadata, json!({ "source": "faq" }));
+
+        merge_patch(&mut metadata, serde_json::Value::Null);
+        assert!(metadata.is_null());
+    }
+}
@@ -0,0 +1,366 @@
+use crate::api::MAX_UPLOAD_BYTES;
+use crate::chunking::ChunkingConfig;
+use crate::cli::IngestArgs;
+use crate::extract::{extract, FileKind};
+use crate::ingestion::{create_file_document, validate_text, Progress};
+use crate::types::{Document, Metadata, PassageStatus};
+use crate::utils::compute_hash;
+use crate::AppState;
+use anyhow::{anyhow, Context, Result};
+use flate2::read::GzDecoder;
+use futures::channel::mpsc::{channel, Receiver, Sender};
+use futures::executor::block_on;
+use futures::{SinkExt, StreamExt};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use indicatif::{ProgressBar, ProgressStyle};
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::thread::JoinHandle;
+use walkdir::WalkDir;
+
+/// Files read ahead of the one being embedded.
+const READ_AHEAD: usize = 8;
+
+/// What the reader thread found in the directory or archive.
+pub enum Entry {
+    /// Number of files to ingest, when known before reading them.
+    Total(u64),
+    File {
+        path: String,
+        data: Vec<u8>,
+    },
+    Unsupported,
+    Failed {
+        path: String,
+        error: String,
+    },
+}
+
+/// Include and exclude globs on paths relative to the ingested root.
+pub struct FileFilter {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl FileFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self> {
+        let build = |patterns: &[String]| -> Result<GlobSet> {
+            let mut set = GlobSetBuilder::new();
+            for pattern in patterns {
+                // A glob without a separator applies to the file name, at any depth.
+                let pattern = if pattern.contains('/') {
+                    pattern.clone()
+                } else {
+                    format!("**/{}", pattern)
+                };
+                let glob = GlobBuilder::new(&pattern)
+                    .literal_separator(true)
+                    .build()
+                    .with_context(|| format!("Motif invalide: {}", pattern))?;
+                set.add(glob);
+            }
+            Ok(set.build()?)
+        };
+
+        Ok(Self {
+            include: (!include.is_empty()).then(|| build(include)).transpose()?,
+            exclude: build(exclude)?,
+        })
+    }
+
+    /// Whether a file is selected; hidden files and directories never are.
+    fn matches(&self, path: &str) -> bool {
+        if path.split('/').any(|part| part.starts_with('.')) {
+            return false;
+        }
+        self.include.as_ref().is_none_or(|set| set.is_match(path)) && !self.exclude.is_match(path)
+    }
+}
+
+/// Counts reported at the end of a bulk ingestion.
+#[derive(Default)]
+pub struct Summary {
+    pub documents: usize,
+    pub passages: usize,
+    /// Files whose text is already stored as a document.
+    pub duplicate_files: usize,
+    /// Passages already stored, identically or as the near-duplicate they were merged into.
+    pub duplicate_passages: usize,
+    /// Passages left out as near-duplicates.
+    pub skipped_passages: usize,
+    pub unsupported: usize,
+    pub failures: usize,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Documents ingérés: {}", self.documents)?;
+        writeln!(f, "Passages: {}", self.passages)?;
+        writeln!(f, "Fichiers en double ignorés: {}", self.duplicate_files)?;
+        writeln!(f, "Passages déjà présents: {}", self.duplicate_passages)?;
+        writeln!(f, "Quasi-doublons ignorés: {}", self.skipped_passages)?;
+        writeln!(f, "Formats non pris en charge: {}", self.unsupported)?;
+        write!(f, "Échecs: {}", self.failures)
+    }
+}
+
+/// Ingests the supported files of a directory, a single file or a `.zip` / `.tar.gz`
+/// archive through the same segmentation, embedding and storage as `/ingest/file`.
+///
+/// Files whose text matches an existing document are skipped. Reading happens on
+/// its own thread while the previous files are embedded.
+pub async fn run(state: &AppState, args: IngestArgs) -> Result<Summary> {
+    let chunking = args
+        .chunking
+        .unwrap_or_default()
+        .resolve(state.config.chunking)
+        .map_err(|e| anyhow!("Options de découpage invalides: {}", e))?;
+    let (mut rx, reader) = spawn_reader(args.path.clone(), &args.include, &args.exclude)?;
+
+    let bar = ProgressBar::new_spinner();
+    bar.set_style(ProgressStyle::with_template(
+        "{spinner} {pos} fichiers {wide_msg}",
+    )?);
+
+    let mut summary = Summary::default();
+    while let Some(entry) = rx.next().await {
+        match entry {
+            Entry::Total(total) => {
+                bar.set_length(total);
+                bar.set_style(ProgressStyle::with_template(
+                    "[{elapsed_precise}] {bar:40} {pos}/{len} {wide_msg}",
+                )?);
+            }
+            Entry::Unsupported => summary.unsupported += 1,
+            Entry::Failed { path, error } => {
+                bar.println(format!("Échec {}: {}", path, error));
+                summary.failures += 1;
+                bar.inc(1);
+            }
+            Entry::File { path, data } => {
+                bar.set_message(path.clone());
+                let progress = Progress::default();
+                let metadata = args.metadata.clone();
+                match ingest_file(state, &path, &data, metadata, chunking, &progress).await {
+                    Ok(Some(document)) => {
+                        summary.documents += 1;
+                        summary.passages += document.passage_ids.len();
+                        for outcome in progress.outcomes() {
+                            match outcome.status {
+                                PassageStatus::Duplicate => summary.duplicate_passages += 1,
+                                PassageStatus::Skipped => summary.skipped_passages += 1,
+                                _ => {}
+                            }
+                        }
+                    }
+                    Ok(None) => summary.duplicate_files += 1,
+                    Err(e) => {
+                        bar.println(format!("Échec {}: {}", path, e));
+                        summary.failures += 1;
+                    }
+                }
+                bar.inc(1);
+            }
+        }
+    }
+    bar.finish_and_clear();
+
+    reader
+        .join()
+        .map_err(|_| anyhow!("Le thread de lecture a paniqué"))??;
+
+    Ok(summary)
+}
+
+/// Reads the files of `root` selected by the globs on a thread of its own, sending
+/// them ahead of their ingestion; the thread ends with the first read error.
+pub fn spawn_reader(
+    root: PathBuf,
+    include: &[String],
+    exclude: &[String],
+) -> Result<(Receiver<Entry>, JoinHandle<Result<()>>)> {
+    let filter = FileFilter::new(include, exclude)?;
+    let (tx, rx) = channel(READ_AHEAD);
+    let reader = std::thread::spawn(move || read_source(&root, &filter, tx));
+    Ok((rx, reader))
+}
+
+/// Stores one file, or returns `None` if a document with the same text exists.
+async fn ingest_file(
+    state: &AppState,
+    path: &str,
+    data: &[u8],
+    metadata: Option<Metadata>,
+    chunking: ChunkingConfig,
+    progress: &Progress,
+) -> Result<Option<Document>, Box<dyn std::error::Error>> {
+    let kind = FileKind::from_name(path).ok_or("Format de fichier non pris en charge")?;
+    let extracted = extract(kind, data)?;
+    validate_text(&extracted.text)?;
+
+    let content_hash = compute_hash(&extracted.text) as i64;
+    if state
+        .store
+        .find_document_by_hash(content_hash)
+        .await?
+        .is_some()
+    {
+        return Ok(None);
+    }
+
+    let document =
+        create_file_document(state, path, kind, extracted, metadata, chunking, progress).await?;
+    Ok(Some(document))
+}
+
+/// Sends the selected files of `root` over `tx`, stopping early if the receiver is gone.
+fn read_source(root: &Path, filter: &FileFilter, tx: Sender<Entry>) -> Result<()> {
+    let name = root
+        .file_name()
+        .map(|n| n.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+
+    let mut sink = EntrySink { tx, filter };
+    if root.is_file() && name.ends_with(".zip") {
+        read_zip(root, &mut sink)
+    } else if root.is_file() && (name.ends_with(".tar.gz") || name.ends_with(".tgz")) {
+        read_tar_gz(root, &mut sink)
+    } else {
+        read_directory(root, &mut sink)
+    }
+}
+
+struct EntrySink<'a> {
+    tx: Sender<Entry>,
+    filter: &'a FileFilter,
+}
+
+impl EntrySink<'_> {
+    /// Whether a file is to be ingested; unsupported formats are reported.
+    fn select(&mut self, path: &str) -> bool {
+        if !self.filter.matches(path) {
+            return false;
+        }
+        if FileKind::from_name(path).is_none() {
+            self.send(Entry::Unsupported);
+            return false;
+        }
+        true
+    }
+
+    /// Reads a selected file, failing it if it exceeds the upload limit.
+    fn read(&mut self, path: String, reader: impl Read) -> bool {
+        let mut data = Vec::new();
+        let entry = match reader
+            .take(MAX_UPLOAD_BYTES as u64 + 1)
+            .read_to_end(&mut data)
+        {
+            Ok(_) if data.len() > MAX_UPLOAD_BYTES => Entry::Failed {
+                path,
+                error: "Fichier trop volumineux".into(),
+            },
+            Ok(_) => Entry::File { path, data },
+            Err(e) => Entry::Failed {
+                path,
+                error: e.to_string(),
+            },
+        };
+        self.send(entry)
+    }
+
+    fn send(&mut self, entry: Entry) -> bool {
+        block_on(self.tx.send(entry)).is_ok()
+    }
+}
+
+fn read_directory(root: &Path, sink: &mut EntrySink) -> Result<()> {
+    let mut files: Vec<(PathBuf, String)> = Vec::new();
+    let walk = WalkDir::new(root)
+        .sort_by_file_name()
+        .into_iter()
+        // Hidden directories such as `.git` are not descended into.
+        .filter_entry(|e| e.depth() == 0 || !e.file_name().to_string_lossy().starts_with('.'));
+    for entry in walk {
+        let entry = entry.with_context(|| format!("Lecture de {} impossible", root.display()))?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        let relative = entry.path().strip_prefix(root).unwrap_or(entry.path());
+        let relative = if relative.as_os_str().is_empty() {
+            // `root` is the file itself.
+            entry.file_name().to_string_lossy().into_owned()
+        } else {
+            relative.to_string_lossy().replace('\\', "/")
+        };
+        if sink.select(&relative) {
+            files.push((entry.into_path(), relative));
+        }
+    }
+
+    if !sink.send(Entry::Total(files.len() as u64)) {
+        return Ok(());
+    }
+    for (path, relative) in files {
+        let sent = match File::open(&path) {
+            Ok(file) => sink.read(relative, file),
+            Err(e) => sink.send(Entry::Failed {
+                path: relative,
+                error: e.to_string(),
+            }),
+        };
+        if !sent {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn read_zip(root: &Path, sink: &mut EntrySink) -> Result<()> {
+    let mut archive = zip::ZipArchive::new(File::open(root)?)
+        .with_context(|| format!("Archive zip illisible: {}", root.display()))?;
+
+    let mut selected = Vec::new();
+    for i in 0..archive.len() {
+        let file = archive.by_index_raw(i)?;
+        if file.is_file() && sink.select(&file.name()?) {
+            selected.push(i);
+        }
+    }
+
+    if !sink.send(Entry::Total(selected.len() as u64)) {
+        return Ok(());
+    }
+    for i in selected {
+        let file = archive.by_index(i)?;
+        let path = file.name()?.into_owned();
+        if !sink.read(path, file) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Reads a gzipped tarball in one pass; its file count is unknown until the end.
+fn read_tar_gz(root: &Path, sink: &mut EntrySink) -> Result<()> {
+    let mut archive = tar::Archive::new(GzDecoder::new(File::open(root)?));
+    let entries = archive
+        .entries()
+        .with_context(|| format!("Archive tar.gz illisible: {}", root.display()))?;
+
+    for entry in entries {
+        let entry =
+            entry.with_context(|| format!("Archive tar.gz illisible: {}", root.display()))?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry.path()?.to_string_lossy().into_owned();
+        let path = path.trim_start_matches("./").to_string();
+        if sink.select(&path) && !sink.read(path, entry) {
+            break;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,954 @@
+use crate::markdown;
+use crate::AppState;
+use async_trait::async_trait;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use tokenizers::Tokenizer;
+
+/// Upper bound on `max_tokens`, the position limit of the embedding model.
+pub const MAX_CHUNK_TOKENS: usize = 512;
+
+/// Context embedded before a passage, its breadcrumb or its path and symbol, is left
+/// room for up to this many tokens; a longer one is truncated to fit.
+pub const MAX_CONTEXT_TOKENS: usize = 128;
+
+pub const DEFAULT_BREAKPOINT_PERCENTILE: u8 = 95;
+
+/// Sentences embedded on each side of a sentence, smoothing the semantic distances.
+const SEMANTIC_WINDOW: usize = 1;
+
+/// How a text is cut into passages.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChunkingStrategy {
+    /// `markdown` when the text has `#` headings, `heuristic` otherwise.
+    #[default]
+    Auto,
+    /// Numbered sections, then paragraphs, then sentences, with overlap.
+    Heuristic,
+    /// Windows of `max_tokens` tokens sliding by `max_tokens - overlap_tokens`.
+    FixedTokens,
+    /// Whole sentences packed up to `max_tokens`, overlapping by whole sentences.
+    SentenceWindow,
+    /// Sections along `#` headings, keeping code blocks and tables intact.
+    Markdown,
+    /// Splits on paragraphs, then lines, sentences and words until pieces fit.
+    RecursiveCharacter,
+    /// Sentences grouped until the meaning shifts, measured with the embedding model.
+    Semantic,
+}
+
+impl FromStr for ChunkingStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ChunkingStrategy::Auto),
+            "heuristic" => Ok(ChunkingStrategy::Heuristic),
+            "fixed_tokens" => Ok(ChunkingStrategy::FixedTokens),
+            "sentence_window" => Ok(ChunkingStrategy::SentenceWindow),
+            "markdown" => Ok(ChunkingStrategy::Markdown),
+            "recursive_character" => Ok(ChunkingStrategy::RecursiveCharacter),
+            "semantic" => Ok(ChunkingStrategy::Semantic),
+            other => Err(format!(
+                "stratégie inconnue: {} (auto, heuristic, fixed_tokens, sentence_window, markdown, recursive_character ou semantic)",
+                other
+            )),
+        }
+    }
+}
+
+/// Chunking strategy and sizes, in tokens of the embedding model's tokenizer.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChunkingConfig {
+    pub strategy: ChunkingStrategy,
+    pub max_tokens: usize,
+    pub overlap_tokens: usize,
+    /// Chunks below this size are dropped, except by the Markdown chunker which
+    /// keeps short sections.
+    pub min_tokens: usize,
+    /// Semantic chunking cuts between sentences whose distance exceeds this percentile
+    /// of all the distances in the text.
+    #[serde(default = "default_breakpoint_percentile")]
+    pub breakpoint_percentile: u8,
+}
+
+fn default_breakpoint_percentile() -> u8 {
+    DEFAULT_BREAKPOINT_PERCENTILE
+}
+
+impl ChunkingConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_tokens == 0 || self.max_tokens > MAX_CHUNK_TOKENS {
+            return Err(format!(
+                "max_tokens doit être compris entre 1 et {}",
+                MAX_CHUNK_TOKENS
+            ));
+        }
+        if self.overlap_tokens >= self.max_tokens {
+            return Err("overlap_tokens doit être inférieur à max_tokens".into());
+        }
+        if self.min_tokens > self.max_tokens {
+            return Err("min_tokens ne peut pas dépasser max_tokens".into());
+        }
+        if !(1..=99).contains(&self.breakpoint_percentile) {
+            return Err("breakpoint_percentile doit être compris entre 1 et 99".into());
+        }
+        Ok(())
+    }
+
+    /// The same configuration for chunks of at most `max_tokens`, with the overlap and
+    /// minimum size scaled down alike.
+    pub fn shrunk_to(self, max_tokens: usize) -> Self {
+        let scale = |tokens: usize| tokens * max_tokens / self.max_tokens;
+        Self {
+            max_tokens,
+            overlap_tokens: scale(self.overlap_tokens),
+            min_tokens: scale(self.min_tokens),
+            ..self
+        }
+    }
+}
+
+/// Piece of text produced by a chunker.
+pub struct Chunk {
+    pub text: String,
+    /// Enclosing headings, outermost first, e.g. `Install > Linux > Proxy`.
+    pub breadcrumb: Option<String>,
+}
+
+impl From<String> for Chunk {
+    fn from(text: String) -> Self {
+        Self {
+            text,
+            breadcrumb: None,
+        }
+    }
+}
+
+#[async_trait(?Send)]
+pub trait Chunker {
+    /// Cuts already cleaned text into chunks, in document order.
+    async fn chunk(&self, text: &str) -> Vec<Chunk>;
+}
+
+/// Builds the chunker for a configuration; `Auto` is resolved against `text`.
+pub fn chunker<'a>(
+    config: ChunkingConfig,
+    state: &'a AppState,
+    text: &str,
+) -> Box<dyn Chunker + 'a> {
+    let tokenizer = &state.tokenizer;
+    let strategy = match config.strategy {
+        ChunkingStrategy::Auto if markdown::has_headings(text) => ChunkingStrategy::Markdown,
+        ChunkingStrategy::Auto => ChunkingStrategy::Heuristic,
+        strategy => strategy,
+    };
+
+    match strategy {
+        ChunkingStrategy::Auto | ChunkingStrategy::Heuristic => {
+            Box::new(HeuristicChunker { tokenizer, config })
+        }
+        ChunkingStrategy::FixedTokens => Box::new(FixedTokenChunker { tokenizer, config }),
+        ChunkingStrategy::SentenceWindow => Box::new(SentenceWindowChunker { tokenizer, config }),
+        ChunkingStrategy::Markdown => Box::new(MarkdownChunker { tokenizer, config }),
+        ChunkingStrategy::RecursiveCharacter => {
+            Box::new(RecursiveCharacterChunker { tokenizer, config })
+        }
+        ChunkingStrategy::Semantic => Box::new(SemanticChunker { state, config }),
+    }
+}
+
+/// Drops chunks under `min_tokens`, unless that would leave nothing.
+fn drop_small(chunks: Vec<String>, tokenizer: &Tokenizer, min_tokens: usize) -> Vec<Chunk> {
+    if chunks.len() <= 1 {
+        return chunks.into_iter().map(Chunk::from).collect();
+    }
+    chunks
+        .into_iter()
+        .filter(|c| count_tokens(tokenizer, c) >= min_tokens)
+        .map(Chunk::from)
+        .collect()
+}
+
+pub fn count_tokens(tokenizer: &Tokenizer, text: &str) -> usize {
+    tokenizer.encode(text, true).unwrap().len()
+}
+
+/// Context lines as they are put before a passage when embedding it.
+pub fn join_context(context: &[&str]) -> String {
+    context.join("\n")
+}
+
+/// Size a passage embedded under `context` can have: `max_tokens`, reduced so that
+/// the passage and its context, up to `MAX_CONTEXT_TOKENS`, fit within the model.
+pub fn passage_budget(tokenizer: &Tokenizer, max_tokens: usize, context: &[&str]) -> usize {
+    if context.is_empty() {
+        return max_tokens;
+    }
+    let context_tokens = tokenizer
+        .encode(join_context(context), false)
+        .map(|e| e.len())
+        .unwrap_or_default();
+    max_tokens.min(MAX_CHUNK_TOKENS - context_tokens.min(MAX_CONTEXT_TOKENS))
+}
+
+/// Longest end of `text` that is at most `n` tokens, starting on a token boundary.
+pub fn tail_tokens<'t>(tokenizer: &Tokenizer, text: &'t str, n: usize) -> &'t str {
+    let Ok(encoding) = tokenizer.encode(text, false) else {
+        return "";
+    };
+    let offsets = encoding.get_offsets();
+
+    // Cutting inside a word can make its end take more tokens, so the tail is re-counted.
+    for &(start, _) in &offsets[offsets.len().saturating_sub(n)..] {
+        let tail = &text[start..];
+        if tokenizer.encode(tail, false).is_ok_and(|e| e.len() <= n) {
+            return tail;
+        }
+    }
+    ""
+}
+
+fn keep_last_tokens(tokenizer: &Tokenizer, text: &str, n: usize) -> String {
+    let encoding = tokenizer.encode(text, true).unwrap();
+    let ids = encoding.get_ids();
+    let start = if ids.len() > n { ids.len() - n } else { 0 };
+    let slice = &ids[start..];
+    tokenizer.decode(slice, true).unwrap()
+}
+
+fn split_sections(text: &str) -> Vec<String> {
+    let re = Regex::new(r"(?m)^(\d+\.\s.*|#{1,6}\s.*)").unwrap();
+    let mut sections = Vec::new();
+    let mut current = String::new();
+
+    for line in text.lines() {
+        if re.is_match(line) && !current.trim().is_empty() {
+            sections.push(current.trim().to_string());
+            current.clear();
+        }
+        current.push_str(line);
+        current.push('\n');
+    }
+
+    if !current.trim().is_empty() {
+        sections.push(current.trim().to_string());
+    }
+
+    sections
+}
+
+pub fn split_sentences(text: &str) -> Vec<String> {
+    let re = Regex::new(r"(?m)([^.!?]*[.!?](?:\s|$))").unwrap();
+    let mut sentences = Vec::new();
+    let mut last_index = 0;
+
+    for mat in re.find_iter(text) {
+        let sentence = text[last_index..mat.end()].trim();
+        if !sentence.is_empty() && sentence.len() > 10 {
+            sentences.push(sentence.to_string());
+        }
+        last_index = mat.end();
+    }
+
+    if last_index < text.len() {
+        let rest = text[last_index..].trim();
+        if !rest.is_empty() {
+            sentences.push(rest.to_string());
+        }
+    }
+
+    if sentences.is_empty() && !text.trim().is_empty() {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        let mut current_sentence = String::new();
+        let mut word_count = 0;
+
+        for word in words {
+            if word_count > 0 {
+                current_sentence.push(' ');
+            }
+            current_sentence.push_str(word);
+            word_count += 1;
+
+            if word_count >= 25 {
+                sentences.push(current_sentence.trim().to_string());
+                current_sentence.clear();
+                word_count = 0;
+            }
+        }
+
+        if !current_sentence.trim().is_empty() {
+            sentences.push(current_sentence.trim().to_string());
+        }
+    }
+
+    sentences
+}
+
+pub fn split_large_text(
+    text: &str,
+    tokenizer: &Tokenizer,
+    max_tokens: usize,
+    overlap_tokens: usize,
+) -> Vec<String> {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let mut chunks = Vec::new();
+    let mut start_idx = 0;
+
+    while start_idx < words.len() {
+        let mut end_idx = start_idx;
+        let mut current_chunk = String::new();
+
+        while end_idx < words.len() {
+            let test_text = if current_chunk.is_empty() {
+                words[end_idx].to_string()
+            } else {
+                format!("{} {}", current_chunk, words[end_idx])
+            };
+
+            if count_tokens(tokenizer, &test_text) > max_tokens && !current_chunk.is_empty() {
+                break;
+            }
+
+            current_chunk = test_text;
+            end_idx += 1;
+        }
+
+        if !current_chunk.is_empty() {
+            chunks.push(current_chunk);
+        }
+
+        if end_idx < words.len() {
+            let overlap_text =
+                keep_last_tokens(tokenizer, &chunks[chunks.len() - 1], overlap_tokens);
+            let overlap_words: Vec<&str> = overlap_text.split_whitespace().collect();
+            start_idx = end_idx.saturating_sub(overlap_words.len());
+        } else {
+            break;
+        }
+    }
+
+    chunks
+}
+
+pub struct HeuristicChunker<'a> {
+    tokenizer: &'a Tokenizer,
+    config: ChunkingConfig,
+}
+
+#[async_trait(?Send)]
+impl Chunker for HeuristicChunker<'_> {
+    async fn chunk(&self, text: &str) -> Vec<Chunk> {
+        let tokenizer = self.tokenizer;
+        let ChunkingConfig {
+            max_tokens,
+            overlap_tokens,
+            min_tokens,
+            ..
+        } = self.config;
+
+        let sections = split_sections(text);
+
+        let mut passages = Vec::new();
+
+        for section in sections {
+            let section_token_count = count_tokens(tokenizer, &section);
+
+            if section_token_count > max_tokens * 3 {
+                let chunks = split_large_text(&section, tokenizer, max_tokens, overlap_tokens);
+                for chunk in chunks {
+                    if count_tokens(tokenizer, &chunk) >= min_tokens {
+                        passages.push(chunk);
+                    }
+                }
+                continue;
+            }
+
+            let paragraphs: Vec<&str> = section
+                .split("\n\n")
+                .filter(|p| !p.trim().is_empty())
+                .collect();
+
+            for paragraph in paragraphs {
+                let paragraph_tokens = count_tokens(tokenizer, paragraph);
+
+                if paragraph_tokens > max_tokens {
+                    let chunks = split_large_text(paragraph, tokenizer, max_tokens, overlap_tokens);
+                    for chunk in chunks {
+                        if count_tokens(tokenizer, &chunk) >= min_tokens {
+                            passages.push(chunk);
+                        }
+                    }
+                    continue;
+                }
+
+                let sentences = split_sentences(paragraph);
+
+                let mut buffer = String::new();
+                let mut token_count = 0;
+
+                for sentence in sentences {
+                    let sentence_tokens = count_tokens(tokenizer, &sentence);
+
+                    if sentence_tokens > max_tokens {
+                        if !buffer.is_empty() && token_count >= min_tokens {
+                            passages.push(buffer.clone());
+                            buffer.clear();
+                            token_count = 0;
+                        }
+
+                        let sentence_chunks =
+                            split_large_text(&sentence, tokenizer, max_tokens, overlap_tokens);
+                        for chunk in sentence_chunks {
+                            if count_tokens(tokenizer, &chunk) >= min_tokens {
+                                passages.push(chunk);
+                            }
+                        }
+                        continue;
+                    }
+
+                    if token_count + sentence_tokens > max_tokens {
+                        if token_count >= min_tokens {
+                            passages.push(buffer.clone());
+                        }
+
+                        let overlap_text = keep_last_tokens(tokenizer, &buffer, overlap_tokens);
+                        buffer = if overlap_text.is_empty() {
+                            sentence.clone()
+                        } else {
+                            format!("{} {}", overlap_text, sentence)
+                        };
+                        token_count = count_tokens(tokenizer, &buffer);
+                    } else {
+                        if !buffer.is_empty() {
+                            buffer.push(' ');
+                        }
+                        buffer.push_str(&sentence);
+                        token_count += sentence_tokens;
+                    }
+                }
+
+                if !buffer.is_empty() && token_count >= min_tokens {
+                    passages.push(buffer.clone());
+                }
+            }
+        }
+
+        if passages.len() < 3 && !text.trim().is_empty() {
+            let aggressive_chunks =
+                split_large_text(text, tokenizer, max_tokens / 2, overlap_tokens / 2);
+            passages.clear();
+            for chunk in aggressive_chunks {
+                if count_tokens(tokenizer, &chunk) >= min_tokens {
+                    passages.push(chunk);
+                }
+            }
+        }
+
+        passages.into_iter().map(Chunk::from).collect()
+    }
+}
+
+pub struct FixedTokenChunker<'a> {
+    tokenizer: &'a Tokenizer,
+    config: ChunkingConfig,
+}
+
+#[async_trait(?Send)]
+impl Chunker for FixedTokenChunker<'_> {
+    async fn chunk(&self, text: &str) -> Vec<Chunk> {
+        let Ok(encoding) = self.tokenizer.encode(text, false) else {
+            return Vec::new();
+        };
+        let offsets = encoding.get_offsets();
+
+        // Leaves room for the special tokens added when embedding.
+        let window = self.config.max_tokens.saturating_sub(2).max(1);
+        let step = window.saturating_sub(self.config.overlap_tokens).max(1);
+
+        let mut chunks = Vec::new();
+        let mut start = 0;
+        while start < offsets.len() {
+            let end = (start + window).min(offsets.len());
+            chunks.push(text[offsets[start].0..offsets[end - 1].1].to_string());
+            if end == offsets.len() {
+                break;
+            }
+            start += step;
+        }
+
+        drop_small(chunks, self.tokenizer, self.config.min_tokens)
+    }
+}
+
+pub struct SentenceWindowChunker<'a> {
+    tokenizer: &'a Tokenizer,
+    config: ChunkingConfig,
+}
+
+#[async_trait(?Send)]
+impl Chunker for SentenceWindowChunker<'_> {
+    async fn chunk(&self, text: &str) -> Vec<Chunk> {
+        let tokenizer = self.tokenizer;
+        let ChunkingConfig {
+            max_tokens,
+            overlap_tokens,
+            ..
+        } = self.config;
+
+        let sentences = sentence_units(text, tokenizer, max_tokens, overlap_tokens);
+
+        let mut chunks = Vec::new();
+        let mut window: Vec<&(String, usize)> = Vec::new();
+        let mut window_tokens = 0;
+
+        for sentence in &sentences {
+            if window_tokens + sentence.1 > max_tokens && !window.is_empty() {
+                chunks.push(join_sentences(&window));
+
+                // Carry over the trailing sentences that fit in the overlap.
+                let mut carried = 0;
+                let mut keep = 0;
+                for s in window.iter().rev() {
+                    if carried + s.1 > overlap_tokens || carried + s.1 + sentence.1 > max_tokens {
+                        break;
+                    }
+                    carried += s.1;
+                    keep += 1;
+                }
+                window.drain(..window.len() - keep);
+                window_tokens = carried;
+            }
+            window.push(sentence);
+            window_tokens += sentence.1;
+        }
+
+        if !window.is_empty() {
+            chunks.push(join_sentences(&window));
+        }
+
+        drop_small(chunks, tokenizer, self.config.min_tokens)
+    }
+}
+
+/// Sentences of each paragraph with their token counts, those over `max_tokens` being split.
+fn sentence_units(
+    text: &str,
+    tokenizer: &Tokenizer,
+    max_tokens: usize,
+    overlap_tokens: usize,
+) -> Vec<(String, usize)> {
+    let mut sentences = Vec::new();
+    for paragraph in text.split("\n\n") {
+        for sentence in split_sentences(paragraph) {
+            let tokens = count_tokens(tokenizer, &sentence);
+            if tokens > max_tokens {
+                for piece in split_large_text(&sentence, tokenizer, max_tokens, overlap_tokens) {
+                    let tokens = count_tokens(tokenizer, &piece);
+                    sentences.push((piece, tokens));
+                }
+            } else {
+                sentences.push((sentence, tokens));
+            }
+        }
+    }
+    sentences
+}
+
+fn join_sentences(window: &[&(String, usize)]) -> String {
+    window
+        .iter()
+        .map(|(s, _)| s.as_str())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub struct MarkdownChunker<'a> {
+    tokenizer: &'a Tokenizer,
+    config: ChunkingConfig,
+}
+
+#[async_trait(?Send)]
+impl Chunker for MarkdownChunker<'_> {
+    async fn chunk(&self, text: &str) -> Vec<Chunk> {
+        markdown::chunk(
+            text,
+            self.tokenizer,
+            self.config.max_tokens,
+            self.config.overlap_tokens,
+        )
+    }
+}
+
+/// Separators tried in order, from the coarsest to the finest.
+const SEPARATORS: [&str; 5] = ["\n\n", "\n", ". ", " ", ""];
+
+pub struct RecursiveCharacterChunker<'a> {
+    tokenizer: &'a Tokenizer,
+    config: ChunkingConfig,
+}
+
+impl RecursiveCharacterChunker<'_> {
+    /// Splits `text` into pieces of at most `max_tokens`, each keeping its trailing separator.
+    fn split(&self, text: &str, separators: &[&str]) -> Vec<String> {
+        if count_tokens(self.tokenizer, text) <= self.config.max_tokens {
+            return vec![text.to_string()];
+        }
+
+        let Some((separator, finer)) = separators.split_first() else {
+            return vec![text.to_string()];
+        };
+
+        let parts: Vec<String> = if separator.is_empty() {
+            text.chars().map(String::from).collect()
+        } else {
+            text.split_inclusive(separator)
+                .map(str::to_string)
+                .collect()
+        };
+        if parts.len() <= 1 {
+            return self.split(text, finer);
+        }
+
+        parts
+            .into_iter()
+            .flat_map(|part| self.split(&part, finer))
+            .collect()
+    }
+}
+
+#[async_trait(?Send)]
+impl Chunker for RecursiveCharacterChunker<'_> {
+    async fn chunk(&self, text: &str) -> Vec<Chunk> {
+        let tokenizer = self.tokenizer;
+        let ChunkingConfig {
+            max_tokens,
+            overlap_tokens,
+            ..
+        } = self.config;
+
+        let pieces: Vec<(String, usize)> = self
+            .split(text, &SEPARATORS)
+            .into_iter()
+            .map(|p| {
+                let tokens = count_tokens(tokenizer, &p);
+                (p, tokens)
+            })
+            .collect();
+
+        let mut chunks = Vec::new();
+        let mut current: Vec<&(String, usize)> = Vec::new();
+        let mut current_tokens = 0;
+
+        for piece in &pieces {
+            if current_tokens + piece.1 > max_tokens && !current.is_empty() {
+                chunks.push(current.iter().map(|(p, _)| p.as_str()).collect::<String>());
+
+                while !current.is_empty()
+                    && (current_tokens > overlap_tokens || current_tokens + piece.1 > max_tokens)
+                {
+                    current_tokens -= current.remove(0).1;
+                }
+            }
+            current.push(piece);
+            current_tokens += piece.1;
+        }
+        if !current.is_empty() {
+            chunks.push(current.iter().map(|(p, _)| p.as_str()).collect::<String>());
+        }
+
+        let chunks = chunks
+            .into_iter()
+            .map(|c| c.trim().to_string())
+            .filter(|c| !c.is_empty())
+            .collect();
+        drop_small(chunks, tokenizer, self.config.min_tokens)
+    }
+}
+
+pub struct SemanticChunker<'a> {
+    state: &'a AppState,
+    config: ChunkingConfig,
+}
+
+impl SemanticChunker<'_> {
+    /// Cosine distance between the window around each sentence and the window around the next.
+    async fn distances(&self, sentences: &[(String, usize)]) -> anyhow::Result<Vec<f32>> {
+        let state = self.state;
+        let mut windows = Vec::with_capacity(sentences.len());
+
+        for i in 0..sentences.len() {
+            let window: Vec<&(String, usize)> = sentences
+                [i.saturating_sub(SEMANTIC_WINDOW)..(i + SEMANTIC_WINDOW + 1).min(sentences.len())]
+                .iter()
+                .collect();
+            let mut text = join_sentences(&window);
+            if count_tokens(&state.tokenizer, &text) > MAX_CHUNK_TOKENS {
+                text = sentences[i].0.clone();
+            }
+            windows.push(text);
+        }
+
+        let windows: Vec<&str> = windows.iter().map(String::as_str).collect();
+        let embeddings = state.embedder.embed_many(&windows).await?;
+
+        // Embeddings are normalized, so their dot product is the cosine similarity.
+        Ok(embeddings
+            .windows(2)
+            .map(|pair| {
+                1.0 - pair[0]
+                    .iter()
+                    .zip(&pair[1])
+                    .map(|(a, b)| a * b)
+                    .sum::<f32>()
+            })
+            .collect())
+    }
+}
+
+/// Linearly interpolated `p`-th percentile of `values`.
+fn percentile(values: &[f32], p: u8) -> f32 {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f32::total_cmp);
+
+    let rank = (sorted.len() - 1) as f32 * f32::from(p) / 100.0;
+    let lower = rank.floor() as usize;
+    let upper = rank.ceil() as usize;
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f32)
+}
+
+#[async_trait(?Send)]
+impl Chunker for SemanticChunker<'_> {
+    async fn chunk(&self, text: &str) -> Vec<Chunk> {
+        let tokenizer = &self.state.tokenizer;
+        let ChunkingConfig {
+            max_tokens,
+            overlap_tokens,
+            breakpoint_percentile,
+            ..
+        } = self.config;
+
+        let sentences = sentence_units(text, tokenizer, max_tokens, overlap_tokens);
+        if sentences.len() < 2 {
+            let chunks = sentences.into_iter().map(|(s, _)| s).collect();
+            return drop_small(chunks, tokenizer, self.config.min_tokens);
+        }
+
+        let distances = match self.distances(&sentences).await {
+            Ok(distances) => distances,
+            Err(e) => {
+                eprintln!(
+                    "Impossible de calculer les embeddings des phrases, découpage par taille seulement: {}",
+                    e
+                );
+                vec![0.0; sentences.len() - 1]
+            }
+        };
+        let threshold = percentile(&distances, breakpoint_percentile);
+
+        let mut chunks = Vec::new();
+        let mut current: Vec<&(String, usize)> = Vec::new();
+        let mut current_tokens = 0;
+
+        for (i, sentence) in sentences.iter().enumerate() {
+            let breakpoint = i > 0 && distances[i - 1] > threshold;
+            if !current.is_empty() && (breakpoint || current_tokens + sentence.1 > max_tokens) {
+                chunks.push(join_sentences(&current));
+                current.clear();
+                current_tokens = 0;
+            }
+            current.push(sentence);
+            current_tokens += sentence.1;
+        }
+
+        if !current.is_empty() {
+            chunks.push(join_sentences(&current));
+        }
+
+        drop_small(chunks, tokenizer, self.config.min_tokens)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use tokenizers::models::wordlevel::WordLevel;
+    use tokenizers::pre_tokenizers::bert::BertPreTokenizer;
+    use tokenizers::processors::bert::BertProcessing;
+
+    /// Tokenizer splitting like BERT's, one token per word or punctuation mark, plus
+    /// `[CLS]` and `[SEP]`.
+    pub(crate) fn tokenizer() -> Tokenizer {
+        let vocab = [
+            ("[UNK]".to_string(), 0),
+            ("[CLS]".into(), 1),
+            ("[SEP]".into(), 2),
+        ];
+        let model = WordLevel::builder()
+            .vocab(vocab.into_iter().collect())
+            .unk_token("[UNK]".into())
+            .build()
+            .unwrap();
+
+        let mut tokenizer = Tokenizer::new(model);
+        tokenizer
+            .with_pre_tokenizer(Some(BertPreTokenizer))
+            .with_post_processor(Some(BertProcessing::new(
+                ("[SEP]".into(), 2),
+                ("[CLS]".into(), 1),
+            )));
+        tokenizer
+    }
+
+    pub(crate) fn words(n: usize) -> String {
+        (0..n)
+            .map(|i| format!("mot{}", i))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    #[test]
+    fn counts_special_tokens() {
+        let tokenizer = tokenizer();
+        assert_eq!(count_tokens(&tokenizer, "le proxy, absent."), 7);
+    }
+
+    #[test]
+    fn budget_leaves_room_for_context() {
+        let tokenizer = tokenizer();
+        assert_eq!(passage_budget(&tokenizer, 300, &[]), 300);
+        assert_eq!(passage_budget(&tokenizer, 300, &[&words(10)]), 300);
+        assert_eq!(
+            passage_budget(&tokenizer, 512, &["src/main.rs", "main"]),
+            506
+        );
+        assert_eq!(
+            passage_budget(&tokenizer, 512, &[&words(1000)]),
+            MAX_CHUNK_TOKENS - MAX_CONTEXT_TOKENS
+        );
+    }
+
+    #[test]
+    fn tail_keeps_last_tokens() {
+        let tokenizer = tokenizer();
+        assert_eq!(
+            tail_tokens(&tokenizer, "Guide > Réseau > Proxy", 3),
+            "Réseau > Proxy"
+        );
+        assert_eq!(tail_tokens(&tokenizer, "Guide", 5), "Guide");
+        assert_eq!(tail_tokens(&tokenizer, "Guide", 0), "");
+    }
+
+    fn config(max_tokens: usize, overlap_tokens: usize, min_tokens: usize) -> ChunkingConfig {
+        ChunkingConfig {
+            strategy: ChunkingStrategy::Auto,
+            max_tokens,
+            overlap_tokens,
+            min_tokens,
+            breakpoint_percentile: DEFAULT_BREAKPOINT_PERCENTILE,
+        }
+    }
+
+    fn texts(chunks: Vec<Chunk>) -> Vec<String> {
+        chunks.into_iter().map(|c| c.text).collect()
+    }
+
+    /// Sentences of five words and a full stop, numbered from `from`.
+    fn sentences(from: usize, n: usize) -> String {
+        (from..from + n)
+            .map(|i| format!("Phrase{} parle du serveur distant.", i))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    #[actix_web::test]
+    async fn fixed_tokens_slide_with_overlap() {
+        let tokenizer = tokenizer();
+        let chunker = FixedTokenChunker {
+            tokenizer: &tokenizer,
+            config: config(10, 3, 0),
+        };
+
+        let chunks = texts(chunker.chunk(&words(20)).await);
+        assert_eq!(
+            chunks,
+            [
+                "mot0 mot1 mot2 mot3 mot4 mot5 mot6 mot7",
+                "mot5 mot6 mot7 mot8 mot9 mot10 mot11 mot12",
+                "mot10 mot11 mot12 mot13 mot14 mot15 mot16 mot17",
+                "mot15 mot16 mot17 mot18 mot19",
+            ]
+        );
+    }
+
+    #[actix_web::test]
+    async fn sentence_windows_carry_trailing_sentences() {
+        let tokenizer = tokenizer();
+        let chunker = SentenceWindowChunker {
+            tokenizer: &tokenizer,
+            config: config(20, 8, 0),
+        };
+
+        let chunks = texts(chunker.chunk(&sentences(0, 4)).await);
+        assert_eq!(chunks, [sentences(0, 2), sentences(1, 2), sentences(2, 2)]);
+    }
+
+    #[actix_web::test]
+    async fn recursive_character_prefers_coarse_separators() {
+        let tokenizer = tokenizer();
+        let chunker = RecursiveCharacterChunker {
+            tokenizer: &tokenizer,
+            config: config(20, 0, 0),
+        };
+
+        let text = format!("{}\n\n{}\n\n{}", words(8), words(8), words(30));
+        let chunks = texts(chunker.chunk(&text).await);
+        assert_eq!(chunks[0], format!("{}\n\n{}", words(8), words(8)));
+        assert!(chunks.len() > 2);
+        assert!(chunks.iter().all(|c| count_tokens(&tokenizer, c) <= 20));
+        assert_eq!(
+            chunks.join(" ").split_whitespace().count(),
+            text.split_whitespace().count()
+        );
+    }
+
+    #[actix_web::test]
+    async fn heuristic_keeps_chunks_within_max_tokens() {
+        let tokenizer = tokenizer();
+        let chunker = HeuristicChunker {
+            tokenizer: &tokenizer,
+            config: config(30, 0, 5),
+        };
+
+        let text = format!(
+            "# Installation\n\n{}\n\n# Proxy\n\n{}",
+            sentences(0, 6),
+            sentences(6, 6)
+        );
+        let chunks = texts(chunker.chunk(&text).await);
+        assert!(chunks.len() >= 3);
+        assert!(chunks.iter().all(|c| count_tokens(&tokenizer, c) <= 30));
+        assert!(chunks.iter().any(|c| c.contains("Phrase11")));
+    }
+
+    #[test]
+    fn drop_small_keeps_a_lone_chunk() {
+        let tokenizer = tokenizer();
+        let kept = texts(drop_small(vec!["court".into()], &tokenizer, 10));
+        assert_eq!(kept, ["court"]);
+
+        let kept = texts(drop_small(vec![words(10), "court".into()], &tokenizer, 10));
+        assert_eq!(kept, [words(10)]);
+    }
+
+    #[test]
+    fn percentile_interpolates_between_values() {
+        let values = [0.4, 0.1, 0.3, 0.2];
+        assert!((percentile(&values, 50) - 0.25).abs() < 1e-6);
+        assert!((percentile(&values, 1) - 0.103).abs() < 1e-6);
+        assert!((percentile(&values, 99) - 0.397).abs() < 1e-6);
+        assert_eq!(percentile(&[0.7], 95), 0.7);
+    }
+}
@@ -0,0 +1,87 @@
+use crate::crawl::{DEFAULT_DEPTH, DEFAULT_MAX_PAGES};
+use crate::types::{ChunkingOptions, Metadata};
+use clap::{Args, Parser, Subcommand};
+use serde::de::DeserializeOwned;
+use std::path::PathBuf;
+
+#[derive(Parser)]
+#[command(version, about = "Question answering over ingested documents")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Start the HTTP API (the default).
+    Serve,
+    /// Ingest every supported file of a directory or of a .zip / .tar.gz archive.
+    Ingest(Box<IngestArgs>),
+    /// Fetch a web page or sitemap and the same-domain pages it links to, and ingest them.
+    Crawl(Box<CrawlArgs>),
+    /// Measure embedding throughput one passage at a time and with batching.
+    Bench(BenchArgs),
+}
+
+#[derive(Args)]
+pub struct IngestArgs {
+    /// Directory, file, .zip, .tar.gz or .tgz archive.
+    pub path: PathBuf,
+
+    /// Only ingest files matching this glob; repeatable. Globs without `/` match file names.
+    #[arg(long, value_name = "GLOB")]
+    pub include: Vec<String>,
+
+    /// Skip files matching this glob; repeatable.
+    #[arg(long, value_name = "GLOB")]
+    pub exclude: Vec<String>,
+
+    /// Metadata added to every document, as JSON like the `metadata` of `/ingest`.
+    #[arg(long, value_name = "JSON", value_parser = parse_json::<Metadata>)]
+    pub metadata: Option<Metadata>,
+
+    /// Chunking options, as JSON like the `chunking` of `/ingest`.
+    #[arg(long, value_name = "JSON", value_parser = parse_json::<ChunkingOptions>)]
+    pub chunking: Option<ChunkingOptions>,
+}
+
+#[derive(Args)]
+pub struct CrawlArgs {
+    /// Page or sitemap to start from.
+    pub url: String,
+
+    /// Link hops followed from the start page.
+    #[arg(long, default_value_t = DEFAULT_DEPTH)]
+    pub depth: usize,
+
+    /// Maximum number of pages fetched, sitemaps aside.
+    #[arg(long, default_value_t = DEFAULT_MAX_PAGES)]
+    pub max_pages: usize,
+
+    /// Metadata added to every page, as JSON like the `metadata` of `/ingest`.
+    #[arg(long, value_name = "JSON", value_parser = parse_json::<Metadata>)]
+    pub metadata: Option<Metadata>,
+
+    /// Chunking options, as JSON like the `chunking` of `/ingest`.
+    #[arg(long, value_name = "JSON", value_parser = parse_json::<ChunkingOptions>)]
+    pub chunking: Option<ChunkingOptions>,
+}
+
+#[derive(Args)]
+pub struct BenchArgs {
+    /// Number of synthetic passages embedded.
+    #[arg(long, default_value_t = 256)]
+    pub passages: usize,
+
+    /// Words per passage.
+    #[arg(long, default_value_t = 150)]
+    pub words: usize,
+
+    /// Passages submitted to the batching service at once.
+    #[arg(long, default_value_t = 64)]
+    pub concurrency: usize,
+}
+
+fn parse_json<T: DeserializeOwned>(value: &str) -> Result<T, String> {
+    serde_json::from_str(value).map_err(|e| format!("JSON invalide: {}", e))
+}
@@ -0,0 +1,604 @@
+use crate::chunking::{count_tokens, passage_budget, split_large_text};
+use std::path::Path;
+use tokenizers::Tokenizer;
+use tree_sitter::{Node, Parser};
+
+/// Languages whose source files are split along their syntax tree.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Language {
+    Rust,
+    Python,
+    TypeScript,
+    /// TypeScript with JSX, also used for JavaScript.
+    Tsx,
+    Go,
+}
+
+impl Language {
+    pub fn from_path(path: &str) -> Option<Self> {
+        let extension = Path::new(path).extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "rs" => Some(Language::Rust),
+            "py" | "pyi" => Some(Language::Python),
+            "ts" | "mts" | "cts" => Some(Language::TypeScript),
+            "tsx" | "js" | "jsx" | "mjs" | "cjs" => Some(Language::Tsx),
+            "go" => Some(Language::Go),
+            _ => None,
+        }
+    }
+
+    fn grammar(self) -> tree_sitter::Language {
+        match self {
+            Language::Rust => tree_sitter_rust::LANGUAGE.into(),
+            Language::Python => tree_sitter_python::LANGUAGE.into(),
+            Language::TypeScript => tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into(),
+            Language::Tsx => tree_sitter_typescript::LANGUAGE_TSX.into(),
+            Language::Go => tree_sitter_go::LANGUAGE.into(),
+        }
+    }
+
+    /// Node kinds that make a passage of their own, named after the symbol they define.
+    fn items(self) -> &'static [&'static str] {
+        match self {
+            Language::Rust => &[
+                "function_item",
+                "function_signature_item",
+                "impl_item",
+                "struct_item",
+                "enum_item",
+                "union_item",
+                "trait_item",
+                "mod_item",
+                "macro_definition",
+            ],
+            Language::Python => &["function_definition", "class_definition"],
+            Language::TypeScript | Language::Tsx => &[
+                "function_declaration",
+                "generator_function_declaration",
+                "class_declaration",
+                "abstract_class_declaration",
+                "interface_declaration",
+                "enum_declaration",
+                "internal_module",
+                "method_definition",
+                "method_signature",
+                "abstract_method_signature",
+            ],
+            Language::Go => &[
+                "function_declaration",
+                "method_declaration",
+                "type_declaration",
+            ],
+        }
+    }
+
+    /// Node kinds kept with the item right below them, such as doc comments.
+    fn preambles(self) -> &'static [&'static str] {
+        match self {
+            Language::Rust => &["line_comment", "block_comment", "attribute_item"],
+            Language::Python | Language::TypeScript | Language::Tsx | Language::Go => &["comment"],
+        }
+    }
+
+    fn scope_separator(self) -> &'static str {
+        match self {
+            Language::Rust => "::",
+            Language::Python | Language::TypeScript | Language::Tsx | Language::Go => ".",
+        }
+    }
+}
+
+/// Node kinds whose `body` holds items split out when the whole is too large.
+const CONTAINERS: [&str; 7] = [
+    "impl_item",
+    "trait_item",
+    "mod_item",
+    "class_definition",
+    "class_declaration",
+    "abstract_class_declaration",
+    "internal_module",
+];
+
+/// Piece of a source file, with the symbol it belongs to and its lines, numbered from 1.
+pub struct CodeChunk {
+    pub text: String,
+    pub symbol: Option<String>,
+    pub start_line: u32,
+    pub end_line: u32,
+}
+
+/// Byte range of the source headed for one or more chunks.
+struct Segment<'t> {
+    start: usize,
+    end: usize,
+    symbol: Option<String>,
+    /// Item node the segment covers; `None` for the code between items.
+    item: Option<Node<'t>>,
+}
+
+struct Splitter<'a> {
+    language: Language,
+    source: &'a str,
+    tokenizer: &'a Tokenizer,
+    max_tokens: usize,
+    path: Option<&'a str>,
+    line_starts: Vec<usize>,
+    chunks: Vec<CodeChunk>,
+}
+
+/// The declaration wrapped by an `export` or a Python decorator, else the node itself.
+fn declaration(node: Node<'_>) -> Node<'_> {
+    let field = match node.kind() {
+        "export_statement" => "declaration",
+        "decorated_definition" => "definition",
+        _ => return node,
+    };
+    node.child_by_field_name(field).unwrap_or(node)
+}
+
+fn named_children(node: Node<'_>) -> Vec<Node<'_>> {
+    let mut cursor = node.walk();
+    node.named_children(&mut cursor).collect()
+}
+
+impl<'a> Splitter<'a> {
+    fn text(&self, node: Node<'_>) -> Option<&'a str> {
+        node.utf8_text(self.source.as_bytes()).ok()
+    }
+
+    fn is_item(&self, node: Node<'_>) -> bool {
+        let node = declaration(node);
+        if node.kind() == "lexical_declaration" {
+            return self.function_declarator(node).is_some();
+        }
+        self.language.items().contains(&node.kind())
+    }
+
+    /// Declarator of `const name = () => ...` and the like.
+    fn function_declarator<'t>(&self, node: Node<'t>) -> Option<Node<'t>> {
+        named_children(node).into_iter().find(|d| {
+            d.kind() == "variable_declarator"
+                && d.child_by_field_name("value").is_some_and(|v| {
+                    matches!(
+                        v.kind(),
+                        "arrow_function" | "function_expression" | "function"
+                    )
+                })
+        })
+    }
+
+    /// Name of the symbol an item defines, e.g. `impl Display for Store` or `Store.Get`.
+    fn symbol(&self, node: Node<'_>) -> Option<String> {
+        let node = declaration(node);
+        let name = |n: Node<'_>| Some(self.text(n.child_by_field_name("name")?)?.to_string());
+
+        match node.kind() {
+            "impl_item" => {
+                let ty = self.text(node.child_by_field_name("type")?)?;
+                match node.child_by_field_name("trait") {
+                    Some(tr) => Some(format!("impl {} for {}", self.text(tr)?, ty)),
+                    None => Some(format!("impl {}", ty)),
+                }
+            }
+            "method_declaration" => {
+                let method = name(node)?;
+                let receiver = node
+                    .child_by_field_name("receiver")
+                    .and_then(|r| self.text(r))
+                    .and_then(|r| r.trim_matches(['(', ')']).split_whitespace().last())
+                    .map(|ty| ty.trim_start_matches('*'));
+                Some(match receiver {
+                    Some(ty) => format!("{}.{}", ty, method),
+                    None => method,
+                })
+            }
+            "type_declaration" => named_children(node)
+                .into_iter()
+                .find(|spec| matches!(spec.kind(), "type_spec" | "type_alias"))
+                .and_then(name),
+            "lexical_declaration" => self.function_declarator(node).and_then(name),
+            _ => name(node),
+        }
+    }
+
+    fn qualify(&self, scope: Option<&str>, symbol: String) -> String {
+        match scope {
+            Some(scope) => format!("{}{}{}", scope, self.language.scope_separator(), symbol),
+            None => symbol,
+        }
+    }
+
+    /// Turns sibling nodes into segments: one per item, with the comments and attributes
+    /// right above it, and one per run of other code.
+    fn segments<'t>(&self, nodes: &[Node<'t>], scope: Option<&str>) -> Vec<Segment<'t>> {
+        let mut segments: Vec<Segment<'t>> = Vec::new();
+        let mut preamble: Option<Segment<'t>> = None;
+        let adjacent =
+            |end: usize, start: usize| self.source[end..start].matches('\n').count() <= 1;
+
+        for &node in nodes {
+            let (start, end) = (node.start_byte(), node.end_byte());
+
+            if self.language.preambles().contains(&node.kind()) {
+                match preamble.as_mut() {
+                    Some(p) if adjacent(p.end, start) => p.end = end,
+                    _ => {
+                        segments.extend(preamble.take());
+                        preamble = Some(Segment {
+                            start,
+                            end,
+                            symbol: scope.map(str::to_string),
+                            item: None,
+                        });
+                    }
+                }
+                continue;
+            }
+
+            let mut segment = Segment {
+                start,
+                end,
+                symbol: scope.map(str::to_string),
+                item: None,
+            };
+            if let Some(p) = preamble.take() {
+                if adjacent(p.end, start) {
+                    segment.start = p.start;
+                } else {
+                    segments.push(p);
+                }
+            }
+            if self.is_item(node) {
+                segment.item = Some(node);
+                segment.symbol = self.symbol(node).map(|s| self.qualify(scope, s));
+            }
+            segments.push(segment);
+        }
+        segments.extend(preamble);
+
+        // Code between items is grouped rather than cut statement by statement.
+        let mut merged: Vec<Segment<'t>> = Vec::new();
+        for segment in segments {
+            match merged.last_mut() {
+                Some(last) if last.item.is_none() && segment.item.is_none() => {
+                    last.end = segment.end
+                }
+                _ => merged.push(segment),
+            }
+        }
+        merged
+    }
+
+    /// Size of the chunks of `symbol`, which is embedded with them along with the path.
+    fn budget(&self, symbol: Option<&str>) -> usize {
+        let context: Vec<&str> = [self.path, symbol].into_iter().flatten().collect();
+        passage_budget(self.tokenizer, self.max_tokens, &context)
+    }
+
+    /// Emits a segment as one chunk if it fits, else splits a container into its items,
+    /// or anything else by lines.
+    fn fit(&mut self, segment: Segment<'_>) {
+        let text = &self.source[segment.start..segment.end];
+        if count_tokens(self.tokenizer, text) <= self.budget(segment.symbol.as_deref()) {
+            return self.emit(segment.start, segment.end, segment.symbol);
+        }
+
+        let body = segment.item.map(declaration).and_then(|item| {
+            if CONTAINERS.contains(&item.kind()) {
+                item.child_by_field_name("body")
+            } else {
+                None
+            }
+        });
+        let members = body.map(named_children).unwrap_or_default();
+        let (Some(item), Some(first), Some(last)) = (segment.item, members.first(), members.last())
+        else {
+            return self.split_lines(segment.start, segment.end, segment.symbol);
+        };
+
+        // Members of an impl are named after its type, those of a class after the class.
+        let scope = match declaration(item).kind() {
+            "impl_item" => item
+                .child_by_field_name("type")
+                .and_then(|ty| self.text(ty))
+                .map(str::to_string),
+            _ => segment.symbol.clone(),
+        };
+        let mut inner = self.segments(&members, scope.as_deref());
+
+        // The item's header, up to its first member, leads the members.
+        let header = Segment {
+            start: segment.start,
+            end: first.start_byte(),
+            symbol: segment.symbol,
+            item: None,
+        };
+        match inner.first_mut() {
+            Some(next) if next.item.is_none() => {
+                next.start = header.start;
+                next.symbol = header.symbol;
+            }
+            _ => inner.insert(0, header),
+        }
+
+        let trailer = &self.source[last.end_byte()..segment.end];
+        let closing_only = trailer
+            .chars()
+            .all(|c| c.is_whitespace() || "});".contains(c));
+        for segment in inner {
+            self.fit(segment);
+        }
+        if !closing_only {
+            self.split_lines(last.end_byte(), segment.end, scope);
+        }
+    }
+
+    /// Cuts a byte range on line boundaries into chunks that fit the symbol's budget.
+    fn split_lines(&mut self, start: usize, end: usize, symbol: Option<String>) {
+        let tokenizer = self.tokenizer;
+        let max_tokens = self.budget(symbol.as_deref());
+        // Special tokens added when the chunk is embedded.
+        let budget = max_tokens.saturating_sub(2).max(1);
+
+        let mut piece_start = start;
+        let mut piece_tokens = 0;
+        let mut offset = start;
+        for line in self.source[start..end].split_inclusive('\n') {
+            let tokens = tokenizer
+                .encode(line, false)
+                .map(|e| e.len())
+                .unwrap_or_default();
+
+            if tokens > budget {
+                self.emit(piece_start, offset, symbol.clone());
+                let line_number = self.line(offset);
+                for text in split_large_text(line, tokenizer, max_tokens, 0) {
+                    self.chunks.push(CodeChunk {
+                        text,
+                        symbol: symbol.clone(),
+                        start_line: line_number,
+                        end_line: line_number,
+                    });
+                }
+                offset += line.len();
+                piece_start = offset;
+                piece_tokens = 0;
+                continue;
+            }
+
+            if piece_tokens + tokens > budget {
+                self.emit(piece_start, offset, symbol.clone());
+                piece_start = offset;
+                piece_tokens = 0;
+            }
+            piece_tokens += tokens;
+            offset += line.len();
+        }
+        self.emit(piece_start, end, symbol);
+    }
+
+    /// Line number of a byte offset, from 1.
+    fn line(&self, offset: usize) -> u32 {
+        self.line_starts.partition_point(|&s| s <= offset) as u32
+    }
+
+    fn emit(&mut self, start: usize, end: usize, symbol: Option<String>) {
+        // Keeps the indentation of the first line, as for the following ones.
+        let line_start = self.source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let start = if self.source[line_start..start].trim().is_empty() {
+            line_start
+        } else {
+            start
+        };
+
+        let text = &self.source[start..end];
+        let trimmed = text.trim_start_matches(['\n', '\r']).trim_end();
+        if trimmed.trim().is_empty() {
+            return;
+        }
+        let start = start + (text.len() - text.trim_start_matches(['\n', '\r']).len());
+        self.chunks.push(CodeChunk {
+            text: trimmed.to_string(),
+            symbol,
+            start_line: self.line(start),
+            end_line: self.line(start + trimmed.len() - 1),
+        });
+    }
+}
+
+/// Splits source code on its top-level items (functions, impls, classes, types...) into
+/// chunks of at most `max_tokens`, less the size of the `path` and symbol embedded with them.
+///
+/// Comments and attributes stay with the item below them, the code between items is
+/// grouped, and items too large for one chunk are split into their members, or by lines.
+pub fn chunk(
+    source: &str,
+    language: Language,
+    path: Option<&str>,
+    tokenizer: &Tokenizer,
+    max_tokens: usize,
+) -> Vec<CodeChunk> {
+    let mut splitter = Splitter {
+        language,
+        source,
+        tokenizer,
+        max_tokens,
+        path,
+        line_starts: std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+            .collect(),
+        chunks: Vec::new(),
+    };
+
+    let mut parser = Parser::new();
+    let tree = match parser.set_language(&language.grammar()) {
+        Ok(()) => parser.parse(source, None),
+        Err(e) => {
+            tracing::warn!("Grammaire {:?} incompatible: {}", language, e);
+            None
+        }
+    };
+
+    match tree {
+        Some(tree) => {
+            let nodes = named_children(tree.root_node());
+            for segment in splitter.segments(&nodes, None) {
+                splitter.fit(segment);
+            }
+        }
+        None => splitter.split_lines(0, source.len(), None),
+    }
+
+    splitter.chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunking::tests::{tokenizer, words};
+    use crate::chunking::MAX_CHUNK_TOKENS;
+
+    fn chunks(source: &str, language: Language, max_tokens: usize) -> Vec<CodeChunk> {
+        chunk(source, language, None, &tokenizer(), max_tokens)
+    }
+
+    fn symbols(chunks: &[CodeChunk]) -> Vec<Option<&str>> {
+        chunks.iter().map(|c| c.symbol.as_deref()).collect()
+    }
+
+    #[test]
+    fn language_from_extension() {
+        assert_eq!(Language::from_path("src/main.RS"), Some(Language::Rust));
+        assert_eq!(Language::from_path("app.jsx"), Some(Language::Tsx));
+        assert_eq!(Language::from_path("stubs.pyi"), Some(Language::Python));
+        assert_eq!(Language::from_path("README.md"), None);
+        assert_eq!(Language::from_path("Makefile"), None);
+    }
+
+    #[test]
+    fn rust_items_keep_their_comments_and_lines() {
+        let source = "use std::fmt;\n\n/// Store.\n#[derive(Debug)]\nstruct Store;\n\n\
+                      impl fmt::Display for Store {\n    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {\n        Ok(())\n    }\n}\n\n\
+                      fn helper() {}\n";
+
+        let chunks = chunks(source, Language::Rust, 200);
+
+        let found: Vec<(&str, Option<&str>, u32, u32)> = chunks
+            .iter()
+            .map(|c| {
+                (
+                    c.text.as_str(),
+                    c.symbol.as_deref(),
+                    c.start_line,
+                    c.end_line,
+                )
+            })
+            .collect();
+        assert_eq!(found[0], ("use std::fmt;", None, 1, 1));
+        assert_eq!(
+            found[1],
+            (
+                "/// Store.\n#[derive(Debug)]\nstruct Store;",
+                Some("Store"),
+                3,
+                5
+            )
+        );
+        assert_eq!(found[2].1, Some("impl fmt::Display for Store"));
+        assert_eq!((found[2].2, found[2].3), (7, 11));
+        assert_eq!(found[3], ("fn helper() {}", Some("helper"), 13, 13));
+        assert_eq!(found.len(), 4);
+    }
+
+    #[test]
+    fn large_impl_is_split_into_methods() {
+        let sum = (0..20)
+            .map(|j| j.to_string())
+            .collect::<Vec<_>>()
+            .join(" + ");
+        let methods: String = (0..3)
+            .map(|i| {
+                format!(
+                    "    fn m{}(&self) -> u32 {{\n        {}\n    }}\n\n",
+                    i, sum
+                )
+            })
+            .collect();
+        let source = format!("impl Store {{\n{}}}\n", methods);
+
+        let chunks = chunks(&source, Language::Rust, 100);
+
+        assert_eq!(
+            symbols(&chunks),
+            [
+                Some("impl Store"),
+                Some("Store::m0"),
+                Some("Store::m1"),
+                Some("Store::m2")
+            ]
+        );
+        assert!(chunks[1].text.starts_with("    fn m0(&self) -> u32 {"));
+        assert_eq!((chunks[3].start_line, chunks[3].end_line), (10, 12));
+    }
+
+    #[test]
+    fn other_languages_name_their_symbols() {
+        let go = "package store\n\nfunc (s *Store) Get(key string) string {\n\treturn key\n}\n";
+        assert_eq!(
+            symbols(&chunks(go, Language::Go, 200)),
+            [None, Some("Store.Get")]
+        );
+
+        let ts = "export const greet = (name: string) => `Bonjour ${name}`;\n";
+        assert_eq!(symbols(&chunks(ts, Language::Tsx, 200)), [Some("greet")]);
+
+        let python = "@cached\ndef load(path):\n    return open(path).read()\n";
+        assert_eq!(
+            symbols(&chunks(python, Language::Python, 200)),
+            [Some("load")]
+        );
+    }
+
+    #[test]
+    fn long_lines_are_split() {
+        let source = format!("fn main() {{\n    let s = \"{}\";\n}}\n", words(300));
+        let tokenizer = tokenizer();
+
+        let chunks = chunk(&source, Language::Rust, None, &tokenizer, 100);
+
+        assert!(chunks.len() > 2);
+        for chunk in &chunks {
+            assert_eq!(chunk.symbol.as_deref(), Some("main"));
+            assert!(count_tokens(&tokenizer, &chunk.text) <= 100);
+        }
+    }
+
+    #[test]
+    fn path_and_symbol_are_taken_from_the_chunk_size() {
+        let path = format!("src/{}.rs", words(40).replace(' ', "/"));
+        let lines: String = (0..300)
+            .map(|i| format!("    let x{} = {};\n", i, i))
+            .collect();
+        let source = format!("fn compute() {{\n{}}}\n", lines);
+        let tokenizer = tokenizer();
+
+        let chunks = chunk(
+            &source,
+            Language::Rust,
+            Some(&path),
+            &tokenizer,
+            MAX_CHUNK_TOKENS,
+        );
+
+        assert!(chunks.len() > 1);
+        for chunk in &chunks {
+            let input = format!(
+                "{}\n{}\n{}",
+                path,
+                chunk.symbol.as_deref().unwrap(),
+                chunk.text
+            );
+            assert!(count_tokens(&tokenizer, &input) <= MAX_CHUNK_TOKENS);
+        }
+    }
+}
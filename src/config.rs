@@ -1,20 +1,208 @@
+use crate::chunking::{ChunkingConfig, DEFAULT_BREAKPOINT_PERCENTILE};
+use crate::hnsw::HnswParams;
+use crate::types::NearDuplicatePolicy;
+use anyhow::{anyhow, bail, Context, Result};
 use std::env;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StoreBackend {
+    Mongo,
+    Memory,
+}
+
+/// Server-side vector search used by the MongoDB backend.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VectorSearchMode {
+    /// Try Cosmos DB, then Atlas, and fall back to scanning on the client.
+    Auto,
+    Cosmos,
+    Atlas,
+    Off,
+}
+
+#[derive(Clone)]
+pub struct HnswConfig {
+    pub params: HnswParams,
+    pub path: String,
+}
+
+#[derive(Clone)]
+pub struct RerankConfig {
+    pub model: String,
+    pub candidates: usize,
+}
+
+#[derive(Clone, Copy)]
+pub struct NearDuplicateConfig {
+    /// Jaccard similarity of word shingles from which a passage counts as a near-duplicate.
+    pub threshold: f32,
+    pub policy: NearDuplicatePolicy,
+}
+
+/// Batching of embedding requests, see `EmbeddingService`.
+#[derive(Clone, Copy)]
+pub struct EmbeddingConfig {
+    pub max_batch_size: usize,
+    pub max_wait: Duration,
+    pub workers: usize,
+}
 
 #[derive(Clone)]
 pub struct Config {
     pub database_name: String,
     pub collection_name: String,
     pub llm_uri: String,
-    pub cosmos_uri: String,
+    pub cosmos_uri: Option<String>,
+    pub store_backend: StoreBackend,
+    pub memory_store_path: Option<String>,
+    pub hnsw: Option<HnswConfig>,
+    pub lexical_index: bool,
+    pub vector_search: VectorSearchMode,
+    pub embedding_dimensions: usize,
+    pub embedding: EmbeddingConfig,
+    pub rerank: Option<RerankConfig>,
+    pub near_duplicates: Option<NearDuplicateConfig>,
+    /// Defaults for requests that don't specify their own chunking.
+    pub chunking: ChunkingConfig,
+    /// Directories under which directory sources may be registered, canonicalized;
+    /// directory sources are refused when there are none.
+    pub source_directories: Vec<PathBuf>,
+    /// Whether crawls may fetch loopback, private and link-local addresses.
+    pub crawl_private_addresses: bool,
+}
+
+fn var(name: &str) -> Result<String> {
+    env::var(name).with_context(|| format!("variable {} manquante", name))
+}
+
+fn parse_var<T>(name: &str, default: T) -> Result<T>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    match env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map_err(|e| anyhow!("variable {} invalide: {}", name, e)),
+        Err(_) => Ok(default),
+    }
 }
 
 impl Config {
-    pub fn from_env() -> Result<Self, env::VarError> {
+    pub fn from_env() -> Result<Self> {
+        let store_backend = match env::var("VECTOR_STORE").as_deref() {
+            Ok("mongo") | Err(_) => StoreBackend::Mongo,
+            Ok("memory") => StoreBackend::Memory,
+            Ok(other) => bail!("VECTOR_STORE inconnu: {} (mongo ou memory)", other),
+        };
+
+        let cosmos_uri = match store_backend {
+            StoreBackend::Mongo => Some(var("COSMOS_URI")?),
+            StoreBackend::Memory => env::var("COSMOS_URI").ok(),
+        };
+
+        let vector_search = match env::var("MONGO_VECTOR_SEARCH").as_deref() {
+            Ok("auto") | Err(_) => VectorSearchMode::Auto,
+            Ok("cosmos") => VectorSearchMode::Cosmos,
+            Ok("atlas") => VectorSearchMode::Atlas,
+            Ok("off") => VectorSearchMode::Off,
+            Ok(other) => bail!(
+                "MONGO_VECTOR_SEARCH inconnu: {} (auto, cosmos, atlas ou off)",
+                other
+            ),
+        };
+
+        let hnsw = if parse_var("HNSW_ENABLED", true)? {
+            Some(HnswConfig {
+                params: HnswParams {
+                    m: parse_var("HNSW_M", 16)?,
+                    ef_construction: parse_var("HNSW_EF_CONSTRUCTION", 200)?,
+                    ef_search: parse_var("HNSW_EF_SEARCH", 64)?,
+                },
+                path: env::var("HNSW_PATH").unwrap_or_else(|_| "index/hnsw.bin".into()),
+            })
+        } else {
+            None
+        };
+
+        let rerank = if parse_var("RERANK_ENABLED", false)? {
+            Some(RerankConfig {
+                model: env::var("RERANK_MODEL")
+                    .unwrap_or_else(|_| "cross-encoder/ms-marco-MiniLM-L-6-v2".into()),
+                candidates: parse_var("RERANK_CANDIDATES", 30)?,
+            })
+        } else {
+            None
+        };
+
+        let near_duplicates = if parse_var("NEAR_DUPLICATE_ENABLED", true)? {
+            let threshold = parse_var("NEAR_DUPLICATE_THRESHOLD", 0.8)?;
+            // Below that, the banded index misses too many pairs to be relied on.
+            if !(0.5..=1.0).contains(&threshold) {
+                bail!("NEAR_DUPLICATE_THRESHOLD doit être compris entre 0.5 et 1");
+            }
+            Some(NearDuplicateConfig {
+                threshold,
+                policy: parse_var("NEAR_DUPLICATE_POLICY", NearDuplicatePolicy::Skip)?,
+            })
+        } else {
+            None
+        };
+
+        let embedding = EmbeddingConfig {
+            max_batch_size: parse_var("EMBEDDING_BATCH_SIZE", 32)?,
+            max_wait: Duration::from_millis(parse_var("EMBEDDING_BATCH_WAIT_MS", 5)?),
+            workers: parse_var("EMBEDDING_WORKERS", 1)?,
+        };
+        if embedding.max_batch_size == 0 || embedding.workers == 0 {
+            bail!("EMBEDDING_BATCH_SIZE et EMBEDDING_WORKERS doivent valoir au moins 1");
+        }
+
+        let chunking = ChunkingConfig {
+            strategy: parse_var("CHUNKING_STRATEGY", Default::default())?,
+            max_tokens: parse_var("CHUNK_MAX_TOKENS", 200)?,
+            overlap_tokens: parse_var("CHUNK_OVERLAP_TOKENS", 30)?,
+            min_tokens: parse_var("CHUNK_MIN_TOKENS", 20)?,
+            breakpoint_percentile: parse_var(
+                "CHUNK_BREAKPOINT_PERCENTILE",
+                DEFAULT_BREAKPOINT_PERCENTILE,
+            )?,
+        };
+        chunking
+            .validate()
+            .map_err(|e| anyhow!("configuration de découpage invalide: {}", e))?;
+
+        let source_directories = env::var_os("SOURCE_DIRECTORIES")
+            .map(|paths| env::split_paths(&paths).collect::<Vec<_>>())
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|path| !path.as_os_str().is_empty())
+            .map(|path| {
+                path.canonicalize()
+                    .with_context(|| format!("SOURCE_DIRECTORIES: {} introuvable", path.display()))
+            })
+            .collect::<Result<_>>()?;
+
         Ok(Self {
-            database_name: env::var("DATABASE")?,
-            collection_name: env::var("COLLECTION")?,
-            llm_uri: env::var("LLM_URI")?,
-            cosmos_uri: env::var("COSMOS_URI")?,
+            database_name: var("DATABASE")?,
+            collection_name: var("COLLECTION")?,
+            llm_uri: var("LLM_URI")?,
+            cosmos_uri,
+            store_backend,
+            memory_store_path: env::var("MEMORY_STORE_PATH").ok(),
+            hnsw,
+            lexical_index: parse_var("LEXICAL_INDEX_ENABLED", true)?,
+            vector_search,
+            embedding_dimensions: parse_var("EMBEDDING_DIMENSIONS", 384)?,
+            embedding,
+            rerank,
+            near_duplicates,
+            chunking,
+            source_directories,
+            crawl_private_addresses: parse_var("CRAWL_ALLOW_PRIVATE_ADDRESSES", false)?,
         })
     }
 }
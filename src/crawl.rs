@@ -0,0 +1,1140 @@
+use crate::api::MAX_UPLOAD_BYTES;
+use crate::cli::CrawlArgs;
+use crate::extract::{extract, html_page_to_text, FileKind};
+use crate::ingestion::{validate_text, DocumentSink, NewDocuments};
+use crate::types::{CrawlFailure, CrawlResponse, CrawledPage, Metadata, SkipReason, SkippedPage};
+use crate::AppState;
+use anyhow::{anyhow, Result};
+use indicatif::ProgressBar;
+use quick_xml::escape::resolve_predefined_entity;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::redirect::Policy;
+use reqwest::{Client, Response, StatusCode, Url};
+use scraper::{Html, Selector};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+
+pub const DEFAULT_DEPTH: usize = 2;
+pub const DEFAULT_MAX_PAGES: usize = 50;
+
+const MAX_DEPTH: usize = 10;
+const MAX_PAGES: usize = 1000;
+
+/// Product token looked up in robots.txt.
+const ROBOTS_AGENT: &str = "rust-rag-api";
+const USER_AGENT: &str = concat!("rust-rag-api/", env!("CARGO_PKG_VERSION"));
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Redirects followed from one URL.
+const MAX_REDIRECTS: usize = 10;
+
+/// Size up to which robots.txt is read.
+const MAX_ROBOTS_BYTES: usize = 512 * 1024;
+
+/// Upper bound on the `Crawl-delay` honoured between two fetches of a site.
+const MAX_CRAWL_DELAY: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy, Debug)]
+pub struct CrawlOptions {
+    pub depth: usize,
+    pub max_pages: usize,
+}
+
+impl CrawlOptions {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.depth > MAX_DEPTH {
+            return Err(format!("depth doit être au plus {}", MAX_DEPTH));
+        }
+        if !(1..=MAX_PAGES).contains(&self.max_pages) {
+            return Err(format!("max_pages doit être entre 1 et {}", MAX_PAGES));
+        }
+        Ok(())
+    }
+}
+
+/// Parses the URL a crawl starts from, which must be absolute http(s).
+pub fn parse_start_url(url: &str) -> Result<Url, String> {
+    let parsed = Url::parse(url).map_err(|e| format!("URL invalide: {}", e))?;
+    if !matches!(parsed.scheme(), "http" | "https") || parsed.host_str().is_none() {
+        return Err(format!("URL invalide: {}", url));
+    }
+    Ok(parsed)
+}
+
+/// Allow and disallow rules of robots.txt applying to this crawler.
+#[derive(Default)]
+struct Robots {
+    /// Path patterns, `true` for `Allow`.
+    rules: Vec<(bool, String)>,
+    delay: Option<Duration>,
+}
+
+impl Robots {
+    fn disallow_all() -> Self {
+        Self {
+            rules: vec![(false, "/".into())],
+            delay: None,
+        }
+    }
+
+    /// Keeps the groups naming this crawler, or else those for `*`.
+    fn parse(text: &str) -> Self {
+        struct Group {
+            agents: Vec<String>,
+            robots: Robots,
+        }
+
+        let mut groups: Vec<Group> = Vec::new();
+        let mut in_rules = true;
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or_default();
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            match key.trim().to_ascii_lowercase().as_str() {
+                "user-agent" => {
+                    // Consecutive user-agent lines share the rules that follow them.
+                    if in_rules {
+                        groups.push(Group {
+                            agents: Vec::new(),
+                            robots: Robots::default(),
+                        });
+                        in_rules = false;
+                    }
+                    if let Some(group) = groups.last_mut() {
+                        group.agents.push(value.to_ascii_lowercase());
+                    }
+                }
+                key @ ("allow" | "disallow") => {
+                    in_rules = true;
+                    if let Some(group) = groups.last_mut()
+                        && !value.is_empty()
+                    {
+                        group.robots.rules.push((key == "allow", value.to_string()));
+                    }
+                }
+                "crawl-delay" => {
+                    in_rules = true;
+                    if let Some(group) = groups.last_mut() {
+                        group.robots.delay = value
+                            .parse::<f64>()
+                            .ok()
+                            .filter(|d| d.is_finite() && *d >= 0.0)
+                            .map(|d| Duration::from_secs_f64(d).min(MAX_CRAWL_DELAY));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let named = groups
+            .iter()
+            .any(|g| g.agents.iter().any(|a| a == ROBOTS_AGENT));
+        let mut robots = Robots::default();
+        for group in groups {
+            let applies = if named {
+                group.agents.iter().any(|a| a == ROBOTS_AGENT)
+            } else {
+                group.agents.iter().any(|a| a == "*")
+            };
+            if applies {
+                robots.rules.extend(group.robots.rules);
+                robots.delay = robots.delay.or(group.robots.delay);
+            }
+        }
+        robots
+    }
+
+    /// The longest matching rule decides; `Allow` wins a tie.
+    fn allows(&self, url: &Url) -> bool {
+        let path = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        };
+        self.rules
+            .iter()
+            .filter(|(_, pattern)| pattern_matches(pattern, &path))
+            .max_by_key(|(allow, pattern)| (pattern.len(), *allow))
+            .is_none_or(|(allow, _)| *allow)
+    }
+}
+
+/// Matches a robots.txt path pattern, where `*` is any sequence and a final `$` anchors the end.
+fn pattern_matches(pattern: &str, path: &str) -> bool {
+    let (pattern, anchored) = match pattern.strip_suffix('$') {
+        Some(pattern) => (pattern, true),
+        None => (pattern, false),
+    };
+
+    let mut parts = pattern.split('*');
+    let Some(mut rest) = path.strip_prefix(parts.next().unwrap_or_default()) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    for (i, part) in parts.iter().enumerate() {
+        if anchored && i + 1 == parts.len() {
+            return rest.ends_with(part);
+        }
+        match rest.find(part) {
+            Some(pos) => rest = &rest[pos + part.len()..],
+            None => return false,
+        }
+    }
+    !anchored || rest.is_empty()
+}
+
+/// Whether `ip` can be reached from the internet, as opposed to loopback, private,
+/// link-local and other special-purpose addresses that may reach internal services.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || a == 0
+                // Carrier-grade NAT, 100.64.0.0/10.
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public(IpAddr::V4(ip));
+            }
+            // NAT64, 64:ff9b::/96, reaches the IPv4 address in its last 32 bits.
+            if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+                let [.., high, low] = segments;
+                return is_public(IpAddr::V4(Ipv4Addr::from(
+                    u32::from(high) << 16 | u32::from(low),
+                )));
+            }
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_multicast()
+                || ip.is_unique_local()
+                || ip.is_unicast_link_local())
+        }
+    }
+}
+
+/// Refuses URLs whose host is a non-public IP address; host names are checked
+/// when `PublicResolver` resolves them.
+fn check_host(url: &Url) -> Result<()> {
+    let host = url.host_str().unwrap_or_default();
+    let Ok(ip) = host.trim_start_matches('[').trim_end_matches(']').parse() else {
+        return Ok(());
+    };
+    if !is_public(ip) {
+        return Err(anyhow!("Adresse non publique refusée: {}", ip));
+    }
+    Ok(())
+}
+
+/// Resolves host names like the system resolver, failing for names with any
+/// non-public address so that no connection is made to them.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let host = name.as_str().to_string();
+            let addrs: Vec<SocketAddr> = actix_web::rt::task::spawn_blocking(move || {
+                (host.as_str(), 0).to_socket_addrs().map(Iterator::collect)
+            })
+            .await??;
+            if let Some(addr) = addrs.iter().find(|addr| !is_public(addr.ip())) {
+                return Err(format!(
+                    "Adresse non publique refusée: {} ({})",
+                    name.as_str(),
+                    addr.ip()
+                )
+                .into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// What a fetched URL turned out to be.
+enum Fetched {
+    Page {
+        kind: FileKind,
+        data: Vec<u8>,
+    },
+    /// URLs listed by a sitemap or a sitemap index.
+    Sitemap(Vec<Url>),
+    Unsupported,
+    /// Answered 404 or 410.
+    Gone,
+    /// Redirected off the domain.
+    OffDomain,
+    /// Redirected to a URL robots.txt disallows.
+    Disallowed,
+}
+
+fn file_kind(content_type: &str, url: &Url) -> Option<FileKind> {
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    match mime.as_str() {
+        "text/html" | "application/xhtml+xml" => Some(FileKind::Html),
+        "text/markdown" => Some(FileKind::Markdown),
+        "application/pdf" => Some(FileKind::Pdf),
+        "application/vnd.openxmlformats-officedocument.wordprocessingml.document" => {
+            Some(FileKind::Docx)
+        }
+        "application/vnd.oasis.opendocument.text" => Some(FileKind::Odt),
+        // Servers label most text, source code included, by its extension or not at all.
+        "text/plain" | "application/octet-stream" | "" => {
+            FileKind::from_name(url.path()).or((mime == "text/plain").then_some(FileKind::Text))
+        }
+        _ => None,
+    }
+}
+
+/// `<loc>` entries of a sitemap or sitemap index, or `None` if `xml` is neither.
+fn sitemap_locations(xml: &str) -> Option<Vec<String>> {
+    let mut reader = Reader::from_str(xml);
+    let mut is_sitemap = false;
+    let mut location: Option<String> = None;
+    let mut locations = Vec::new();
+
+    loop {
+        match reader.read_event().ok()? {
+            Event::Start(e) => match e.local_name().as_ref() {
+                "urlset" | "sitemapindex" => is_sitemap = true,
+                "loc" => location = Some(String::new()),
+                _ => {}
+            },
+            Event::End(e) if e.local_name().as_ref() == "loc" => {
+                if let Some(loc) = location.take() {
+                    locations.push(loc.trim().to_string());
+                }
+            }
+            Event::Text(t) => {
+                if let Some(loc) = location.as_mut() {
+                    loc.push_str(&t.xml10_content());
+                }
+            }
+            Event::GeneralRef(r) => {
+                if let Some(loc) = location.as_mut() {
+                    loc.push_str(resolve_predefined_entity(&r.into_inner()).unwrap_or_default());
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    is_sitemap.then_some(locations)
+}
+
+/// Directives of `<meta name="robots">`.
+struct PageDirectives {
+    index: bool,
+    follow: bool,
+}
+
+fn page_directives(document: &Html) -> PageDirectives {
+    let selector = Selector::parse(r#"meta[name="robots" i]"#).unwrap();
+    let content: Vec<String> = document
+        .select(&selector)
+        .filter_map(|meta| meta.value().attr("content"))
+        .flat_map(|content| content.split(','))
+        .map(|directive| directive.trim().to_ascii_lowercase())
+        .collect();
+    let has = |directive: &str| content.iter().any(|d| d == directive || d == "none");
+    PageDirectives {
+        index: !has("noindex"),
+        follow: !has("nofollow"),
+    }
+}
+
+/// Absolute http(s) targets of the page links, without fragments; `rel="nofollow"` links are left out.
+fn page_links(document: &Html, base: &Url) -> Vec<Url> {
+    let selector = Selector::parse("a[href]").unwrap();
+    document
+        .select(&selector)
+        .filter(|a| {
+            !a.value()
+                .attr("rel")
+                .is_some_and(|rel| rel.split_whitespace().any(|r| r == "nofollow"))
+        })
+        .filter_map(|a| base.join(a.value().attr("href")?.trim()).ok())
+        .filter(|url| matches!(url.scheme(), "http" | "https"))
+        .map(|mut url| {
+            url.set_fragment(None);
+            url
+        })
+        .collect()
+}
+
+/// HTTP side of a crawl, kept to one domain: reads robots.txt, follows redirects
+/// hop by hop, and bounds the size of what it downloads.
+struct Fetcher {
+    client: Client,
+    /// Follows redirects, which robots.txt commonly goes through, e.g. to https.
+    robots_client: Client,
+    domain: String,
+    /// Whether loopback, private and link-local addresses may be fetched.
+    private_addresses: bool,
+    /// robots.txt of each origin met, fetched on first use.
+    robots: HashMap<String, Robots>,
+}
+
+impl Fetcher {
+    fn new(domain: String, private_addresses: bool) -> Result<Self> {
+        let builder = || {
+            let builder = Client::builder()
+                .user_agent(USER_AGENT)
+                .timeout(FETCH_TIMEOUT);
+            if private_addresses {
+                builder
+            } else {
+                builder.dns_resolver(Arc::new(PublicResolver))
+            }
+        };
+        let robots_redirects = Policy::custom(move |attempt| {
+            if attempt.previous().len() > MAX_REDIRECTS {
+                attempt.error("Trop de redirections")
+            } else if !private_addresses && let Err(e) = check_host(attempt.url()) {
+                attempt.error(e)
+            } else {
+                attempt.follow()
+            }
+        });
+        Ok(Self {
+            client: builder().redirect(Policy::none()).build()?,
+            robots_client: builder().redirect(robots_redirects).build()?,
+            domain,
+            private_addresses,
+            robots: HashMap::new(),
+        })
+    }
+
+    fn check_host(&self, url: &Url) -> Result<()> {
+        if self.private_addresses {
+            return Ok(());
+        }
+        check_host(url)
+    }
+
+    fn same_domain(&self, url: &Url) -> bool {
+        url.host_str() == Some(self.domain.as_str())
+    }
+
+    /// robots.txt of the URL's origin. A missing file allows everything; a server
+    /// error disallows everything, as the site may be unavailable.
+    async fn robots(&mut self, url: &Url) -> &Robots {
+        let origin = url.origin().ascii_serialization();
+        if !self.robots.contains_key(&origin) {
+            let response = match self.check_host(url) {
+                Ok(()) => Some(
+                    self.robots_client
+                        .get(format!("{}/robots.txt", origin))
+                        .send()
+                        .await,
+                ),
+                // Fetching the page itself fails the same check.
+                Err(_) => None,
+            };
+            let robots = match response {
+                Some(Ok(response)) if response.status().is_success() => {
+                    let text = read_body(response, MAX_ROBOTS_BYTES)
+                        .await
+                        .map(|data| String::from_utf8_lossy(&data).into_owned())
+                        .unwrap_or_default();
+                    Robots::parse(&text)
+                }
+                Some(Ok(response)) if response.status().is_server_error() => Robots::disallow_all(),
+                _ => Robots::default(),
+            };
+            self.robots.insert(origin.clone(), robots);
+        }
+        &self.robots[&origin]
+    }
+
+    /// Waits for the `Crawl-delay` of the URL's site.
+    async fn pause(&mut self, url: &Url) {
+        if let Some(delay) = self.robots(url).await.delay {
+            actix_web::rt::time::sleep(delay).await;
+        }
+    }
+
+    /// Fetches `url`, which robots.txt allows, following redirects as long as they
+    /// stay on the domain and robots.txt allows their target. Returns the URL the
+    /// content comes from, or the first redirect target that was not followed.
+    async fn fetch(&mut self, url: &Url) -> Result<(Url, Fetched)> {
+        let mut url = url.clone();
+        for _ in 0..=MAX_REDIRECTS {
+            self.check_host(&url)?;
+            let response = self.client.get(url.clone()).send().await?;
+            if !response.status().is_redirection() {
+                let fetched = read(response).await;
+                self.pause(&url).await;
+                return Ok((url, fetched?));
+            }
+            self.pause(&url).await;
+            let target = response
+                .headers()
+                .get(reqwest::header::LOCATION)
+                .and_then(|location| location.to_str().ok())
+                .and_then(|location| url.join(location).ok())
+                .ok_or_else(|| anyhow!("Redirection sans destination ({})", response.status()))?;
+
+            if !self.same_domain(&target) {
+                return Ok((target, Fetched::OffDomain));
+            }
+            if !self.robots(&target).await.allows(&target) {
+                return Ok((target, Fetched::Disallowed));
+            }
+            url = target;
+        }
+        Err(anyhow!("Trop de redirections"))
+    }
+}
+
+/// Reads a response body, giving up as soon as it exceeds `limit` bytes.
+async fn read_body(mut response: Response, limit: usize) -> Result<Vec<u8>> {
+    if response
+        .content_length()
+        .is_some_and(|len| len > limit as u64)
+    {
+        return Err(anyhow!("Page trop volumineuse"));
+    }
+
+    let mut data = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if data.len() + chunk.len() > limit {
+            return Err(anyhow!("Page trop volumineuse"));
+        }
+        data.extend_from_slice(&chunk);
+    }
+    Ok(data)
+}
+
+/// Interprets a response that is not a redirect.
+async fn read(response: Response) -> Result<Fetched> {
+    let status = response.status();
+    if status == StatusCode::NOT_FOUND || status == StatusCode::GONE {
+        return Ok(Fetched::Gone);
+    }
+    if status != StatusCode::OK {
+        return Err(anyhow!("Statut {}", status));
+    }
+
+    let url = response.url().clone();
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let data = read_body(response, MAX_UPLOAD_BYTES).await?;
+
+    let fetched = if content_type.contains("xml") && !content_type.contains("xhtml") {
+        let locations = std::str::from_utf8(&data).ok().and_then(sitemap_locations);
+        match locations {
+            Some(locations) => Fetched::Sitemap(
+                locations
+                    .iter()
+                    .filter_map(|loc| Url::parse(loc).ok())
+                    .collect(),
+            ),
+            None => Fetched::Unsupported,
+        }
+    } else {
+        match file_kind(&content_type, &url) {
+            Some(kind) => Fetched::Page { kind, data },
+            None => Fetched::Unsupported,
+        }
+    };
+    Ok(fetched)
+}
+
+/// Breadth-first crawl of one site: fetches pages and sitemaps, follows the links
+/// staying on the start URL's domain, and hands each page to the sink.
+struct Crawler<'a> {
+    state: &'a AppState,
+    fetcher: Fetcher,
+    options: CrawlOptions,
+    metadata: Option<Metadata>,
+    sink: &'a mut dyn DocumentSink,
+    response: CrawlResponse,
+}
+
+impl Crawler<'_> {
+    fn skip(&mut self, url: &Url, reason: SkipReason) {
+        self.response.skipped.push(SkippedPage {
+            url: url.to_string(),
+            reason,
+        });
+    }
+
+    fn fail(&mut self, url: &Url, error: impl ToString) {
+        self.response.failures.push(CrawlFailure {
+            url: url.to_string(),
+            error: error.to_string(),
+        });
+    }
+
+    async fn run(&mut self, start: Url) {
+        let mut queue = VecDeque::from([(start.clone(), 0)]);
+        let mut seen = HashSet::from([start.to_string()]);
+        let mut fetched = 0;
+
+        while let Some((url, depth)) = queue.pop_front() {
+            if fetched >= self.options.max_pages {
+                break;
+            }
+            if !self.fetcher.robots(&url).await.allows(&url) {
+                self.skip(&url, SkipReason::Disallowed);
+                continue;
+            }
+
+            let (final_url, content) = match self.fetcher.fetch(&url).await {
+                Ok(result) => result,
+                Err(e) => {
+                    fetched += 1;
+                    // The cause, e.g. a refused address, is further down the chain.
+                    self.fail(&url, format!("{:#}", e));
+                    continue;
+                }
+            };
+
+            // Sitemaps don't count as pages; what they list starts at the sitemap's depth.
+            let links = match content {
+                Fetched::Sitemap(locations) => {
+                    for location in locations {
+                        if self.fetcher.same_domain(&location) && seen.insert(location.to_string())
+                        {
+                            queue.push_back((location, depth));
+                        }
+                    }
+                    continue;
+                }
+                Fetched::OffDomain => {
+                    self.skip(&url, SkipReason::OffDomain);
+                    continue;
+                }
+                Fetched::Disallowed => {
+                    self.skip(&url, SkipReason::Disallowed);
+                    continue;
+                }
+                Fetched::Unsupported => {
+                    fetched += 1;
+                    self.skip(&final_url, SkipReason::Unsupported);
+                    continue;
+                }
+                Fetched::Gone => {
+                    fetched += 1;
+                    self.skip(&final_url, SkipReason::Gone);
+                    continue;
+                }
+                Fetched::Page { kind, data } => {
+                    fetched += 1;
+                    self.ingest(&final_url, kind, &data).await
+                }
+            };
+
+            if depth < self.options.depth {
+                for link in links {
+                    if self.fetcher.same_domain(&link) && seen.insert(link.to_string()) {
+                        queue.push_back((link, depth + 1));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Stores one page and returns the links to follow from it.
+    async fn ingest(&mut self, url: &Url, kind: FileKind, data: &[u8]) -> Vec<Url> {
+        let (extracted, links) = if kind == FileKind::Html {
+            let Ok(source) = std::str::from_utf8(data) else {
+                self.fail(url, "Page non UTF-8");
+                return Vec::new();
+            };
+            let document = Html::parse_document(source);
+            let directives = page_directives(&document);
+            let links = if directives.follow {
+                page_links(&document, url)
+            } else {
+                Vec::new()
+            };
+            if !directives.index {
+                self.skip(url, SkipReason::Noindex);
+                return links;
+            }
+            (html_page_to_text(&document), links)
+        } else {
+            match extract(kind, data) {
+                Ok(extracted) => (extracted, Vec::new()),
+                Err(e) => {
+                    self.fail(url, e);
+                    return Vec::new();
+                }
+            }
+        };
+
+        if let Err(e) = validate_text(&extracted.text) {
+            self.fail(url, e);
+            return links;
+        }
+
+        let mut metadata = self.metadata.clone().unwrap_or_default();
+        metadata.url = Some(url.to_string());
+        metadata.title = extracted.title.clone().or(metadata.title);
+        metadata
+            .source
+            .get_or_insert_with(|| self.fetcher.domain.clone());
+        let title = metadata.title.clone();
+
+        match self
+            .sink
+            .store(self.state, url.as_str(), extracted, metadata)
+            .await
+        {
+            Ok(Some(document)) => self.response.pages.push(CrawledPage {
+                url: url.to_string(),
+                title,
+                document_id: document.id.map(|id| id.to_hex()).unwrap_or_default(),
+                count: document.passage_ids.len(),
+            }),
+            Ok(None) => self.skip(url, SkipReason::Unchanged),
+            Err(e) => self.fail(url, e),
+        }
+        links
+    }
+}
+
+/// Crawls from `start` and stores every page reached through `sink`, filling in each
+/// page's `url`, `title`, and `source` (the domain) unless `metadata` sets it.
+pub async fn crawl(
+    state: &AppState,
+    start: Url,
+    options: CrawlOptions,
+    metadata: Option<Metadata>,
+    sink: &mut dyn DocumentSink,
+) -> Result<CrawlResponse> {
+    let domain = start
+        .host_str()
+        .ok_or_else(|| anyhow!("URL sans hôte: {}", start))?
+        .to_string();
+
+    let mut crawler = Crawler {
+        state,
+        fetcher: Fetcher::new(domain, state.config.crawl_private_addresses)?,
+        options,
+        metadata,
+        sink,
+        response: CrawlResponse::default(),
+    };
+    crawler.run(start).await;
+    Ok(crawler.response)
+}
+
+/// Runs the `crawl` subcommand.
+pub async fn run(state: &AppState, args: CrawlArgs) -> Result<CrawlResponse> {
+    let start = parse_start_url(&args.url).map_err(|e| anyhow!(e))?;
+    let options = CrawlOptions {
+        depth: args.depth,
+        max_pages: args.max_pages,
+    };
+    options.validate().map_err(|e| anyhow!(e))?;
+    let chunking = args
+        .chunking
+        .unwrap_or_default()
+        .resolve(state.config.chunking)
+        .map_err(|e| anyhow!("Options de découpage invalides: {}", e))?;
+
+    let spinner = ProgressBar::new_spinner();
+    spinner.set_message(format!("Exploration de {}", start));
+    spinner.enable_steady_tick(Duration::from_millis(100));
+    let response = crawl(
+        state,
+        start,
+        options,
+        args.metadata,
+        &mut NewDocuments(chunking),
+    )
+    .await;
+    spinner.finish_and_clear();
+
+    response
+}
+
+impl fmt::Display for CrawlResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for page in &self.pages {
+            writeln!(f, "{} ({} passages)", page.url, page.count)?;
+        }
+        for failure in &self.failures {
+            writeln!(f, "Échec {}: {}", failure.url, failure.error)?;
+        }
+
+        let skipped = |reason| self.skipped.iter().filter(|s| s.reason == reason).count();
+        writeln!(f, "Pages ingérées: {}", self.pages.len())?;
+        writeln!(f, "Pages inchangées: {}", skipped(SkipReason::Unchanged))?;
+        writeln!(
+            f,
+            "Pages exclues (robots): {}",
+            skipped(SkipReason::Disallowed) + skipped(SkipReason::Noindex)
+        )?;
+        writeln!(
+            f,
+            "Pages ignorées: {}",
+            skipped(SkipReason::Unsupported)
+                + skipped(SkipReason::OffDomain)
+                + skipped(SkipReason::Gone)
+        )?;
+        write!(f, "Échecs: {}", self.failures.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ingestion::NewDocuments;
+    use crate::testing::{response, FixtureServer};
+
+    fn url(path: &str) -> Url {
+        Url::parse("https://example.com")
+            .unwrap()
+            .join(path)
+            .unwrap()
+    }
+
+    #[test]
+    fn pattern_wildcards_and_anchor() {
+        assert!(pattern_matches("/private", "/private/notes"));
+        assert!(!pattern_matches("/private", "/public/private"));
+        assert!(pattern_matches("/*.pdf", "/docs/guide.pdf"));
+        assert!(pattern_matches("/*.pdf", "/docs/guide.pdf?v=2"));
+        assert!(pattern_matches("/*.pdf$", "/docs/guide.pdf"));
+        assert!(!pattern_matches("/*.pdf$", "/docs/guide.pdf?v=2"));
+        assert!(pattern_matches("/a*b*c", "/a-x-b-y-c-z"));
+        assert!(!pattern_matches("/a*b*c", "/a-x-c-y-b"));
+        assert!(pattern_matches("/index$", "/index"));
+        assert!(!pattern_matches("/index$", "/index.html"));
+        assert!(pattern_matches("*", "/anything"));
+    }
+
+    #[test]
+    fn robots_keeps_the_group_naming_the_crawler() {
+        let robots = Robots::parse(
+            "User-agent: *\nDisallow: /\n\n\
+             User-agent: autre-robot\nUser-agent: Rust-RAG-API  # nous\nDisallow: /private\n\
+             Allow: /private/public\nCrawl-delay: 2.5\n",
+        );
+
+        assert!(robots.allows(&url("/docs")));
+        assert!(!robots.allows(&url("/private/notes")));
+        assert!(robots.allows(&url("/private/public/page")));
+        assert_eq!(robots.delay, Some(Duration::from_millis(2500)));
+    }
+
+    #[test]
+    fn robots_falls_back_to_the_wildcard_group() {
+        let robots = Robots::parse(
+            "User-agent: autre-robot\nDisallow: /\n\n\
+             User-agent: *\nDisallow: /*?session=\nDisallow:\nCrawl-delay: 60\n",
+        );
+
+        assert!(robots.allows(&url("/docs")));
+        assert!(!robots.allows(&url("/docs?session=1")));
+        assert_eq!(robots.delay, Some(MAX_CRAWL_DELAY));
+    }
+
+    #[test]
+    fn robots_longest_rule_wins_and_allow_wins_ties() {
+        let robots =
+            Robots::parse("User-agent: *\nDisallow: /docs\nAllow: /docs/\nDisallow: /docs/$\n");
+
+        assert!(!robots.allows(&url("/docs")));
+        assert!(robots.allows(&url("/docs/guide")));
+        assert!(!robots.allows(&url("/docs/")));
+        assert!(Robots::parse("").allows(&url("/")));
+        assert!(!Robots::disallow_all().allows(&url("/")));
+    }
+
+    /// Site whose robots.txt disallows `/private`, with redirects from `/moved`,
+    /// `/leave` and `/hidden`.
+    fn site() -> FixtureServer {
+        FixtureServer::start(|request| {
+            // The host name differs from 127.0.0.1 the fetcher was started on.
+            let elsewhere = format!(
+                "http://localhost:{}/page",
+                request.header("host").unwrap().rsplit(':').next().unwrap()
+            );
+            match request.path.as_str() {
+                "/robots.txt" => response(200, &[], b"User-agent: *\nDisallow: /private\n"),
+                "/page" => response(200, &[("Content-Type", "text/html")], b"<p>Page</p>"),
+                "/moved" => response(301, &[("Location", "/page")], b""),
+                "/leave" => response(302, &[("Location", &elsewhere)], b""),
+                "/hidden" => response(307, &[("Location", "/private/page")], b""),
+                "/loop" => response(302, &[("Location", "/loop")], b""),
+                _ => response(404, &[], b""),
+            }
+        })
+    }
+
+    async fn fetch(server: &FixtureServer, path: &str) -> Result<(Url, Fetched)> {
+        let mut fetcher = Fetcher::new("127.0.0.1".into(), true).unwrap();
+        fetcher
+            .fetch(&Url::parse(&server.url).unwrap().join(path).unwrap())
+            .await
+    }
+
+    fn paths(server: &FixtureServer) -> Vec<String> {
+        server.requests().into_iter().map(|r| r.path).collect()
+    }
+
+    #[actix_web::test]
+    async fn redirects_on_the_domain_are_followed() {
+        let server = site();
+
+        let (url, fetched) = fetch(&server, "/moved").await.unwrap();
+
+        assert_eq!(url.path(), "/page");
+        assert!(
+            matches!(fetched, Fetched::Page { kind: FileKind::Html, ref data } if data == b"<p>Page</p>")
+        );
+    }
+
+    #[actix_web::test]
+    async fn redirects_off_the_domain_are_not_followed() {
+        let server = site();
+
+        let (url, fetched) = fetch(&server, "/leave").await.unwrap();
+
+        assert_eq!(url.host_str(), Some("localhost"));
+        assert!(matches!(fetched, Fetched::OffDomain));
+        assert_eq!(paths(&server), ["/leave", "/robots.txt"]);
+    }
+
+    #[actix_web::test]
+    async fn redirects_to_disallowed_paths_are_not_followed() {
+        let server = site();
+
+        let (url, fetched) = fetch(&server, "/hidden").await.unwrap();
+
+        assert_eq!(url.path(), "/private/page");
+        assert!(matches!(fetched, Fetched::Disallowed));
+        assert!(!paths(&server).contains(&"/private/page".to_string()));
+    }
+
+    #[actix_web::test]
+    async fn redirect_loops_end() {
+        let server = site();
+
+        let error = fetch(&server, "/loop").await.err().unwrap();
+
+        assert_eq!(error.to_string(), "Trop de redirections");
+    }
+
+    #[test]
+    fn only_public_addresses_are_allowed() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fe80::1",
+            "fd00::1",
+            "::ffff:127.0.0.1",
+            "64:ff9b::a00:1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in ["93.184.216.34", "2606:4700::1111", "::ffff:8.8.8.8"] {
+            assert!(is_public(ip.parse().unwrap()), "{}", ip);
+        }
+
+        assert!(check_host(&url("/")).is_ok());
+        assert!(check_host(&Url::parse("http://10.0.0.1/").unwrap()).is_err());
+        assert!(check_host(&Url::parse("http://[::1]:8080/").unwrap()).is_err());
+    }
+
+    #[actix_web::test]
+    async fn private_addresses_are_not_fetched() {
+        let server = site();
+        let by_name = server.url.replace("127.0.0.1", "localhost");
+
+        for (domain, base) in [("127.0.0.1", &server.url), ("localhost", &by_name)] {
+            let mut fetcher = Fetcher::new(domain.into(), false).unwrap();
+            let page = Url::parse(base).unwrap().join("/page").unwrap();
+
+            assert!(fetcher.robots(&page).await.allows(&page));
+            assert!(fetcher.fetch(&page).await.is_err());
+        }
+        assert!(server.requests().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn oversized_bodies_are_cut_off() {
+        // No Content-Length: the size is only known while reading.
+        let server = FixtureServer::start(|_| {
+            let mut response =
+                b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nConnection: close\r\n\r\n"
+                    .to_vec();
+            response.resize(response.len() + 2048, b'a');
+            response
+        });
+
+        let response = reqwest::get(&server.url).await.unwrap();
+        let error = read_body(response, 1024).await.err().unwrap();
+        assert_eq!(error.to_string(), "Page trop volumineuse");
+
+        let response = reqwest::get(&server.url).await.unwrap();
+        assert_eq!(read_body(response, 4096).await.unwrap().len(), 2048);
+    }
+
+    /// Site of linked HTML pages: `/` links to `/a` (then `/a/deep`), `/b`, `/noindex`
+    /// and `/nofollow`, plus a `rel="nofollow"` link and one to another host name.
+    fn linked_site() -> FixtureServer {
+        FixtureServer::start(|request| {
+            let port = request.header("host").unwrap().rsplit(':').next().unwrap();
+            let page = |meta: &str, links: &[&str]| {
+                let links: String = links
+                    .iter()
+                    .map(|link| format!(r#"<a {}>lien</a>"#, link))
+                    .collect();
+                let html = format!(
+                    "<html><head>{}</head><body><main><p>Contenu de {}.</p>{}</main></body></html>",
+                    meta, request.path, links
+                );
+                response(200, &[("Content-Type", "text/html")], html.as_bytes())
+            };
+            let robots = |content| format!(r#"<meta name="robots" content="{}">"#, content);
+            match request.path.as_str() {
+                "/" => page(
+                    "",
+                    &[
+                        r#"href="/a""#,
+                        r#"href="b#section""#,
+                        r#"href="/c" rel="nofollow""#,
+                        &format!(r#"href="http://localhost:{}/offsite""#, port),
+                        r#"href="/noindex""#,
+                        r#"href="/nofollow""#,
+                    ],
+                ),
+                "/a" => page("", &[r#"href="/a/deep""#]),
+                "/noindex" => page(&robots("noindex"), &[r#"href="/from-noindex""#]),
+                "/nofollow" => page(&robots("nofollow"), &[r#"href="/hidden""#]),
+                "/b" | "/c" | "/a/deep" | "/from-noindex" | "/hidden" | "/offsite" => page("", &[]),
+                "/sitemap.xml" => {
+                    let sitemap = format!(
+                        "<urlset><url><loc>http://127.0.0.1:{0}/b</loc></url>\
+                         <url><loc>http://127.0.0.1:{0}/a/deep</loc></url></urlset>",
+                        port
+                    );
+                    response(
+                        200,
+                        &[("Content-Type", "application/xml")],
+                        sitemap.as_bytes(),
+                    )
+                }
+                _ => response(404, &[], b""),
+            }
+        })
+    }
+
+    async fn crawl_site(
+        server: &FixtureServer,
+        path: &str,
+        depth: usize,
+        max_pages: usize,
+    ) -> CrawlResponse {
+        let state = crate::testing::app_state();
+        let start = Url::parse(&server.url).unwrap().join(path).unwrap();
+        let options = CrawlOptions { depth, max_pages };
+        let mut sink = NewDocuments(state.config.chunking);
+        crawl(&state, start, options, None, &mut sink)
+            .await
+            .unwrap()
+    }
+
+    fn stored(response: &CrawlResponse) -> Vec<String> {
+        response
+            .pages
+            .iter()
+            .map(|page| Url::parse(&page.url).unwrap().path().to_string())
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn crawl_follows_links_on_the_domain_up_to_the_depth() {
+        let server = linked_site();
+
+        let response = crawl_site(&server, "/", 1, 50).await;
+
+        assert_eq!(stored(&response), ["/", "/a", "/b", "/nofollow"]);
+        assert!(response.failures.is_empty());
+        let requested = paths(&server);
+        for path in ["/a/deep", "/c", "/offsite"] {
+            assert!(!requested.contains(&path.to_string()), "{}", path);
+        }
+    }
+
+    #[actix_web::test]
+    async fn crawl_honours_noindex_and_nofollow() {
+        let server = linked_site();
+
+        let response = crawl_site(&server, "/", 2, 50).await;
+
+        assert_eq!(
+            stored(&response),
+            ["/", "/a", "/b", "/nofollow", "/a/deep", "/from-noindex"]
+        );
+        let noindex: Vec<&str> = response
+            .skipped
+            .iter()
+            .filter(|s| s.reason == SkipReason::Noindex)
+            .map(|s| s.url.as_str())
+            .collect();
+        assert_eq!(noindex, [format!("{}/noindex", server.url)]);
+        assert!(!paths(&server).contains(&"/hidden".to_string()));
+    }
+
+    #[actix_web::test]
+    async fn crawl_stops_at_max_pages() {
+        let server = linked_site();
+
+        let response = crawl_site(&server, "/", 2, 2).await;
+
+        assert_eq!(stored(&response), ["/", "/a"]);
+        let pages = paths(&server)
+            .into_iter()
+            .filter(|path| path != "/robots.txt")
+            .count();
+        assert_eq!(pages, 2);
+    }
+
+    #[actix_web::test]
+    async fn crawl_expands_sitemaps_at_their_depth() {
+        let server = linked_site();
+
+        let response = crawl_site(&server, "/sitemap.xml", 0, 50).await;
+
+        assert_eq!(stored(&response), ["/b", "/a/deep"]);
+        assert!(!paths(&server).contains(&"/".to_string()));
+    }
+}
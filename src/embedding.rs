@@ -0,0 +1,242 @@
+use crate::cli::BenchArgs;
+use crate::config::EmbeddingConfig;
+use crate::AppState;
+use anyhow::{anyhow, Result};
+use candle_core::{DType, Device, Tensor};
+use candle_transformers::models::bert::BertModel;
+use futures::channel::oneshot;
+use futures::future::join_all;
+use futures::stream::{self, StreamExt};
+use std::sync::mpsc::{channel, sync_channel, Receiver, RecvTimeoutError, Sender, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+use tokenizers::Tokenizer;
+
+/// Sentence embedding model: mean-pooled, L2-normalized BERT outputs.
+pub struct Embedder {
+    model: BertModel,
+    tokenizer: Tokenizer,
+    device: Device,
+}
+
+impl Embedder {
+    pub fn new(model: BertModel, tokenizer: Tokenizer, device: Device) -> Self {
+        Self {
+            model,
+            tokenizer,
+            device,
+        }
+    }
+
+    /// Embeds `texts` in one forward pass, padding them to the longest, and returns
+    /// one vector per text in input order.
+    pub fn embed(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>> {
+        if texts.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let encodings = self
+            .tokenizer
+            .encode_batch(texts.to_vec(), true)
+            .map_err(|e| anyhow!(e))?;
+        let len = encodings.iter().map(|e| e.len()).max().unwrap_or(0);
+
+        // Padding positions are masked out of attention and pooling, so any id will do.
+        let mut ids = Vec::with_capacity(texts.len() * len);
+        let mut mask = Vec::with_capacity(texts.len() * len);
+        for encoding in &encodings {
+            ids.extend_from_slice(encoding.get_ids());
+            ids.resize(ids.len() + len - encoding.len(), 0);
+            mask.extend_from_slice(encoding.get_attention_mask());
+            mask.resize(mask.len() + len - encoding.len(), 0);
+        }
+
+        let ids = Tensor::from_vec(ids, (texts.len(), len), &self.device)?;
+        let mask = Tensor::from_vec(mask, (texts.len(), len), &self.device)?;
+        let type_ids = ids.zeros_like()?;
+        let hidden = self.model.forward(&ids, &type_ids, Some(&mask))?;
+
+        let mask = mask.to_dtype(DType::F32)?.unsqueeze(2)?;
+        let summed = hidden.broadcast_mul(&mask)?.sum(1)?;
+        let mean = summed.broadcast_div(&mask.sum(1)?)?;
+        let norm = mean.sqr()?.sum_keepdim(1)?.sqrt()?;
+
+        Ok(mean.broadcast_div(&norm)?.to_vec2()?)
+    }
+}
+
+struct Request {
+    text: String,
+    reply: oneshot::Sender<Result<Vec<f32>>>,
+}
+
+/// Embeds texts sent by concurrent callers in batches, on dedicated threads that keep
+/// inference off the async executor.
+///
+/// A collector thread groups pending texts into batches of up to `max_batch_size`,
+/// waiting at most `max_wait` after the first one for others to join, and hands each
+/// batch to the first free worker. While every worker is busy, texts pile up and the
+/// next batch leaves full.
+pub struct EmbeddingService {
+    embedder: Arc<Embedder>,
+    sender: Sender<Request>,
+    config: EmbeddingConfig,
+}
+
+impl EmbeddingService {
+    pub fn start(embedder: Embedder, config: EmbeddingConfig) -> Result<Self> {
+        let embedder = Arc::new(embedder);
+        let (sender, requests) = channel();
+        let (batch_sender, batches) = sync_channel(0);
+        let batches = Arc::new(Mutex::new(batches));
+
+        thread::Builder::new()
+            .name("embedding-batcher".into())
+            .spawn(move || collect(requests, batch_sender, config))?;
+        for i in 0..config.workers {
+            let (embedder, batches) = (embedder.clone(), batches.clone());
+            thread::Builder::new()
+                .name(format!("embedding-worker-{}", i))
+                .spawn(move || work(&embedder, &batches))?;
+        }
+
+        Ok(Self {
+            embedder,
+            sender,
+            config,
+        })
+    }
+
+    /// Queues `text` for the next batch and waits for its embedding.
+    pub async fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        let (reply, result) = oneshot::channel();
+        self.sender
+            .send(Request {
+                text: text.to_string(),
+                reply,
+            })
+            .map_err(|_| anyhow!("Service d'embedding arrêté"))?;
+
+        result
+            .await
+            .map_err(|_| anyhow!("Service d'embedding arrêté"))?
+    }
+
+    /// Queues all of `texts` at once and waits for their embeddings, in order.
+    pub async fn embed_many(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>> {
+        join_all(texts.iter().map(|text| self.embed(text)))
+            .await
+            .into_iter()
+            .collect()
+    }
+}
+
+fn collect(
+    requests: Receiver<Request>,
+    batches: SyncSender<Vec<Request>>,
+    config: EmbeddingConfig,
+) {
+    while let Ok(first) = requests.recv() {
+        let deadline = Instant::now() + config.max_wait;
+        let mut batch = vec![first];
+
+        while batch.len() < config.max_batch_size {
+            let wait = deadline.saturating_duration_since(Instant::now());
+            match requests.recv_timeout(wait) {
+                Ok(request) => batch.push(request),
+                Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => break,
+            }
+        }
+
+        if batches.send(batch).is_err() {
+            return;
+        }
+    }
+}
+
+fn work(embedder: &Embedder, batches: &Mutex<Receiver<Vec<Request>>>) {
+    loop {
+        let Ok(batch) = batches.lock().unwrap().recv() else {
+            return;
+        };
+
+        let texts: Vec<&str> = batch.iter().map(|r| r.text.as_str()).collect();
+        match embedder.embed(&texts) {
+            Ok(embeddings) => {
+                for (request, embedding) in batch.into_iter().zip(embeddings) {
+                    let _ = request.reply.send(Ok(embedding));
+                }
+            }
+            // Retried one by one, so that a text the model rejects only fails its own caller.
+            Err(_) if batch.len() > 1 => {
+                for request in batch {
+                    let result = embedder
+                        .embed(&[request.text.as_str()])
+                        .map(|mut embeddings| embeddings.remove(0));
+                    let _ = request.reply.send(result);
+                }
+            }
+            Err(e) => {
+                for request in batch {
+                    let _ = request.reply.send(Err(anyhow!("{}", e)));
+                }
+            }
+        }
+    }
+}
+
+/// Words the benchmark passages are drawn from.
+const BENCH_WORDS: &str = "le serveur renvoie une erreur lorsque la configuration du proxy est absente \
+    et les journaux indiquent un délai dépassé pendant la synchronisation des documents avec l'index";
+
+/// Measures, with the loaded model on the current machine, the throughput of embedding
+/// synthetic passages one at a time on the calling thread, then all at once through the
+/// batching service, and prints both. Nothing is recorded.
+pub async fn bench(state: &AppState, args: BenchArgs) -> Result<()> {
+    let words: Vec<&str> = BENCH_WORDS.split_whitespace().collect();
+    let texts: Vec<String> = (0..args.passages)
+        .map(|i| {
+            (0..args.words)
+                .map(|j| words[(i * 7 + j * 13 + j / 5) % words.len()])
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .collect();
+
+    let started = Instant::now();
+    for text in &texts {
+        state.embedder.embedder.embed(&[text])?;
+    }
+    let sequential = started.elapsed();
+
+    let started = Instant::now();
+    let results: Vec<Result<Vec<f32>>> = stream::iter(&texts)
+        .map(|text| state.embedder.embed(text))
+        .buffer_unordered(args.concurrency)
+        .collect()
+        .await;
+    let batched = started.elapsed();
+    results.into_iter().collect::<Result<Vec<_>>>()?;
+
+    let config = state.embedder.config;
+    let rate = |elapsed: std::time::Duration| args.passages as f64 / elapsed.as_secs_f64();
+    println!(
+        "{} passages de {} mots, {} requêtes simultanées",
+        args.passages, args.words, args.concurrency
+    );
+    println!(
+        "Un par un:  {:>8.1} passages/s ({:.2?})",
+        rate(sequential),
+        sequential
+    );
+    println!(
+        "Par lots:   {:>8.1} passages/s ({:.2?}) — lots de {} au plus, attente {:?}, {} worker(s)",
+        rate(batched),
+        batched,
+        config.max_batch_size,
+        config.max_wait,
+        config.workers
+    );
+    Ok(())
+}
@@ -0,0 +1,481 @@
+mod office;
+
+use crate::code::Language;
+use anyhow::{anyhow, Result};
+use pulldown_cmark::{CodeBlockKind, Event, Options, Parser, Tag, TagEnd};
+use scraper::{ElementRef, Html, Node};
+use std::path::Path;
+
+/// File formats accepted by `/ingest/file`, detected from the extension.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileKind {
+    Markdown,
+    Html,
+    Text,
+    Pdf,
+    Docx,
+    Odt,
+    /// Source code, split on its syntax rather than on sentences.
+    Code(Language),
+}
+
+impl FileKind {
+    pub fn from_name(file_name: &str) -> Option<Self> {
+        let extension = Path::new(file_name)
+            .extension()?
+            .to_str()?
+            .to_ascii_lowercase();
+        match extension.as_str() {
+            "md" | "markdown" => Some(FileKind::Markdown),
+            "html" | "htm" => Some(FileKind::Html),
+            "txt" => Some(FileKind::Text),
+            "pdf" => Some(FileKind::Pdf),
+            "docx" => Some(FileKind::Docx),
+            "odt" => Some(FileKind::Odt),
+            _ => Language::from_path(file_name).map(FileKind::Code),
+        }
+    }
+}
+
+/// Plain text of a file, with headings rendered as `#` lines so that
+/// `split_sections` treats them as section boundaries.
+pub struct Extracted {
+    pub text: String,
+    /// HTML `<title>`, PDF `Title` entry, document properties title, or else the first heading.
+    pub title: Option<String>,
+    /// Text of each page for paginated formats, numbered from 1; `text` joins them.
+    pub pages: Vec<String>,
+}
+
+impl Extracted {
+    fn unpaged(text: String, title: Option<String>) -> Self {
+        Self {
+            text,
+            title,
+            pages: Vec::new(),
+        }
+    }
+}
+
+pub fn extract(kind: FileKind, data: &[u8]) -> Result<Extracted> {
+    let text = |data| std::str::from_utf8(data).map_err(|e| anyhow!("Fichier non UTF-8: {}", e));
+
+    match kind {
+        FileKind::Markdown => Ok(markdown_to_text(text(data)?)),
+        FileKind::Html => Ok(html_to_text(text(data)?)),
+        FileKind::Text | FileKind::Code(_) => Ok(Extracted::unpaged(text(data)?.to_string(), None)),
+        FileKind::Pdf => pdf_to_text(data),
+        FileKind::Docx => office::docx_to_text(data),
+        FileKind::Odt => office::odt_to_text(data),
+    }
+}
+
+/// Bound on the decompressed content of a single PDF page, against compression bombs.
+const MAX_PDF_PAGE_CONTENT: usize = 64 * 1024 * 1024;
+
+fn pdf_to_text(data: &[u8]) -> Result<Extracted> {
+    let document = lopdf::Document::load_mem(data).map_err(|e| anyhow!("PDF illisible: {}", e))?;
+
+    let mut pages = Vec::new();
+    for (number, _) in document.get_pages() {
+        let text = document
+            .extract_text_with_limit(&[number], MAX_PDF_PAGE_CONTENT)
+            .map_err(|e| anyhow!("Extraction de la page {} impossible: {}", number, e))?;
+        pages.push(normalize(&text));
+    }
+
+    let title = document
+        .get_dict_in_dict(&document.trailer, b"Info")
+        .ok()
+        .and_then(|info| info.get_deref(b"Title", &document).ok())
+        .and_then(|title| lopdf::decode_text_string(title).ok())
+        .map(|title| collapse_whitespace(&title))
+        .filter(|title| !title.is_empty());
+
+    Ok(Extracted {
+        text: pages.join("\n\n"),
+        title,
+        pages,
+    })
+}
+
+fn heading_prefix(level: usize) -> String {
+    format!("{} ", "#".repeat(level.clamp(1, 6)))
+}
+
+fn markdown_to_text(source: &str) -> Extracted {
+    let mut text = String::new();
+    let mut title = None;
+    let mut heading: Option<String> = None;
+
+    for event in Parser::new_ext(source, Options::ENABLE_TABLES) {
+        match event {
+            Event::Start(Tag::Heading { level, .. }) => {
+                text.push_str("\n\n");
+                text.push_str(&heading_prefix(level as usize));
+                heading = Some(String::new());
+            }
+            Event::End(TagEnd::Heading(_)) => {
+                if let Some(h) = heading.take()
+                    && title.is_none()
+                    && !h.trim().is_empty()
+                {
+                    title = Some(h.trim().to_string());
+                }
+                text.push_str("\n\n");
+            }
+            Event::Start(Tag::CodeBlock(kind)) => {
+                let lang = match kind {
+                    CodeBlockKind::Fenced(lang) => lang.to_string(),
+                    CodeBlockKind::Indented => String::new(),
+                };
+                text.push_str(&format!("\n\n```{}\n", lang));
+            }
+            Event::End(TagEnd::CodeBlock) => text.push_str("```\n\n"),
+            Event::Start(Tag::Item) => text.push_str("\n- "),
+            Event::End(TagEnd::Paragraph) | Event::End(TagEnd::List(_)) => text.push_str("\n\n"),
+            Event::Start(Tag::TableHead) | Event::Start(Tag::TableRow) => text.push_str("| "),
+            Event::End(TagEnd::TableCell) => text.push_str(" | "),
+            Event::End(TagEnd::TableHead) | Event::End(TagEnd::TableRow) => text.push('\n'),
+            Event::End(TagEnd::Table) => text.push_str("\n\n"),
+            Event::Text(t) | Event::Code(t) => {
+                if let Some(h) = heading.as_mut() {
+                    h.push_str(&t);
+                }
+                text.push_str(&t);
+            }
+            Event::SoftBreak => text.push(' '),
+            Event::HardBreak => text.push('\n'),
+            _ => {}
+        }
+    }
+
+    Extracted::unpaged(normalize(&text), title)
+}
+
+const SKIPPED_ELEMENTS: [&str; 7] = [
+    "head", "script", "style", "noscript", "template", "svg", "nav",
+];
+
+const BLOCK_ELEMENTS: [&str; 16] = [
+    "p",
+    "div",
+    "section",
+    "article",
+    "main",
+    "header",
+    "footer",
+    "aside",
+    "blockquote",
+    "ul",
+    "ol",
+    "dl",
+    "dt",
+    "dd",
+    "figure",
+    "table",
+];
+
+/// Site chrome left out of a web page when it has no main content element.
+const PAGE_CHROME_ELEMENTS: [&str; 4] = ["header", "footer", "aside", "form"];
+
+/// Elements holding the main content of a web page, by preference.
+const MAIN_CONTENT_SELECTORS: [&str; 3] = ["main", "[role=main]", "article"];
+
+fn html_to_text(source: &str) -> Extracted {
+    let document = Html::parse_document(source);
+    html_element_to_text(&document, document.root_element(), &SKIPPED_ELEMENTS)
+}
+
+/// Main content of a fetched web page: its `<main>` or `<article>` element if any,
+/// otherwise the body without its header, footer and sidebars.
+pub fn html_page_to_text(document: &Html) -> Extracted {
+    let main = MAIN_CONTENT_SELECTORS.iter().find_map(|selector| {
+        let selector = scraper::Selector::parse(selector).ok()?;
+        document.select(&selector).next()
+    });
+    match main {
+        Some(main) => html_element_to_text(document, main, &SKIPPED_ELEMENTS),
+        None => {
+            let skipped: Vec<&str> = SKIPPED_ELEMENTS
+                .iter()
+                .chain(&PAGE_CHROME_ELEMENTS)
+                .copied()
+                .collect();
+            html_element_to_text(document, document.root_element(), &skipped)
+        }
+    }
+}
+
+fn html_element_to_text(document: &Html, element: ElementRef, skipped: &[&str]) -> Extracted {
+    let mut text = String::new();
+    let mut first_heading = None;
+    walk_html(element, skipped, &mut text, &mut first_heading);
+
+    let title = scraper::Selector::parse("title")
+        .ok()
+        .and_then(|s| document.select(&s).next())
+        .map(|t| collapse_whitespace(&t.text().collect::<String>()))
+        .filter(|t| !t.is_empty())
+        .or(first_heading);
+
+    Extracted::unpaged(normalize(&text), title)
+}
+
+fn walk_html(
+    element: ElementRef,
+    skipped: &[&str],
+    text: &mut String,
+    first_heading: &mut Option<String>,
+) {
+    for child in element.children() {
+        match child.value() {
+            Node::Text(t) => text.push_str(&collapse_whitespace_keep_edges(t)),
+            Node::Element(_) => {
+                let Some(child) = ElementRef::wrap(child) else {
+                    continue;
+                };
+                let name = child.value().name();
+
+                if skipped.contains(&name) {
+                    continue;
+                }
+
+                if let Some(level) = name
+                    .strip_prefix('h')
+                    .and_then(|l| l.parse::<usize>().ok())
+                    .filter(|l| (1..=6).contains(l))
+                {
+                    let heading = collapse_whitespace(&child.text().collect::<String>());
+                    if !heading.is_empty() {
+                        if first_heading.is_none() {
+                            *first_heading = Some(heading.clone());
+                        }
+                        text.push_str(&format!("\n\n{}{}\n\n", heading_prefix(level), heading));
+                    }
+                    continue;
+                }
+
+                match name {
+                    "pre" => {
+                        text.push_str("\n\n```\n");
+                        text.push_str(child.text().collect::<String>().trim_end());
+                        text.push_str("\n```\n\n");
+                    }
+                    "br" => text.push('\n'),
+                    "li" => {
+                        text.push_str("\n- ");
+                        walk_html(child, skipped, text, first_heading);
+                    }
+                    "tr" => {
+                        text.push_str("\n| ");
+                        walk_html(child, skipped, text, first_heading);
+                    }
+                    "td" | "th" => {
+                        walk_html(child, skipped, text, first_heading);
+                        text.push_str(" | ");
+                    }
+                    _ if BLOCK_ELEMENTS.contains(&name) => {
+                        text.push_str("\n\n");
+                        walk_html(child, skipped, text, first_heading);
+                        text.push_str("\n\n");
+                    }
+                    _ => walk_html(child, skipped, text, first_heading),
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Collapses inner whitespace while keeping a single space at each edge, so
+/// inline elements stay separated from the surrounding text.
+fn collapse_whitespace_keep_edges(text: &str) -> String {
+    let inner = collapse_whitespace(text);
+    if inner.is_empty() {
+        return if text.is_empty() { inner } else { " ".into() };
+    }
+
+    let mut out = String::with_capacity(inner.len() + 2);
+    if text.starts_with(char::is_whitespace) {
+        out.push(' ');
+    }
+    out.push_str(&inner);
+    if text.ends_with(char::is_whitespace) {
+        out.push(' ');
+    }
+    out
+}
+
+/// Trims lines and keeps at most one blank line between blocks, except inside code fences.
+fn normalize(text: &str) -> String {
+    let mut lines: Vec<&str> = Vec::new();
+    let mut in_code = false;
+
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("```") {
+            in_code = !in_code;
+            lines.push(trimmed);
+            continue;
+        }
+        if in_code {
+            lines.push(line.trim_end());
+            continue;
+        }
+        if trimmed.is_empty() && lines.last().is_none_or(|l| l.is_empty()) {
+            continue;
+        }
+        lines.push(trimmed);
+    }
+
+    while lines.last().is_some_and(|l| l.is_empty()) {
+        lines.pop();
+    }
+
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_kind_from_extension() {
+        assert_eq!(FileKind::from_name("guide.MD"), Some(FileKind::Markdown));
+        assert_eq!(FileKind::from_name("a/b/index.htm"), Some(FileKind::Html));
+        assert_eq!(FileKind::from_name("notes.txt"), Some(FileKind::Text));
+        assert_eq!(FileKind::from_name("rapport.odt"), Some(FileKind::Odt));
+        assert!(matches!(
+            FileKind::from_name("src/main.rs"),
+            Some(FileKind::Code(_))
+        ));
+        assert_eq!(FileKind::from_name("image.png"), None);
+        assert_eq!(FileKind::from_name("README"), None);
+    }
+
+    #[test]
+    fn markdown_keeps_headings_lists_code_and_tables() {
+        let extracted = markdown_to_text(
+            "# Installation\n\nLancer le\nserveur.\n\n## Proxy\n\n- un\n- deux\n\n\
+             ```sh\n  export HTTP_PROXY=x\n```\n\n| Clé | Valeur |\n|---|---|\n| a | 1 |\n",
+        );
+
+        assert_eq!(extracted.title.as_deref(), Some("Installation"));
+        assert_eq!(
+            extracted.text,
+            "# Installation\n\nLancer le serveur.\n\n## Proxy\n\n- un\n- deux\n\n\
+             ```sh\n  export HTTP_PROXY=x\n```\n\n| Clé | Valeur |\n| a | 1 |"
+        );
+    }
+
+    #[test]
+    fn html_skips_scripts_and_uses_title() {
+        let extracted = html_to_text(
+            "<html><head><title> Guide  proxy </title><style>p {}</style></head>\
+             <body><nav>Menu</nav><h2>Réglages</h2><p>Texte <b>gras</b>\n suivi.</p>\
+             <script>alert(1)</script><ul><li>un</li><li>deux</li></ul>\
+             <table><tr><th>a</th><td>1</td></tr></table><pre>  code\n  indenté</pre></body></html>",
+        );
+
+        assert_eq!(extracted.title.as_deref(), Some("Guide proxy"));
+        assert_eq!(
+            extracted.text,
+            "## Réglages\n\nTexte gras suivi.\n\n- un\n- deux\n\n| a | 1 |\n\n```\n  code\n  indenté\n```"
+        );
+    }
+
+    #[test]
+    fn html_page_prefers_main_content() {
+        let page = Html::parse_document(
+            "<body><header>Logo</header><main><h1>Article</h1><p>Corps</p></main>\
+             <footer>Mentions</footer></body>",
+        );
+        assert_eq!(html_page_to_text(&page).text, "# Article\n\nCorps");
+
+        let page = Html::parse_document(
+            "<body><header>Logo</header><p>Corps</p><aside>Pub</aside></body>",
+        );
+        assert_eq!(html_page_to_text(&page).text, "Corps");
+    }
+
+    #[test]
+    fn text_must_be_utf8() {
+        let extracted = extract(FileKind::Text, "déjà vu".as_bytes()).unwrap();
+        assert_eq!(extracted.text, "déjà vu");
+        assert!(extracted.pages.is_empty());
+        assert!(extract(FileKind::Text, &[0xff, 0xfe]).is_err());
+    }
+
+    /// Minimal PDF with one Helvetica text line per page and an `Info` title.
+    fn pdf(pages: &[&str], title: &str) -> Vec<u8> {
+        use lopdf::content::{Content, Operation};
+        use lopdf::{dictionary, Object, Stream};
+
+        let mut document = lopdf::Document::with_version("1.5");
+        let pages_id = document.new_object_id();
+        let font_id = document.add_object(dictionary! {
+            "Type" => "Font",
+            "Subtype" => "Type1",
+            "BaseFont" => "Helvetica",
+        });
+
+        let mut kids = Vec::new();
+        for text in pages {
+            let content = Content {
+                operations: vec![
+                    Operation::new("BT", vec![]),
+                    Operation::new("Tf", vec!["F1".into(), 12.into()]),
+                    Operation::new("Td", vec![50.into(), 700.into()]),
+                    Operation::new("Tj", vec![Object::string_literal(*text)]),
+                    Operation::new("ET", vec![]),
+                ],
+            };
+            let content_id =
+                document.add_object(Stream::new(dictionary! {}, content.encode().unwrap()));
+            kids.push(Object::from(document.add_object(dictionary! {
+                "Type" => "Page",
+                "Parent" => pages_id,
+                "Contents" => content_id,
+                "Resources" => dictionary! { "Font" => dictionary! { "F1" => font_id } },
+                "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+            })));
+        }
+
+        let count = kids.len() as i64;
+        document.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! { "Type" => "Pages", "Kids" => kids, "Count" => count }),
+        );
+        let catalog_id =
+            document.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+        let info_id = document.add_object(dictionary! { "Title" => Object::string_literal(title) });
+        document.trailer.set("Root", catalog_id);
+        document.trailer.set("Info", info_id);
+
+        let mut data = Vec::new();
+        document.save_to(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn pdf_text_page_by_page() {
+        let data = pdf(&["Premiere page", "Seconde page"], "Manuel  proxy");
+        let extracted = extract(FileKind::Pdf, &data).unwrap();
+
+        assert_eq!(extracted.title.as_deref(), Some("Manuel proxy"));
+        assert_eq!(extracted.pages.len(), 2);
+        assert!(extracted.pages[0].contains("Premiere page"));
+        assert!(extracted.pages[1].contains("Seconde page"));
+        assert_eq!(extracted.text, extracted.pages.join("\n\n"));
+    }
+
+    #[test]
+    fn invalid_pdf_is_an_error() {
+        assert!(extract(FileKind::Pdf, b"%PDF-1.5 tronqu").is_err());
+    }
+}
@@ -0,0 +1,453 @@
+use super::{collapse_whitespace, heading_prefix, normalize, Extracted};
+use anyhow::{anyhow, Context, Result};
+use quick_xml::escape::resolve_predefined_entity;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::{Reader, XmlVersion};
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+
+/// Bound on a decompressed XML part of an archive, against zip bombs.
+const MAX_PART_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Elements whose content is not part of the running text (ODT notes and comments).
+const SKIPPED_ELEMENTS: [&str; 3] = ["note", "annotation", "tracked-changes"];
+
+type Archive = zip::ZipArchive<Cursor<Vec<u8>>>;
+
+fn open_archive(data: &[u8]) -> Result<Archive> {
+    zip::ZipArchive::new(Cursor::new(data.to_vec()))
+        .map_err(|e| anyhow!("Archive illisible: {}", e))
+}
+
+/// Reads an XML part of the archive, or `None` if it is absent.
+fn read_part(archive: &mut Archive, name: &str) -> Result<Option<String>> {
+    let part = match archive.by_name(name) {
+        Ok(part) => part,
+        Err(zip::result::ZipError::FileNotFound) => return Ok(None),
+        Err(e) => return Err(anyhow!("Lecture de {} impossible: {}", name, e)),
+    };
+
+    let mut xml = String::new();
+    part.take(MAX_PART_SIZE + 1)
+        .read_to_string(&mut xml)
+        .with_context(|| format!("Lecture de {} impossible", name))?;
+    if xml.len() as u64 > MAX_PART_SIZE {
+        return Err(anyhow!("{} dépasse la taille maximale", name));
+    }
+
+    Ok(Some(xml))
+}
+
+fn attribute(element: &BytesStart, local_name: &str) -> Option<String> {
+    element
+        .attributes()
+        .flatten()
+        .find(|a| a.key.local_name().as_ref() == local_name)
+        .and_then(|a| a.normalized_value(XmlVersion::Implicit1_0).ok())
+        .map(|v| v.into_owned())
+}
+
+/// Text of an entity reference such as `&amp;` or `&#233;`.
+fn resolve_reference(name: &str) -> Option<String> {
+    if let Some(code) = name.strip_prefix("#x").or_else(|| name.strip_prefix("#X")) {
+        return u32::from_str_radix(code, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .map(String::from);
+    }
+    if let Some(code) = name.strip_prefix('#') {
+        return code.parse().ok().and_then(char::from_u32).map(String::from);
+    }
+    resolve_predefined_entity(name).map(str::to_string)
+}
+
+/// Text of the first `<dc:title>` in a metadata part.
+fn metadata_title(xml: &str) -> Option<String> {
+    let mut reader = Reader::from_str(xml);
+    let mut in_title = false;
+    let mut title = String::new();
+
+    loop {
+        match reader.read_event().ok()? {
+            Event::Start(e) if e.local_name().as_ref() == "title" => in_title = true,
+            Event::End(e) if e.local_name().as_ref() == "title" => break,
+            Event::Text(t) if in_title => title.push_str(&t.xml10_content()),
+            Event::GeneralRef(r) if in_title => {
+                title.push_str(&resolve_reference(&r.into_inner()).unwrap_or_default())
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Some(collapse_whitespace(&title)).filter(|t| !t.is_empty())
+}
+
+/// Accumulates headings, paragraphs and table rows into text.
+#[derive(Default)]
+struct TextBuilder {
+    text: String,
+    paragraph: String,
+    paragraph_depth: usize,
+    heading: Option<usize>,
+    list_item: bool,
+    table_depth: usize,
+    cell: String,
+    row: Vec<String>,
+    first_heading: Option<String>,
+}
+
+impl TextBuilder {
+    fn start_paragraph(&mut self) {
+        if self.paragraph_depth == 0 {
+            self.paragraph.clear();
+            self.heading = None;
+            self.list_item = false;
+        } else {
+            self.paragraph.push(' ');
+        }
+        self.paragraph_depth += 1;
+    }
+
+    fn push(&mut self, text: &str) {
+        if self.paragraph_depth > 0 {
+            self.paragraph.push_str(text);
+        }
+    }
+
+    fn end_paragraph(&mut self) {
+        self.paragraph_depth = self.paragraph_depth.saturating_sub(1);
+        if self.paragraph_depth > 0 {
+            return;
+        }
+
+        let paragraph = collapse_whitespace(&self.paragraph);
+        if paragraph.is_empty() {
+            return;
+        }
+
+        if self.table_depth > 0 {
+            if !self.cell.is_empty() {
+                self.cell.push(' ');
+            }
+            self.cell.push_str(&paragraph);
+        } else if let Some(level) = self.heading {
+            if self.first_heading.is_none() {
+                self.first_heading = Some(paragraph.clone());
+            }
+            self.text
+                .push_str(&format!("\n\n{}{}\n\n", heading_prefix(level), paragraph));
+        } else if self.list_item {
+            self.text.push_str(&format!("\n- {}\n", paragraph));
+        } else {
+            self.text.push_str(&format!("\n\n{}\n\n", paragraph));
+        }
+    }
+
+    fn start_table(&mut self) {
+        if self.table_depth == 0 {
+            self.text.push_str("\n\n");
+        }
+        self.table_depth += 1;
+    }
+
+    fn end_table(&mut self) {
+        self.table_depth = self.table_depth.saturating_sub(1);
+        if self.table_depth == 0 {
+            self.text.push_str("\n\n");
+        }
+    }
+
+    /// Nested tables are flattened into the enclosing cell.
+    fn end_cell(&mut self) {
+        if self.table_depth == 1 {
+            self.row.push(std::mem::take(&mut self.cell));
+        } else {
+            self.cell.push(' ');
+        }
+    }
+
+    fn end_row(&mut self) {
+        if self.table_depth != 1 {
+            return;
+        }
+        let row = std::mem::take(&mut self.row);
+        if row.iter().any(|cell| !cell.is_empty()) {
+            self.text.push_str(&format!("| {} |\n", row.join(" | ")));
+        }
+    }
+
+    fn finish(self, title: Option<String>) -> Extracted {
+        Extracted::unpaged(normalize(&self.text), title.or(self.first_heading))
+    }
+}
+
+/// Heading level of each paragraph style of a DOCX, from its name (`heading 2`, `Title`)
+/// or outline level. Style ids are localized (`Titre2`, `berschrift2`), names are not.
+fn docx_heading_styles(styles: &str) -> HashMap<String, usize> {
+    let mut levels = HashMap::new();
+    let mut reader = Reader::from_str(styles);
+    let mut style: Option<String> = None;
+
+    while let Ok(event) = reader.read_event() {
+        match event {
+            Event::Start(e) if e.local_name().as_ref() == "style" => {
+                style = attribute(&e, "styleId");
+            }
+            Event::End(e) if e.local_name().as_ref() == "style" => style = None,
+            Event::Empty(e) | Event::Start(e) => {
+                let Some(id) = &style else {
+                    continue;
+                };
+                let level = match e.local_name().as_ref() {
+                    "name" => attribute(&e, "val").and_then(|name| {
+                        let name = name.to_lowercase();
+                        if name == "title" {
+                            Some(1)
+                        } else {
+                            name.strip_prefix("heading ")?.parse().ok()
+                        }
+                    }),
+                    "outlineLvl" => attribute(&e, "val")
+                        .and_then(|l| l.parse::<usize>().ok())
+                        .map(|l| l + 1),
+                    _ => None,
+                };
+                if let Some(level) = level.filter(|l| (1..=9).contains(l)) {
+                    levels.insert(id.clone(), level);
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    levels
+}
+
+pub fn docx_to_text(data: &[u8]) -> Result<Extracted> {
+    let mut archive = open_archive(data)?;
+    let document = read_part(&mut archive, "word/document.xml")?
+        .ok_or_else(|| anyhow!("word/document.xml absent, fichier DOCX invalide"))?;
+    let heading_styles = read_part(&mut archive, "word/styles.xml")?
+        .map(|styles| docx_heading_styles(&styles))
+        .unwrap_or_default();
+    let title = read_part(&mut archive, "docProps/core.xml")?.and_then(|xml| metadata_title(&xml));
+
+    let mut builder = TextBuilder::default();
+    let mut reader = Reader::from_str(&document);
+    let mut in_text = false;
+
+    loop {
+        let event = reader
+            .read_event()
+            .map_err(|e| anyhow!("XML invalide dans word/document.xml: {}", e))?;
+        match event {
+            Event::Start(e) => match e.local_name().as_ref() {
+                "p" => builder.start_paragraph(),
+                "t" => in_text = true,
+                "tbl" => builder.start_table(),
+                "numPr" => builder.list_item = true,
+                _ => {}
+            },
+            Event::Empty(e) => match e.local_name().as_ref() {
+                "pStyle" => {
+                    if let Some(level) = attribute(&e, "val").and_then(|s| heading_styles.get(&s)) {
+                        builder.heading = Some(*level);
+                    }
+                }
+                "outlineLvl" => {
+                    if let Some(level) = attribute(&e, "val").and_then(|l| l.parse::<usize>().ok())
+                        && level < 9
+                    {
+                        builder.heading = Some(level + 1);
+                    }
+                }
+                "tab" | "br" | "cr" => builder.push(" "),
+                _ => {}
+            },
+            Event::End(e) => match e.local_name().as_ref() {
+                "p" => builder.end_paragraph(),
+                "t" => in_text = false,
+                "tc" => builder.end_cell(),
+                "tr" => builder.end_row(),
+                "tbl" => builder.end_table(),
+                _ => {}
+            },
+            Event::Text(t) if in_text => builder.push(&t.xml10_content()),
+            Event::GeneralRef(r) if in_text => {
+                builder.push(&resolve_reference(&r.into_inner()).unwrap_or_default())
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(builder.finish(title))
+}
+
+pub fn odt_to_text(data: &[u8]) -> Result<Extracted> {
+    let mut archive = open_archive(data)?;
+    let content = read_part(&mut archive, "content.xml")?
+        .ok_or_else(|| anyhow!("content.xml absent, fichier ODT invalide"))?;
+    let title = read_part(&mut archive, "meta.xml")?.and_then(|xml| metadata_title(&xml));
+
+    let mut builder = TextBuilder::default();
+    let mut reader = Reader::from_str(&content);
+    let mut list_depth = 0usize;
+    let mut skip_depth = 0usize;
+
+    loop {
+        let event = reader
+            .read_event()
+            .map_err(|e| anyhow!("XML invalide dans content.xml: {}", e))?;
+
+        if skip_depth > 0 {
+            match event {
+                Event::Start(_) => skip_depth += 1,
+                Event::End(_) => skip_depth -= 1,
+                Event::Eof => break,
+                _ => {}
+            }
+            continue;
+        }
+
+        match event {
+            Event::Start(e) => match e.local_name().as_ref() {
+                name if SKIPPED_ELEMENTS.contains(&name) => skip_depth = 1,
+                "h" => {
+                    builder.start_paragraph();
+                    builder.heading = Some(
+                        attribute(&e, "outline-level")
+                            .and_then(|l| l.parse().ok())
+                            .unwrap_or(1),
+                    );
+                }
+                "p" => {
+                    builder.start_paragraph();
+                    builder.list_item = list_depth > 0;
+                }
+                "list-item" => list_depth += 1,
+                "table" => builder.start_table(),
+                _ => {}
+            },
+            Event::Empty(e) => match e.local_name().as_ref() {
+                // Runs of spaces are collapsed, so the `c` repeat count of `<text:s>` is ignored.
+                "s" | "tab" | "line-break" => builder.push(" "),
+                _ => {}
+            },
+            Event::End(e) => match e.local_name().as_ref() {
+                "h" | "p" => builder.end_paragraph(),
+                "list-item" => list_depth = list_depth.saturating_sub(1),
+                "table-cell" => builder.end_cell(),
+                "table-row" => builder.end_row(),
+                "table" => builder.end_table(),
+                _ => {}
+            },
+            Event::Text(t) => builder.push(&t.xml10_content()),
+            Event::GeneralRef(r) => {
+                builder.push(&resolve_reference(&r.into_inner()).unwrap_or_default())
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(builder.finish(title))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn archive(parts: &[(&str, &str)]) -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, content) in parts {
+            zip.start_file(*name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    const DOCX_STYLES: &str = r#"<w:styles xmlns:w="w">
+        <w:style w:styleId="Titre2"><w:name w:val="heading 2"/></w:style>
+        <w:style w:styleId="Plan"><w:pPr><w:outlineLvl w:val="0"/></w:pPr></w:style>
+    </w:styles>"#;
+
+    #[test]
+    fn docx_headings_lists_and_tables() {
+        let document = r#"<w:document xmlns:w="w"><w:body>
+            <w:p><w:pPr><w:pStyle w:val="Plan"/></w:pPr><w:r><w:t>Installation</w:t></w:r></w:p>
+            <w:p><w:r><w:t>Lancer</w:t></w:r><w:r><w:tab/><w:t>le serveur &amp; attendre.</w:t></w:r></w:p>
+            <w:p><w:pPr><w:pStyle w:val="Titre2"/></w:pPr><w:r><w:t>Proxy</w:t></w:r></w:p>
+            <w:p><w:pPr><w:numPr><w:numId w:val="1"/></w:numPr></w:pPr><w:r><w:t>premier point</w:t></w:r></w:p>
+            <w:tbl>
+                <w:tr><w:tc><w:p><w:r><w:t>Clé</w:t></w:r></w:p></w:tc><w:tc><w:p><w:r><w:t>Valeur</w:t></w:r></w:p></w:tc></w:tr>
+                <w:tr><w:tc><w:p><w:r><w:t>port</w:t></w:r></w:p></w:tc><w:tc><w:p><w:r><w:t>8080</w:t></w:r></w:p></w:tc></w:tr>
+            </w:tbl>
+        </w:body></w:document>"#;
+        let core = r#"<cp:coreProperties xmlns:dc="dc"><dc:title>Guide &#233;t&#xE9;</dc:title></cp:coreProperties>"#;
+        let data = archive(&[
+            ("word/document.xml", document),
+            ("word/styles.xml", DOCX_STYLES),
+            ("docProps/core.xml", core),
+        ]);
+
+        let extracted = docx_to_text(&data).unwrap();
+        assert_eq!(extracted.title.as_deref(), Some("Guide été"));
+        assert_eq!(
+            extracted.text,
+            "# Installation\n\nLancer le serveur & attendre.\n\n## Proxy\n\n- premier point\n\n\
+             | Clé | Valeur |\n| port | 8080 |"
+        );
+    }
+
+    #[test]
+    fn docx_title_falls_back_to_first_heading() {
+        let document = r#"<w:document xmlns:w="w"><w:body>
+            <w:p><w:pPr><w:outlineLvl w:val="1"/></w:pPr><w:r><w:t>Annexe</w:t></w:r></w:p>
+        </w:body></w:document>"#;
+        let extracted = docx_to_text(&archive(&[("word/document.xml", document)])).unwrap();
+        assert_eq!(extracted.title.as_deref(), Some("Annexe"));
+        assert_eq!(extracted.text, "## Annexe");
+    }
+
+    #[test]
+    fn docx_without_document_part_is_rejected() {
+        assert!(docx_to_text(&archive(&[("word/styles.xml", DOCX_STYLES)])).is_err());
+        assert!(docx_to_text(b"pas une archive").is_err());
+    }
+
+    #[test]
+    fn odt_skips_notes_and_keeps_structure() {
+        let content = r#"<office:document-content xmlns:text="t" xmlns:table="tb"><office:body><office:text>
+            <text:h text:outline-level="2">Proxy</text:h>
+            <text:p>Texte<text:s text:c="4"/>avec<text:note><text:note-body><text:p>note</text:p></text:note-body></text:note> espaces.</text:p>
+            <text:list><text:list-item><text:p>point</text:p></text:list-item></text:list>
+            <table:table><table:table-row>
+                <table:table-cell><text:p>a</text:p></table:table-cell>
+                <table:table-cell><text:p>1</text:p></table:table-cell>
+            </table:table-row></table:table>
+        </office:text></office:body></office:document-content>"#;
+        let meta = r#"<office:document-meta xmlns:dc="dc"><office:meta><dc:title>Réseau</dc:title></office:meta></office:document-meta>"#;
+        let data = archive(&[("content.xml", content), ("meta.xml", meta)]);
+
+        let extracted = odt_to_text(&data).unwrap();
+        assert_eq!(extracted.title.as_deref(), Some("Réseau"));
+        assert_eq!(
+            extracted.text,
+            "## Proxy\n\nTexte avec espaces.\n\n- point\n\n| a | 1 |"
+        );
+    }
+
+    #[test]
+    fn odt_space_count_is_not_allocated() {
+        let content = r#"<office:document-content xmlns:text="t"><office:body><office:text>
+            <text:p>a<text:s text:c="99999999999"/>b</text:p>
+        </office:text></office:body></office:document-content>"#;
+        let extracted = odt_to_text(&archive(&[("content.xml", content)])).unwrap();
+        assert_eq!(extracted.text, "a b");
+    }
+}
@@ -0,0 +1,416 @@
+use crate::types::{FieldValue, Metadata};
+use mongodb::bson::{doc, Bson, Document};
+use serde::Deserialize;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+const FIELDS: [&str; 11] = [
+    "title",
+    "source",
+    "date",
+    "url",
+    "page",
+    "breadcrumb",
+    "path",
+    "symbol",
+    "line_start",
+    "line_end",
+    "tags",
+];
+const EXTRA_PREFIX: &str = "extra.";
+
+/// Condition on metadata fields, all of which must hold.
+///
+/// ```json
+/// {
+///   "source": "manuel-v2",
+///   "title": { "in": ["Installation", "Proxy"] },
+///   "date": { "gte": "2024-01-01", "lt": "2025-01-01" },
+///   "url": { "prefix": "https://docs.example.com/linux/" },
+///   "tags": "réseau",
+///   "extra.version": { "gte": 3 }
+/// }
+/// ```
+///
+/// As in MongoDB, each operator of a condition on a list (`tags` or a
+/// list-valued extra field) holds when it holds for any element, or for the
+/// list as a whole, not necessarily the same element for every operator:
+/// `{ "gt": 1, "lt": 3 }` matches `[0, 5]`. A list given as value only equals
+/// the same elements in the same order. Range operators compare numbers
+/// numerically and strings lexically, so dates must be ISO 8601.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(transparent)]
+pub struct MetadataFilter(BTreeMap<String, Condition>);
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum Condition {
+    Operators(Operators),
+    Equals(FieldValue),
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Operators {
+    eq: Option<FieldValue>,
+    #[serde(rename = "in")]
+    any_of: Option<Vec<FieldValue>>,
+    gt: Option<FieldValue>,
+    gte: Option<FieldValue>,
+    lt: Option<FieldValue>,
+    lte: Option<FieldValue>,
+    prefix: Option<String>,
+}
+
+fn equals(a: &FieldValue, b: &FieldValue) -> bool {
+    match (a, b) {
+        (FieldValue::List(a), FieldValue::List(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| equals(a, b))
+        }
+        _ => a.compare(b) == Some(Ordering::Equal),
+    }
+}
+
+/// Whether `test` holds for `value` or, if it is a list, for any of its elements.
+fn holds(value: &FieldValue, test: impl Fn(&FieldValue) -> bool) -> bool {
+    test(value) || matches!(value, FieldValue::List(values) if values.iter().any(&test))
+}
+
+fn ordered(value: &FieldValue, bound: &Option<FieldValue>, accept: fn(Ordering) -> bool) -> bool {
+    bound
+        .as_ref()
+        .is_none_or(|b| holds(value, |v| v.compare(b).is_some_and(accept)))
+}
+
+impl Operators {
+    fn is_empty(&self) -> bool {
+        self.eq.is_none()
+            && self.any_of.is_none()
+            && self.gt.is_none()
+            && self.gte.is_none()
+            && self.lt.is_none()
+            && self.lte.is_none()
+            && self.prefix.is_none()
+    }
+
+    /// Each operator is checked on its own against the value or any of its elements.
+    fn matches(&self, value: &FieldValue) -> bool {
+        self.eq
+            .as_ref()
+            .is_none_or(|eq| holds(value, |v| equals(v, eq)))
+            && self.any_of.as_ref().is_none_or(|values| {
+                holds(value, |v| values.iter().any(|expected| equals(v, expected)))
+            })
+            && ordered(value, &self.gt, Ordering::is_gt)
+            && ordered(value, &self.gte, Ordering::is_ge)
+            && ordered(value, &self.lt, Ordering::is_lt)
+            && ordered(value, &self.lte, Ordering::is_le)
+            && self.prefix.as_ref().is_none_or(|prefix| {
+                holds(
+                    value,
+                    |v| matches!(v, FieldValue::Text(text) if text.starts_with(prefix.as_str())),
+                )
+            })
+    }
+
+    fn to_bson(&self) -> Document {
+        let mut ops = Document::new();
+        if let Some(eq) = &self.eq {
+            ops.insert("$eq", Bson::from(eq));
+        }
+        if let Some(values) = &self.any_of {
+            ops.insert("$in", values.iter().map(Bson::from).collect::<Vec<_>>());
+        }
+        for (op, bound) in [
+            ("$gt", &self.gt),
+            ("$gte", &self.gte),
+            ("$lt", &self.lt),
+            ("$lte", &self.lte),
+        ] {
+            if let Some(bound) = bound {
+                ops.insert(op, Bson::from(bound));
+            }
+        }
+        if let Some(prefix) = &self.prefix {
+            ops.insert("$regex", format!("^{}", regex::escape(prefix)));
+        }
+        ops
+    }
+}
+
+impl Condition {
+    fn matches(&self, value: &FieldValue) -> bool {
+        match self {
+            Condition::Equals(expected) => holds(value, |v| equals(v, expected)),
+            Condition::Operators(ops) => ops.matches(value),
+        }
+    }
+}
+
+impl MetadataFilter {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Filter holding a single equality condition.
+    pub fn field_equals(field: &str, value: FieldValue) -> Self {
+        Self(BTreeMap::from([(
+            field.to_string(),
+            Condition::Equals(value),
+        )]))
+    }
+
+    /// Rejects unknown fields and operator objects without any operator.
+    pub fn validate(&self) -> Result<(), String> {
+        for (field, condition) in &self.0 {
+            let known = FIELDS.contains(&field.as_str())
+                || field
+                    .strip_prefix(EXTRA_PREFIX)
+                    .is_some_and(|key| !key.is_empty());
+            if !known {
+                return Err(format!(
+                    "Champ de filtre inconnu: {} (attendu: {} ou {}<nom>)",
+                    field,
+                    FIELDS.join(", "),
+                    EXTRA_PREFIX
+                ));
+            }
+            if let Condition::Operators(ops) = condition
+                && ops.is_empty()
+            {
+                return Err(format!("Aucun opérateur pour le champ {}", field));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn matches(&self, metadata: Option<&Metadata>) -> bool {
+        self.0.iter().all(|(field, condition)| {
+            metadata
+                .and_then(|m| m.field(field))
+                .is_some_and(|value| condition.matches(&value))
+        })
+    }
+
+    /// Equivalent MongoDB query on the `metadata` sub-document.
+    pub fn to_bson(&self) -> Document {
+        let mut query = doc! {};
+        for (field, condition) in &self.0 {
+            let value = match condition {
+                Condition::Equals(expected) => Bson::from(expected),
+                Condition::Operators(ops) => Bson::Document(ops.to_bson()),
+            };
+            query.insert(format!("metadata.{}", field), value);
+        }
+        query
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Metadata;
+
+    fn filter(json: &str) -> MetadataFilter {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn metadata(json: &str) -> Metadata {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn equality_and_missing_fields() {
+        let f = filter(r#"{ "source": "manuel-v2" }"#);
+        assert!(f.matches(Some(&metadata(r#"{ "source": "manuel-v2" }"#))));
+        assert!(!f.matches(Some(&metadata(r#"{ "source": "manuel-v1" }"#))));
+        assert!(!f.matches(Some(&metadata(r#"{ "title": "Proxy" }"#))));
+        assert!(!f.matches(None));
+    }
+
+    #[test]
+    fn operators() {
+        let f = filter(
+            r#"{
+                "date": { "gte": "2024-01-01", "lt": "2025-01-01" },
+                "url": { "prefix": "https://docs.example.com/" },
+                "title": { "in": ["Installation", "Proxy"] }
+            }"#,
+        );
+        let matching = r#"{
+            "date": "2024-06-01",
+            "url": "https://docs.example.com/linux",
+            "title": "Proxy"
+        }"#;
+        assert!(f.matches(Some(&metadata(matching))));
+        assert!(!f.matches(Some(&metadata(&matching.replace("2024-06", "2025-06")))));
+        assert!(!f.matches(Some(&metadata(&matching.replace("docs.", "blog.")))));
+        assert!(!f.matches(Some(&metadata(&matching.replace("Proxy", "FAQ")))));
+    }
+
+    #[test]
+    fn numbers_compare_across_types() {
+        let f = filter(r#"{ "extra.version": { "gte": 3 } }"#);
+        assert!(f.matches(Some(&metadata(r#"{ "extra": { "version": 3.5 } }"#))));
+        assert!(!f.matches(Some(&metadata(r#"{ "extra": { "version": 2 } }"#))));
+        assert!(!f.matches(Some(&metadata(r#"{ "extra": { "version": "3" } }"#))));
+    }
+
+    #[test]
+    fn scalar_condition_on_list_matches_any_element() {
+        let f = filter(r#"{ "tags": "réseau" }"#);
+        assert!(f.matches(Some(&metadata(r#"{ "tags": ["proxy", "réseau"] }"#))));
+        assert!(!f.matches(Some(&metadata(r#"{ "tags": ["proxy"] }"#))));
+    }
+
+    #[test]
+    fn list_value_matches_the_whole_list() {
+        let f = filter(r#"{ "tags": ["proxy", "réseau"] }"#);
+        assert!(f.matches(Some(&metadata(r#"{ "tags": ["proxy", "réseau"] }"#))));
+        assert!(!f.matches(Some(&metadata(r#"{ "tags": ["réseau", "proxy"] }"#))));
+        assert!(!f.matches(Some(&metadata(r#"{ "tags": ["proxy"] }"#))));
+        assert!(!f.matches(Some(&metadata(r#"{ "tags": ["proxy", "réseau", "dns"] }"#))));
+
+        let f = filter(r#"{ "extra.versions": { "eq": [1, 2] } }"#);
+        assert!(f.matches(Some(&metadata(r#"{ "extra": { "versions": [1, 2.0] } }"#))));
+        assert!(!f.matches(Some(&metadata(r#"{ "extra": { "versions": [1] } }"#))));
+    }
+
+    #[test]
+    fn validation() {
+        assert!(filter(r#"{ "tags": "a", "extra.team": "x" }"#)
+            .validate()
+            .is_ok());
+        assert!(filter(r#"{ "auteur": "x" }"#).validate().is_err());
+        assert!(filter(r#"{ "extra.": "x" }"#).validate().is_err());
+        assert!(filter(r#"{ "title": {} }"#).validate().is_err());
+        assert!(serde_json::from_str::<MetadataFilter>(r#"{ "title": { "ne": "x" } }"#).is_err());
+    }
+
+    #[test]
+    fn bson_translation() {
+        let f = filter(
+            r#"{
+                "tags": ["proxy", "réseau"],
+                "date": { "gte": "2024-01-01" },
+                "url": { "prefix": "https://a.b/" }
+            }"#,
+        );
+        assert_eq!(
+            f.to_bson(),
+            doc! {
+                "metadata.date": { "$gte": "2024-01-01" },
+                "metadata.tags": ["proxy", "réseau"],
+                "metadata.url": { "$regex": "^https://a\\.b/" },
+            }
+        );
+    }
+
+    /// Evaluates a query from `to_bson` on stored metadata the way MongoDB does: each
+    /// operator holds on the field or on any element of it, independently of the others.
+    fn mongo_matches(query: &Document, metadata: &Metadata) -> bool {
+        fn any(value: &Bson, test: &dyn Fn(&Bson) -> bool) -> bool {
+            test(value) || matches!(value, Bson::Array(values) if values.iter().any(test))
+        }
+        fn number(value: &Bson) -> Option<f64> {
+            match value {
+                Bson::Int32(n) => Some(f64::from(*n)),
+                Bson::Int64(n) => Some(*n as f64),
+                Bson::Double(n) => Some(*n),
+                _ => None,
+            }
+        }
+        fn compare(a: &Bson, b: &Bson) -> Option<Ordering> {
+            match (a, b) {
+                (Bson::String(a), Bson::String(b)) => Some(a.cmp(b)),
+                (Bson::Boolean(a), Bson::Boolean(b)) => Some(a.cmp(b)),
+                _ => number(a)?.partial_cmp(&number(b)?),
+            }
+        }
+        fn equal(a: &Bson, b: &Bson) -> bool {
+            match (a, b) {
+                (Bson::Array(a), Bson::Array(b)) => {
+                    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| equal(a, b))
+                }
+                _ => compare(a, b) == Some(Ordering::Equal),
+            }
+        }
+        fn operator(op: &str, value: &Bson, arg: &Bson) -> bool {
+            match (op, arg) {
+                ("$eq", _) => equal(value, arg),
+                ("$in", Bson::Array(values)) => values.iter().any(|v| equal(value, v)),
+                ("$gt", _) => compare(value, arg).is_some_and(Ordering::is_gt),
+                ("$gte", _) => compare(value, arg).is_some_and(Ordering::is_ge),
+                ("$lt", _) => compare(value, arg).is_some_and(Ordering::is_lt),
+                ("$lte", _) => compare(value, arg).is_some_and(Ordering::is_le),
+                ("$regex", Bson::String(pattern)) => matches!(
+                    value,
+                    Bson::String(text) if regex::Regex::new(pattern).unwrap().is_match(text)
+                ),
+                _ => panic!("opérateur non pris en charge: {}", op),
+            }
+        }
+
+        let stored = doc! { "metadata": mongodb::bson::to_bson(metadata).unwrap() };
+        query.iter().all(|(path, condition)| {
+            let mut value = Some(&stored);
+            let mut field = None;
+            for key in path.split('.') {
+                field = value.and_then(|d| d.get(key));
+                value = field.and_then(Bson::as_document);
+            }
+            let Some(field) = field else {
+                return false;
+            };
+            match condition {
+                Bson::Document(ops) => ops
+                    .iter()
+                    .all(|(op, arg)| any(field, &|v| operator(op, v, arg))),
+                expected => any(field, &|v| equal(v, expected)),
+            }
+        })
+    }
+
+    #[test]
+    fn memory_and_mongo_agree() {
+        let filters = [
+            r#"{ "extra.versions": { "gt": 1, "lt": 3 } }"#,
+            r#"{ "extra.versions": { "gte": 2, "lte": 2 } }"#,
+            r#"{ "extra.versions": [0, 5] }"#,
+            r#"{ "extra.versions": { "in": [5, 7] } }"#,
+            r#"{ "tags": "réseau" }"#,
+            r#"{ "tags": { "prefix": "pro" } }"#,
+            r#"{ "tags": { "in": ["dns", "réseau"] } }"#,
+            r#"{ "tags": ["proxy", "réseau"] }"#,
+            r#"{ "tags": [] }"#,
+            r#"{ "page": { "gte": 2, "lt": 10 }, "source": "manuel" }"#,
+            r#"{ "date": { "gte": "2024-01-01" }, "url": { "prefix": "https://a.b/" } }"#,
+        ];
+        let samples = [
+            r#"{ "extra": { "versions": [0, 5] } }"#,
+            r#"{ "extra": { "versions": [2.0] } }"#,
+            r#"{ "extra": { "versions": 2 } }"#,
+            r#"{ "tags": ["proxy", "réseau"] }"#,
+            r#"{ "tags": [] }"#,
+            r#"{ "source": "manuel", "page": 4 }"#,
+            r#"{ "date": "2024-06-01", "url": "https://a.b/c" }"#,
+            r#"{ "date": "2023-06-01", "url": "https://axb/c" }"#,
+        ];
+
+        for f in filters {
+            for m in samples {
+                let (query, stored) = (filter(f), metadata(m));
+                assert_eq!(
+                    query.matches(Some(&stored)),
+                    mongo_matches(&query.to_bson(), &stored),
+                    "{} sur {}",
+                    f,
+                    m
+                );
+            }
+        }
+        assert!(filter(r#"{ "extra.versions": { "gt": 1, "lt": 3 } }"#)
+            .matches(Some(&metadata(r#"{ "extra": { "versions": [0, 5] } }"#))));
+        assert!(!filter(r#"{ "tags": [] }"#).matches(Some(&metadata(r#"{ "tags": [] }"#))));
+    }
+}
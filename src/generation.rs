@@ -3,6 +3,38 @@ use futures_util::Stream;
 use futures_util::StreamExt;
 use std::pin::Pin;
 
+/// Where a passage comes from, e.g. ` (Manuel d'installation, Linux > Proxy, page 14)`, so the
+/// model can cite it.
+fn citation(passage: &Passage) -> String {
+    let Some(metadata) = &passage.metadata else {
+        return String::new();
+    };
+
+    let parts: Vec<String> = [
+        metadata
+            .title
+            .clone()
+            .or_else(|| metadata.path.clone())
+            .or_else(|| metadata.source.clone()),
+        metadata.breadcrumb.clone(),
+        metadata.symbol.clone(),
+        metadata.page.map(|page| format!("page {}", page)),
+        metadata
+            .line_start
+            .zip(metadata.line_end)
+            .map(|(start, end)| format!("lines {}-{}", start, end)),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+
+    if parts.is_empty() {
+        String::new()
+    } else {
+        format!(" ({})", parts.join(", "))
+    }
+}
+
 pub async fn generate_answer(
     question: &str,
     context: &[Passage],
@@ -18,7 +50,7 @@ pub async fn generate_answer(
     let context_text = context
         .iter()
         .enumerate()
-        .map(|(i, passage)| format!("Passage {}: {}", i + 1, passage.text))
+        .map(|(i, passage)| format!("Passage {}{}: {}", i + 1, citation(passage), passage.text))
         .collect::<Vec<_>>()
         .join("\n\n");
 
@@ -61,17 +93,16 @@ pub async fn generate_answer(
                 let mut parts = Vec::new();
 
                 for line in text.lines() {
-                    if line.starts_with("data: ") {
-                        let json_str = &line[6..];
+                    if let Some(json_str) = line.strip_prefix("data: ") {
                         if json_str == "[DONE]" {
                             return Some(Ok("[DONE]".to_string()));
                         }
 
-                        if let Ok(event) = serde_json::from_str::<LLMStreamResponse>(json_str) {
-                            if let Some(choice) = event.choices.first() {
-                                let content = &choice.delta.content;
-                                parts.push(content.clone());
-                            }
+                        if let Ok(event) = serde_json::from_str::<LLMStreamResponse>(json_str)
+                            && let Some(choice) = event.choices.first()
+                        {
+                            let content = &choice.delta.content;
+                            parts.push(content.clone());
                         }
                     }
                 }
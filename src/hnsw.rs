@@ -0,0 +1,472 @@
+use crate::utils::compute_hash;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct HnswParams {
+    /// Maximum number of links per node above layer 0 (layer 0 keeps twice as many).
+    pub m: usize,
+    pub ef_construction: usize,
+    pub ef_search: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Node {
+    id: String,
+    vector: Vec<f32>,
+    links: Vec<Vec<u32>>,
+    deleted: bool,
+}
+
+/// Hierarchical navigable small world graph over normalized embeddings.
+///
+/// Removals are tombstones: the node stays in the graph to keep it navigable
+/// and is only skipped in results, so the index should be rebuilt once
+/// `tombstone_ratio` grows large.
+#[derive(Serialize, Deserialize)]
+pub struct HnswIndex {
+    params: HnswParams,
+    nodes: Vec<Node>,
+    ids: HashMap<String, u32>,
+    entry_point: Option<u32>,
+    deleted: usize,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct Candidate {
+    dist: f32,
+    node: u32,
+}
+
+impl Eq for Candidate {}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.dist
+            .total_cmp(&other.dist)
+            .then(self.node.cmp(&other.node))
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+fn normalize(vector: &[f32]) -> Vec<f32> {
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm < f32::EPSILON {
+        vector.to_vec()
+    } else {
+        vector.iter().map(|x| x / norm).collect()
+    }
+}
+
+fn distance(a: &[f32], b: &[f32]) -> f32 {
+    1.0 - a.iter().zip(b).map(|(x, y)| x * y).sum::<f32>()
+}
+
+impl HnswIndex {
+    pub fn new(params: HnswParams) -> Self {
+        Self {
+            params,
+            nodes: Vec::new(),
+            ids: HashMap::new(),
+            entry_point: None,
+            deleted: 0,
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("Lecture de {} impossible", path.display()))?;
+        Ok(bincode::deserialize_from(std::io::BufReader::new(file))?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let tmp = path.with_extension("tmp");
+        let file = std::fs::File::create(&tmp)
+            .with_context(|| format!("Écriture de {} impossible", tmp.display()))?;
+        bincode::serialize_into(std::io::BufWriter::new(file), self)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.ids.contains_key(id)
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = &str> {
+        self.ids.keys().map(String::as_str)
+    }
+
+    pub fn tombstone_ratio(&self) -> f32 {
+        if self.nodes.is_empty() {
+            0.0
+        } else {
+            self.deleted as f32 / self.nodes.len() as f32
+        }
+    }
+
+    /// Level drawn from the id hash so rebuilding the same collection yields the same graph.
+    fn random_level(&self, id: &str) -> usize {
+        let unit = (compute_hash(id) >> 11) as f64 / (1u64 << 53) as f64;
+        let ml = 1.0 / (self.params.m.max(2) as f64).ln();
+        (-(1.0 - unit).ln() * ml).floor() as usize
+    }
+
+    fn max_links(&self, layer: usize) -> usize {
+        if layer == 0 {
+            self.params.m * 2
+        } else {
+            self.params.m
+        }
+    }
+
+    fn dist_to(&self, query: &[f32], node: u32) -> f32 {
+        distance(query, &self.nodes[node as usize].vector)
+    }
+
+    fn top_level(&self) -> usize {
+        self.entry_point
+            .map(|ep| self.nodes[ep as usize].links.len() - 1)
+            .unwrap_or(0)
+    }
+
+    pub fn insert(&mut self, id: &str, vector: &[f32]) {
+        self.remove(id);
+
+        let vector = normalize(vector);
+        let level = self.random_level(id);
+        let node = self.nodes.len() as u32;
+
+        self.nodes.push(Node {
+            id: id.to_string(),
+            vector,
+            links: vec![Vec::new(); level + 1],
+            deleted: false,
+        });
+        self.ids.insert(id.to_string(), node);
+
+        let Some(entry_point) = self.entry_point else {
+            self.entry_point = Some(node);
+            return;
+        };
+
+        let query = self.nodes[node as usize].vector.clone();
+        let top_level = self.top_level();
+        let mut entry = Candidate {
+            dist: self.dist_to(&query, entry_point),
+            node: entry_point,
+        };
+
+        for layer in (level + 1..=top_level).rev() {
+            entry = self.greedy_closest(&query, entry, layer);
+        }
+
+        let mut entries = vec![entry];
+        for layer in (0..=level.min(top_level)).rev() {
+            let found = self.search_layer(&query, &entries, self.params.ef_construction, layer);
+            let neighbours = self.select_neighbours(&found, self.params.m);
+
+            self.nodes[node as usize].links[layer] = neighbours.iter().map(|c| c.node).collect();
+            for neighbour in &neighbours {
+                self.link(neighbour.node, node, layer);
+            }
+
+            entries = found;
+        }
+
+        if level > top_level {
+            self.entry_point = Some(node);
+        }
+    }
+
+    /// Adds `to` to the links of `from`, pruning back to the layer capacity.
+    fn link(&mut self, from: u32, to: u32, layer: usize) {
+        let max_links = self.max_links(layer);
+        let links = &mut self.nodes[from as usize].links[layer];
+        links.push(to);
+        if links.len() <= max_links {
+            return;
+        }
+
+        let base = self.nodes[from as usize].vector.clone();
+        let mut candidates: Vec<Candidate> = self.nodes[from as usize].links[layer]
+            .iter()
+            .map(|&n| Candidate {
+                dist: self.dist_to(&base, n),
+                node: n,
+            })
+            .collect();
+        candidates.sort();
+
+        self.nodes[from as usize].links[layer] = self
+            .select_neighbours(&candidates, max_links)
+            .into_iter()
+            .map(|c| c.node)
+            .collect();
+    }
+
+    /// Neighbour selection heuristic: prefer candidates that are closer to the
+    /// base than to any already selected neighbour, then fill up with the rest.
+    fn select_neighbours(&self, sorted: &[Candidate], max: usize) -> Vec<Candidate> {
+        let mut selected: Vec<Candidate> = Vec::with_capacity(max);
+        let mut pruned = Vec::new();
+
+        for candidate in sorted {
+            if selected.len() >= max {
+                break;
+            }
+            let vector = &self.nodes[candidate.node as usize].vector;
+            let diverse = selected
+                .iter()
+                .all(|s| distance(vector, &self.nodes[s.node as usize].vector) > candidate.dist);
+            if diverse {
+                selected.push(*candidate);
+            } else {
+                pruned.push(*candidate);
+            }
+        }
+
+        for candidate in pruned {
+            if selected.len() >= max {
+                break;
+            }
+            selected.push(candidate);
+        }
+
+        selected
+    }
+
+    fn greedy_closest(&self, query: &[f32], mut current: Candidate, layer: usize) -> Candidate {
+        loop {
+            let mut improved = false;
+            for &n in &self.nodes[current.node as usize].links[layer] {
+                let dist = self.dist_to(query, n);
+                if dist < current.dist {
+                    current = Candidate { dist, node: n };
+                    improved = true;
+                }
+            }
+            if !improved {
+                return current;
+            }
+        }
+    }
+
+    /// Beam search within one layer, returning up to `ef` candidates sorted by distance.
+    fn search_layer(
+        &self,
+        query: &[f32],
+        entries: &[Candidate],
+        ef: usize,
+        layer: usize,
+    ) -> Vec<Candidate> {
+        let mut visited: HashSet<u32> = entries.iter().map(|c| c.node).collect();
+        let mut candidates: BinaryHeap<std::cmp::Reverse<Candidate>> =
+            entries.iter().copied().map(std::cmp::Reverse).collect();
+        let mut found: BinaryHeap<Candidate> = entries.iter().copied().collect();
+
+        while let Some(std::cmp::Reverse(current)) = candidates.pop() {
+            let furthest = found.peek().map(|c| c.dist).unwrap_or(f32::INFINITY);
+            if current.dist > furthest && found.len() >= ef {
+                break;
+            }
+
+            let Some(links) = self.nodes[current.node as usize].links.get(layer) else {
+                continue;
+            };
+
+            for &n in links {
+                if !visited.insert(n) {
+                    continue;
+                }
+                let dist = self.dist_to(query, n);
+                let furthest = found.peek().map(|c| c.dist).unwrap_or(f32::INFINITY);
+                if found.len() < ef || dist < furthest {
+                    let candidate = Candidate { dist, node: n };
+                    candidates.push(std::cmp::Reverse(candidate));
+                    found.push(candidate);
+                    if found.len() > ef {
+                        found.pop();
+                    }
+                }
+            }
+        }
+
+        found.into_sorted_vec()
+    }
+
+    pub fn remove(&mut self, id: &str) -> bool {
+        let Some(node) = self.ids.remove(id) else {
+            return false;
+        };
+        self.nodes[node as usize].deleted = true;
+        self.deleted += 1;
+        true
+    }
+
+    /// Returns up to `k` `(id, cosine similarity)` pairs, best first.
+    pub fn search(&self, query: &[f32], k: usize) -> Vec<(String, f32)> {
+        let Some(entry_point) = self.entry_point else {
+            return Vec::new();
+        };
+
+        let query = normalize(query);
+        let mut entry = Candidate {
+            dist: self.dist_to(&query, entry_point),
+            node: entry_point,
+        };
+        for layer in (1..=self.top_level()).rev() {
+            entry = self.greedy_closest(&query, entry, layer);
+        }
+
+        let ef = self.params.ef_search.max(k);
+        self.search_layer(&query, &[entry], ef, 0)
+            .into_iter()
+            .filter(|c| !self.nodes[c.node as usize].deleted)
+            .take(k)
+            .map(|c| (self.nodes[c.node as usize].id.clone(), 1.0 - c.dist))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PARAMS: HnswParams = HnswParams {
+        m: 8,
+        ef_construction: 64,
+        ef_search: 32,
+    };
+
+    /// Deterministic pseudo-random vectors, so failures can be reproduced.
+    fn vectors(count: usize, dimensions: usize) -> Vec<Vec<f32>> {
+        let mut seed = 42u64;
+        (0..count)
+            .map(|_| {
+                (0..dimensions)
+                    .map(|_| {
+                        seed = seed
+                            .wrapping_mul(6364136223846793005)
+                            .wrapping_add(1442695040888963407);
+                        (seed >> 40) as f32 / (1u64 << 24) as f32 - 0.5
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn exact(vectors: &[Vec<f32>], query: &[f32], k: usize) -> Vec<String> {
+        let query = normalize(query);
+        let mut ranked: Vec<(usize, f32)> = vectors
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (i, distance(&query, &normalize(v))))
+            .collect();
+        ranked.sort_by(|a, b| a.1.total_cmp(&b.1));
+        ranked
+            .into_iter()
+            .take(k)
+            .map(|(i, _)| i.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn finds_inserted_vector_first() {
+        let data = vectors(300, 16);
+        let mut index = HnswIndex::new(PARAMS);
+        for (i, v) in data.iter().enumerate() {
+            index.insert(&i.to_string(), v);
+        }
+
+        let hits = index.search(&data[123], 5);
+        assert_eq!(hits[0].0, "123");
+        assert!((hits[0].1 - 1.0).abs() < 1e-5);
+        assert!(hits.windows(2).all(|w| w[0].1 >= w[1].1));
+    }
+
+    #[test]
+    fn recall_against_exact_search() {
+        let data = vectors(500, 16);
+        let mut index = HnswIndex::new(PARAMS);
+        for (i, v) in data.iter().enumerate() {
+            index.insert(&i.to_string(), v);
+        }
+
+        let queries = vectors(520, 16).split_off(500);
+        let mut found = 0;
+        for query in &queries {
+            let expected = exact(&data, query, 10);
+            let hits: HashSet<String> = index.search(query, 10).into_iter().map(|h| h.0).collect();
+            found += expected.iter().filter(|id| hits.contains(*id)).count();
+        }
+        assert!(found >= 180, "rappel {} / 200", found);
+    }
+
+    #[test]
+    fn removed_ids_are_skipped() {
+        let data = vectors(50, 8);
+        let mut index = HnswIndex::new(PARAMS);
+        for (i, v) in data.iter().enumerate() {
+            index.insert(&i.to_string(), v);
+        }
+
+        assert!(index.remove("7"));
+        assert!(!index.remove("7"));
+        assert!(!index.contains("7"));
+        assert_eq!(index.len(), 49);
+        assert!((index.tombstone_ratio() - 1.0 / 50.0).abs() < 1e-6);
+        assert!(index.search(&data[7], 50).iter().all(|(id, _)| id != "7"));
+    }
+
+    #[test]
+    fn reinserting_replaces_the_vector() {
+        let data = vectors(20, 8);
+        let mut index = HnswIndex::new(PARAMS);
+        for (i, v) in data.iter().enumerate() {
+            index.insert(&i.to_string(), v);
+        }
+
+        index.insert("3", &data[10]);
+        assert_eq!(index.len(), 20);
+        let hits = index.search(&data[10], 2);
+        assert!(hits.iter().any(|(id, _)| id == "3"));
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let data = vectors(100, 8);
+        let mut index = HnswIndex::new(PARAMS);
+        for (i, v) in data.iter().enumerate() {
+            index.insert(&i.to_string(), v);
+        }
+        index.remove("0");
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index").join("hnsw.bin");
+        index.save(&path).unwrap();
+        let loaded = HnswIndex::load(&path).unwrap();
+
+        assert_eq!(loaded.len(), 99);
+        assert_eq!(loaded.search(&data[42], 5), index.search(&data[42], 5));
+    }
+
+    #[test]
+    fn empty_index_returns_nothing() {
+        assert!(HnswIndex::new(PARAMS).search(&[1.0, 0.0], 3).is_empty());
+    }
+}
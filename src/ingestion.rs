@@ -1,43 +1,38 @@
-use crate::types::{Metadata, Passage};
+use crate::chunking::{
+    chunker, count_tokens, join_context, passage_budget, tail_tokens, ChunkingConfig,
+    MAX_CHUNK_TOKENS,
+};
+use crate::code::{self, Language};
+use crate::extract::{Extracted, FileKind};
+use crate::minhash::MinHashIndex;
+use crate::store::{Upserted, VectorStore};
+use crate::types::{
+    Document, DocumentUpdateResponse, JobProgress, Metadata, NearDuplicate, NearDuplicatePolicy,
+    Passage, PassageOutcome, PassageStatus,
+};
 use crate::utils::compute_hash;
-use mongodb::bson::{doc, Bson};
-use mongodb::Client;
-use regex::Regex;
+use crate::AppState;
+use async_trait::async_trait;
+use futures::future::join_all;
+use mongodb::bson::DateTime;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
 use tokenizers::Tokenizer;
 
-fn count_tokens(tokenizer: &Tokenizer, text: &str) -> usize {
-    tokenizer.encode(text, true).unwrap().len()
-}
-
-fn keep_last_tokens(tokenizer: &Tokenizer, text: &str, n: usize) -> String {
-    let encoding = tokenizer.encode(text, true).unwrap();
-    let ids = encoding.get_ids();
-    let start = if ids.len() > n { ids.len() - n } else { 0 };
-    let slice = &ids[start..];
-    tokenizer.decode(&*slice.to_vec(), true).unwrap()
-}
-
-fn split_sections(text: &str) -> Vec<String> {
-    let re = Regex::new(r"(?m)^(\d+\.\s.*)").unwrap();
-    let mut sections = Vec::new();
-    let mut current = String::new();
-
-    for line in text.lines() {
-        if re.is_match(line) {
-            if !current.trim().is_empty() {
-                sections.push(current.trim().to_string());
-                current.clear();
-            }
-        }
-        current.push_str(line);
-        current.push('\n');
+/// Rejects ingestion payloads that are empty or too large.
+pub fn validate_text(text: &str) -> Result<(), &'static str> {
+    if text.len() > 1_000_000 {
+        return Err("Texte trop volumineux");
     }
 
-    if !current.trim().is_empty() {
-        sections.push(current.trim().to_string());
+    if text.trim().is_empty() {
+        return Err("Texte vide");
     }
 
-    sections
+    Ok(())
 }
 
 fn make_passage(text: &str, metadata: &Option<Metadata>) -> Passage {
@@ -50,244 +45,802 @@ fn make_passage(text: &str, metadata: &Option<Metadata>) -> Passage {
     }
 }
 
+/// Trims lines and collapses runs of blank lines into one, so paragraphs stay
+/// separated; indentation inside fenced code blocks is kept.
 fn clean_text(text: &str) -> String {
-    text.replace("\r\n", "\n")
-        .replace('\r', "\n")
-        .lines()
-        .map(|l| l.trim())
-        .filter(|l| !l.is_empty())
-        .collect::<Vec<_>>()
-        .join("\n")
-}
-
-fn split_sentences(text: &str) -> Vec<String> {
-    let re = Regex::new(r"(?m)([^.!?]*[.!?](?:\s|$))").unwrap();
-    let mut sentences = Vec::new();
-    let mut last_index = 0;
-
-    for mat in re.find_iter(text) {
-        let sentence = text[last_index..mat.end()].trim();
-        if !sentence.is_empty() && sentence.len() > 10 {
-            sentences.push(sentence.to_string());
+    let text = text.replace("\r\n", "\n").replace('\r', "\n");
+    let mut lines: Vec<&str> = Vec::new();
+    let mut in_code = false;
+
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_code = !in_code;
+        } else if in_code {
+            lines.push(line.trim_end());
+            continue;
         }
-        last_index = mat.end();
-    }
 
-    if last_index < text.len() {
-        let rest = text[last_index..].trim();
-        if !rest.is_empty() {
-            sentences.push(rest.to_string());
+        if trimmed.is_empty() && lines.last().is_none_or(|l| l.is_empty()) {
+            continue;
         }
+        lines.push(trimmed);
     }
 
-    if sentences.is_empty() && !text.trim().is_empty() {
-        let words: Vec<&str> = text.split_whitespace().collect();
-        let mut current_sentence = String::new();
-        let mut word_count = 0;
+    while lines.last().is_some_and(|l| l.is_empty()) {
+        lines.pop();
+    }
 
-        for word in words {
-            if word_count > 0 {
-                current_sentence.push(' ');
-            }
-            current_sentence.push_str(word);
-            word_count += 1;
+    lines.join("\n")
+}
+
+/// Cleans `text` and cuts it into passages carrying `metadata`, plus the breadcrumb
+/// of each chunk when the chunker records one.
+///
+/// Source code, recognized by the extension of `metadata.path`, is split on its syntax instead.
+pub async fn segment_text(
+    text: &str,
+    metadata: Option<Metadata>,
+    config: ChunkingConfig,
+    state: &AppState,
+) -> Vec<Passage> {
+    let language = metadata
+        .as_ref()
+        .and_then(|m| m.path.as_deref())
+        .and_then(Language::from_path);
+    if let Some(language) = language {
+        return segment_code(text, metadata, language, config, state);
+    }
 
-            if word_count >= 25 {
-                sentences.push(current_sentence.trim().to_string());
-                current_sentence.clear();
-                word_count = 0;
+    let text = clean_text(text);
+    // The path is embedded before each passage, so it takes from their size.
+    let path = metadata.as_ref().and_then(|m| m.path.as_deref());
+    let config = config.shrunk_to(passage_budget(
+        &state.tokenizer,
+        config.max_tokens,
+        &Vec::from_iter(path),
+    ));
+
+    chunker(config, state, &text)
+        .chunk(&text)
+        .await
+        .into_iter()
+        .map(|chunk| {
+            let mut passage = make_passage(&chunk.text, &metadata);
+            if chunk.breadcrumb.is_some() {
+                passage.metadata.get_or_insert_default().breadcrumb = chunk.breadcrumb;
             }
+            passage
+        })
+        .collect()
+}
+
+/// Splits source code along its top-level items, keeping its indentation, and records
+/// the symbol and lines of each passage.
+fn segment_code(
+    source: &str,
+    metadata: Option<Metadata>,
+    language: Language,
+    config: ChunkingConfig,
+    state: &AppState,
+) -> Vec<Passage> {
+    let source = source.replace("\r\n", "\n");
+
+    let path = metadata.as_ref().and_then(|m| m.path.as_deref());
+    code::chunk(&source, language, path, &state.tokenizer, config.max_tokens)
+        .into_iter()
+        .map(|chunk| {
+            let mut passage = make_passage(&chunk.text, &metadata);
+            let metadata = passage.metadata.get_or_insert_default();
+            metadata.symbol = chunk.symbol;
+            metadata.line_start = Some(chunk.start_line);
+            metadata.line_end = Some(chunk.end_line);
+            passage
+        })
+        .collect()
+}
+
+/// What a passage is embedded under: its heading breadcrumb, file path and symbol,
+/// those it has.
+fn embedding_context(passage: &Passage) -> Vec<&str> {
+    passage
+        .metadata
+        .as_ref()
+        .map(|m| {
+            [
+                m.breadcrumb.as_deref(),
+                m.path.as_deref(),
+                m.symbol.as_deref(),
+            ]
+            .into_iter()
+            .flatten()
+            .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn with_context<'p>(context: &str, passage: &'p Passage) -> Cow<'p, str> {
+    if context.is_empty() {
+        Cow::Borrowed(&passage.text)
+    } else {
+        Cow::Owned(format!("{}\n{}", context, passage.text))
+    }
+}
+
+/// Text given to the embedding model: the passage under its context, whose start is
+/// dropped if both would not fit within the model.
+pub fn embedding_input<'p>(passage: &'p Passage, tokenizer: &Tokenizer) -> Cow<'p, str> {
+    let context = join_context(&embedding_context(passage));
+    let room = MAX_CHUNK_TOKENS.saturating_sub(count_tokens(tokenizer, &passage.text));
+
+    with_context(tail_tokens(tokenizer, &context, room).trim_start(), passage)
+}
+
+/// Deduplication key of a passage, covering its whole context.
+fn passage_hash(passage: &Passage) -> i64 {
+    let context = join_context(&embedding_context(passage));
+    compute_hash(&with_context(&context, passage)) as i64
+}
+
+pub async fn store_passage(
+    mut passage: Passage,
+    store: &dyn VectorStore,
+) -> Result<Upserted, Box<dyn std::error::Error>> {
+    passage.hash = Some(passage_hash(&passage));
+
+    Ok(store.upsert_by_hash(passage).await?)
+}
+
+/// Errors and near-duplicates kept per ingestion; later ones are only counted.
+const MAX_PROGRESS_ENTRIES: usize = 100;
+
+/// Passage counters and outcomes of an ingestion under way, readable while it runs.
+#[derive(Default)]
+pub struct Progress {
+    total: AtomicUsize,
+    embedded: AtomicUsize,
+    stored: AtomicUsize,
+    failed: AtomicUsize,
+    skipped: AtomicUsize,
+    errors: Mutex<Vec<String>>,
+    near_duplicates: Mutex<Vec<NearDuplicate>>,
+    outcomes: Mutex<Vec<PassageOutcome>>,
+}
+
+impl Progress {
+    fn fail(&self, error: String) {
+        self.failed.fetch_add(1, Ordering::Relaxed);
+        let mut errors = self.errors.lock().unwrap();
+        if errors.len() < MAX_PROGRESS_ENTRIES {
+            errors.push(error);
+        }
+    }
+
+    fn record(&self, outcome: PassageOutcome) {
+        self.outcomes.lock().unwrap().push(outcome);
+    }
+
+    fn near_duplicate(&self, found: NearDuplicate) {
+        let mut near_duplicates = self.near_duplicates.lock().unwrap();
+        if near_duplicates.len() < MAX_PROGRESS_ENTRIES {
+            near_duplicates.push(found);
         }
+    }
 
-        if !current_sentence.trim().is_empty() {
-            sentences.push(current_sentence.trim().to_string());
+    pub fn snapshot(&self) -> JobProgress {
+        JobProgress {
+            total: self.total.load(Ordering::Relaxed),
+            embedded: self.embedded.load(Ordering::Relaxed),
+            stored: self.stored.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+            skipped: self.skipped.load(Ordering::Relaxed),
         }
     }
 
-    sentences
+    pub fn errors(&self) -> Vec<String> {
+        self.errors.lock().unwrap().clone()
+    }
+
+    /// Near-duplicates found so far, in passage order.
+    pub fn near_duplicates(&self) -> Vec<NearDuplicate> {
+        let mut found = self.near_duplicates.lock().unwrap().clone();
+        found.sort_by_key(|n| n.index);
+        found
+    }
+
+    /// Outcome of each passage handled so far, in passage order.
+    pub fn outcomes(&self) -> Vec<PassageOutcome> {
+        let mut outcomes = self.outcomes.lock().unwrap().clone();
+        outcomes.sort_by_key(|o| o.index);
+        outcomes
+    }
 }
 
-fn split_large_text(
-    text: &str,
-    tokenizer: &Tokenizer,
-    max_tokens: usize,
-    overlap_tokens: usize,
-) -> Vec<String> {
-    let words: Vec<&str> = text.split_whitespace().collect();
-    let mut chunks = Vec::new();
-    let mut start_idx = 0;
-
-    while start_idx < words.len() {
-        let mut end_idx = start_idx;
-        let mut current_chunk = String::new();
-
-        while end_idx < words.len() {
-            let test_text = if current_chunk.is_empty() {
-                words[end_idx].to_string()
-            } else {
-                format!("{} {}", current_chunk, words[end_idx])
-            };
-
-            if count_tokens(tokenizer, &test_text) > max_tokens && !current_chunk.is_empty() {
-                break;
-            }
+/// Candidates fetched per near-duplicate lookup, on top of the passages being replaced.
+const NEAR_DUPLICATE_CANDIDATES: usize = 8;
+
+/// Closest stored passage nearly identical to `passage`, other than an exact
+/// duplicate, which the hash already takes care of, or one of `replaced`.
+async fn find_near_duplicate(
+    store: &dyn VectorStore,
+    passage: &Passage,
+    threshold: f32,
+    replaced: &[String],
+) -> Option<(Passage, f32)> {
+    let hash = passage_hash(passage);
+    let candidates = match store
+        .near_duplicates(
+            &passage.text,
+            threshold,
+            NEAR_DUPLICATE_CANDIDATES + replaced.len(),
+        )
+        .await
+    {
+        Ok(candidates) => candidates,
+        Err(e) => {
+            eprintln!("Impossible de rechercher les quasi-doublons: {}", e);
+            return None;
+        }
+    };
+
+    candidates.into_iter().find(|(stored, _)| {
+        stored.hash != Some(hash) && stored.id.is_some_and(|id| !replaced.contains(&id.to_hex()))
+    })
+}
+
+/// Adds the metadata of `passage` to the stored near-duplicate it is merged into.
+async fn merge_into(
+    store: &dyn VectorStore,
+    stored: Passage,
+    passage: &Passage,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let id = stored.id.map(|id| id.to_hex()).unwrap_or_default();
+    let current = stored.metadata.unwrap_or_default();
+    let mut metadata = current.clone();
+    if let Some(new) = &passage.metadata {
+        metadata.merge(new);
+    }
+    if metadata != current {
+        store.set_metadata(&id, Some(metadata)).await?;
+    }
+    Ok(id)
+}
 
-            current_chunk = test_text;
-            end_idx += 1;
+/// Stores one passage, unless it is a near-duplicate handled otherwise, returning
+/// what became of it and the id it is stored under.
+async fn ingest_passage(
+    state: &AppState,
+    mut p: Passage,
+    index: usize,
+    replaced: &[String],
+    progress: &Progress,
+) -> Result<(PassageStatus, Option<String>), String> {
+    let store = state.store.as_ref();
+
+    if let Some(config) = state.config.near_duplicates
+        && let Some((stored, similarity)) =
+            find_near_duplicate(store, &p, config.threshold, replaced).await
+    {
+        progress.near_duplicate(NearDuplicate {
+            index,
+            duplicate_of: stored.id.map(|id| id.to_hex()).unwrap_or_default(),
+            similarity,
+            action: config.policy,
+        });
+
+        match config.policy {
+            NearDuplicatePolicy::Keep => {}
+            NearDuplicatePolicy::Skip => {
+                progress.skipped.fetch_add(1, Ordering::Relaxed);
+                return Ok((PassageStatus::Skipped, None));
+            }
+            NearDuplicatePolicy::Merge => {
+                return match merge_into(store, stored, &p).await {
+                    Ok(id) => {
+                        progress.skipped.fetch_add(1, Ordering::Relaxed);
+                        Ok((PassageStatus::Duplicate, Some(id)))
+                    }
+                    Err(e) => {
+                        eprintln!("Impossible de fusionner le passage: {}", e);
+                        Err(format!("Fusion impossible: {}", e))
+                    }
+                };
+            }
         }
+    }
 
-        if !current_chunk.is_empty() {
-            chunks.push(current_chunk);
+    let embedding = match state
+        .embedder
+        .embed(&embedding_input(&p, &state.tokenizer))
+        .await
+    {
+        Ok(emb) => emb,
+        Err(e) => {
+            eprintln!(
+                "Impossible de calculer l'embedding pour le passage {:?}: {}",
+                &p.id, e
+            );
+            return Err(format!("Embedding impossible: {}", e));
         }
+    };
+    progress.embedded.fetch_add(1, Ordering::Relaxed);
+
+    p.embedding = embedding;
 
-        if end_idx < words.len() {
-            let overlap_text =
-                keep_last_tokens(tokenizer, &chunks[chunks.len() - 1], overlap_tokens);
-            let overlap_words: Vec<&str> = overlap_text.split_whitespace().collect();
-            start_idx = end_idx.saturating_sub(overlap_words.len());
-        } else {
-            break;
+    match store_passage(p, store).await {
+        Ok(upserted) => {
+            progress.stored.fetch_add(1, Ordering::Relaxed);
+            Ok(match upserted {
+                Upserted::Inserted(id) => (PassageStatus::Inserted, Some(id)),
+                Upserted::Existing(id) => (PassageStatus::Duplicate, Some(id)),
+            })
+        }
+        Err(e) => {
+            eprintln!("Impossible d'enregistrer le passage: {}", e);
+            Err(format!("Enregistrement impossible: {}", e))
         }
     }
+}
 
-    chunks
+/// Embeds and stores passages concurrently, returning their ids in input order,
+/// with `None` where embedding or storage failed or a near-duplicate was skipped.
+/// The outcome of each passage is recorded in `progress`.
+///
+/// Near-duplicates are looked for among the stored passages except `replaced`, those
+/// of the document being updated, and handled according to the configured policy.
+/// A passage nearly duplicating an earlier one of the same batch is only looked up
+/// once that one is stored, as if the passages were handled one after the other.
+pub async fn embed_and_store(
+    state: &AppState,
+    passages: Vec<Passage>,
+    replaced: &[String],
+    progress: &Progress,
+) -> Vec<Option<String>> {
+    let passages = passages.into_iter().enumerate().collect();
+    embed_and_store_at(state, passages, replaced, progress).await
 }
 
-pub fn segment_text(text: &str, metadata: Option<Metadata>, tokenizer: &Tokenizer) -> Vec<Passage> {
-    let max_tokens = 200;
-    let overlap_tokens = 30;
-    let min_tokens = 20;
+/// `embed_and_store` for passages given with their position in the segmented text.
+async fn embed_and_store_at(
+    state: &AppState,
+    passages: Vec<(usize, Passage)>,
+    replaced: &[String],
+    progress: &Progress,
+) -> Vec<Option<String>> {
+    progress.total.fetch_add(passages.len(), Ordering::Relaxed);
+
+    let rounds = match state.config.near_duplicates {
+        Some(config) => near_duplicate_rounds(&passages, config.threshold),
+        None => vec![0; passages.len()],
+    };
+    let mut ids = vec![None; passages.len()];
+    let mut pending: Vec<(usize, usize, Passage)> = passages
+        .into_iter()
+        .enumerate()
+        .map(|(position, (index, p))| (position, index, p))
+        .collect();
+
+    for round in 0..rounds.iter().max().map_or(0, |last| last + 1) {
+        let (current, later) = pending
+            .into_iter()
+            .partition(|(position, ..)| rounds[*position] == round);
+        pending = later;
+
+        let tasks = current.into_iter().map(|(position, index, p)| async move {
+            (
+                position,
+                ingest_at(state, index, p, replaced, progress).await,
+            )
+        });
+        for (position, id) in join_all(tasks).await {
+            ids[position] = id;
+        }
+    }
+    ids
+}
 
-    let text = clean_text(text);
-    let sections = split_sections(&text);
+/// Round in which each passage is stored: after every earlier passage of the batch
+/// it nearly duplicates, so that it can be compared with them.
+fn near_duplicate_rounds(passages: &[(usize, Passage)], threshold: f32) -> Vec<usize> {
+    let mut earlier = MinHashIndex::new();
+    let mut rounds: Vec<usize> = Vec::with_capacity(passages.len());
+    for (position, (_, passage)) in passages.iter().enumerate() {
+        let round = earlier
+            .search(&passage.text, threshold, usize::MAX)
+            .into_iter()
+            .filter_map(|(other, _)| other.parse::<usize>().ok())
+            .map(|other| rounds[other] + 1)
+            .max()
+            .unwrap_or(0);
+        rounds.push(round);
+        earlier.insert(&position.to_string(), &passage.text);
+    }
+    rounds
+}
 
-    let mut passages = Vec::new();
+/// Stores one passage at `index` in the segmented text, recording its outcome.
+async fn ingest_at(
+    state: &AppState,
+    index: usize,
+    p: Passage,
+    replaced: &[String],
+    progress: &Progress,
+) -> Option<String> {
+    let tokens = count_tokens(&state.tokenizer, &p.text);
+    let outcome = match ingest_passage(state, p, index, replaced, progress).await {
+        Ok((status, id)) => PassageOutcome {
+            index,
+            id,
+            status,
+            tokens,
+            error: None,
+        },
+        Err(e) => {
+            progress.fail(e.clone());
+            PassageOutcome {
+                index,
+                id: None,
+                status: PassageStatus::Failed,
+                tokens,
+                error: Some(e),
+            }
+        }
+    };
 
-    for section in sections {
-        let section_token_count = count_tokens(tokenizer, &section);
+    let id = outcome.id.clone();
+    progress.record(outcome);
+    id
+}
 
-        if section_token_count > max_tokens * 3 {
-            let chunks = split_large_text(&section, tokenizer, max_tokens, overlap_tokens);
-            for chunk in chunks {
-                if count_tokens(tokenizer, &chunk) >= min_tokens {
-                    passages.push(make_passage(&chunk, &metadata));
-                }
-            }
+/// Keeps the first occurrence of each id; identical passages share one stored copy.
+fn unique_ids(ids: impl IntoIterator<Item = String>) -> Vec<String> {
+    let mut seen = HashSet::new();
+    ids.into_iter()
+        .filter(|id| seen.insert(id.clone()))
+        .collect()
+}
+
+/// Segments each page on its own, recording its number in the passage metadata.
+pub async fn segment_pages(
+    pages: &[String],
+    metadata: Option<Metadata>,
+    config: ChunkingConfig,
+    state: &AppState,
+) -> Vec<Passage> {
+    let mut passages = Vec::new();
+    for (i, page) in pages.iter().enumerate() {
+        if page.trim().is_empty() {
             continue;
         }
+        let mut page_metadata = metadata.clone().unwrap_or_default();
+        page_metadata.page = Some(i as u32 + 1);
+        passages.extend(segment_text(page, Some(page_metadata), config, state).await);
+    }
+    passages
+}
 
-        let paragraphs: Vec<&str> = section
-            .split("\n\n")
-            .filter(|p| !p.trim().is_empty())
-            .collect();
+/// Segments, embeds and stores `text`, then records the document grouping its passages.
+pub async fn create_document(
+    state: &AppState,
+    text: &str,
+    metadata: Option<Metadata>,
+    chunking: ChunkingConfig,
+    progress: &Progress,
+) -> Result<Document, Box<dyn std::error::Error>> {
+    let passages = segment_text(text, metadata.clone(), chunking, state).await;
+    store_document(state, text, metadata, chunking, passages, progress).await
+}
 
-        for paragraph in paragraphs {
-            let paragraph_tokens = count_tokens(tokenizer, paragraph);
+/// Stores the text extracted from a file, filling in the metadata the file provides.
+pub async fn create_file_document(
+    state: &AppState,
+    file_name: &str,
+    kind: FileKind,
+    mut extracted: Extracted,
+    metadata: Option<Metadata>,
+    chunking: ChunkingConfig,
+    progress: &Progress,
+) -> Result<Document, Box<dyn std::error::Error>> {
+    let metadata = file_metadata(file_name, kind, &mut extracted, metadata);
+    store_extracted(state, extracted, metadata, chunking, progress).await
+}
 
-            if paragraph_tokens > max_tokens {
-                let chunks = split_large_text(paragraph, tokenizer, max_tokens, overlap_tokens);
-                for chunk in chunks {
-                    if count_tokens(tokenizer, &chunk) >= min_tokens {
-                        passages.push(make_passage(&chunk, &metadata));
-                    }
-                }
-                continue;
-            }
+/// Completes `metadata` with what the file provides: title, `source` from the file
+/// name, and `path` for source code.
+pub fn file_metadata(
+    file_name: &str,
+    kind: FileKind,
+    extracted: &mut Extracted,
+    metadata: Option<Metadata>,
+) -> Metadata {
+    let mut metadata = metadata.unwrap_or_default();
+    if let FileKind::Code(_) = kind {
+        // The path selects syntax-aware splitting and stands in for the title in citations.
+        metadata.path.get_or_insert_with(|| file_name.to_string());
+    } else if metadata.title.is_none() {
+        metadata.title = extracted.title.take().or_else(|| {
+            Path::new(file_name)
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+        });
+    }
+    metadata.source.get_or_insert_with(|| file_name.to_string());
+    metadata
+}
 
-            let sentences = split_sentences(paragraph);
+/// Stores extracted text as a document, page by page for paginated formats.
+pub async fn store_extracted(
+    state: &AppState,
+    extracted: Extracted,
+    metadata: Metadata,
+    chunking: ChunkingConfig,
+    progress: &Progress,
+) -> Result<Document, Box<dyn std::error::Error>> {
+    if extracted.pages.is_empty() {
+        create_document(state, &extracted.text, Some(metadata), chunking, progress).await
+    } else {
+        let passages =
+            segment_pages(&extracted.pages, Some(metadata.clone()), chunking, state).await;
+        store_document(
+            state,
+            &extracted.text,
+            Some(metadata),
+            chunking,
+            passages,
+            progress,
+        )
+        .await
+    }
+}
 
-            let mut buffer = String::new();
-            let mut token_count = 0;
+/// Destination of the documents found by a crawl or a source synchronization.
+#[async_trait(?Send)]
+pub trait DocumentSink {
+    /// Stores the document found under `key`, its URL or path, or returns `None`
+    /// if it is already stored unchanged.
+    async fn store(
+        &mut self,
+        state: &AppState,
+        key: &str,
+        extracted: Extracted,
+        metadata: Metadata,
+    ) -> Result<Option<Document>, Box<dyn std::error::Error>>;
+}
 
-            for sentence in sentences {
-                let sentence_tokens = count_tokens(tokenizer, &sentence);
+/// Stores every document whose text is not stored yet.
+pub struct NewDocuments(pub ChunkingConfig);
+
+#[async_trait(?Send)]
+impl DocumentSink for NewDocuments {
+    async fn store(
+        &mut self,
+        state: &AppState,
+        _key: &str,
+        extracted: Extracted,
+        metadata: Metadata,
+    ) -> Result<Option<Document>, Box<dyn std::error::Error>> {
+        let content_hash = compute_hash(&extracted.text) as i64;
+        if state
+            .store
+            .find_document_by_hash(content_hash)
+            .await?
+            .is_some()
+        {
+            return Ok(None);
+        }
 
-                if sentence_tokens > max_tokens {
-                    if !buffer.is_empty() && token_count >= min_tokens {
-                        passages.push(make_passage(&buffer, &metadata));
-                        buffer.clear();
-                        token_count = 0;
-                    }
+        Ok(Some(
+            store_extracted(state, extracted, metadata, self.0, &Progress::default()).await?,
+        ))
+    }
+}
 
-                    let sentence_chunks =
-                        split_large_text(&sentence, tokenizer, max_tokens, overlap_tokens);
-                    for chunk in sentence_chunks {
-                        if count_tokens(tokenizer, &chunk) >= min_tokens {
-                            passages.push(make_passage(&chunk, &metadata));
-                        }
-                    }
-                    continue;
-                }
+/// Embeds and stores passages segmented from `text` with `chunking`, then records the document.
+pub async fn store_document(
+    state: &AppState,
+    text: &str,
+    metadata: Option<Metadata>,
+    chunking: ChunkingConfig,
+    passages: Vec<Passage>,
+    progress: &Progress,
+) -> Result<Document, Box<dyn std::error::Error>> {
+    let passage_ids = unique_ids(
+        embed_and_store(state, passages, &[], progress)
+            .await
+            .into_iter()
+            .flatten(),
+    );
+
+    let now = DateTime::now();
+    let mut document = Document {
+        id: None,
+        metadata,
+        content_hash: compute_hash(text) as i64,
+        passage_ids,
+        chunking: Some(chunking),
+        source_id: None,
+        created_at: now,
+        updated_at: now,
+    };
+    let id = state.store.insert_document(document.clone()).await?;
+    document.id = Some(id.parse()?);
 
-                if token_count + sentence_tokens > max_tokens {
-                    if token_count >= min_tokens {
-                        passages.push(make_passage(&buffer, &metadata));
-                    }
+    Ok(document)
+}
 
-                    let overlap_text = keep_last_tokens(tokenizer, &buffer, overlap_tokens);
-                    buffer = if overlap_text.is_empty() {
-                        sentence.clone()
-                    } else {
-                        format!("{} {}", overlap_text, sentence)
-                    };
-                    token_count = count_tokens(tokenizer, &buffer);
-                } else {
-                    if !buffer.is_empty() {
-                        buffer.push(' ');
-                    }
-                    buffer.push_str(&sentence);
-                    token_count += sentence_tokens;
-                }
-            }
+/// Re-segments the new text of a document and only embeds the passages that changed.
+///
+/// Passages whose text and breadcrumb are unchanged are kept, with their metadata
+/// updated if it differs, except for passages another document also references, which
+/// keep theirs; passages no longer produced are deleted unless another document still
+/// references them. Returns `None` if the document does not exist.
+///
+/// Without `chunking`, the text is cut the way the document was last segmented.
+pub async fn update_document(
+    state: &AppState,
+    id: &str,
+    text: &str,
+    metadata: Option<Metadata>,
+    chunking: Option<ChunkingConfig>,
+) -> Result<Option<DocumentUpdateResponse>, Box<dyn std::error::Error>> {
+    let store = state.store.as_ref();
+    let Some(mut document) = store.get_document(id).await? else {
+        return Ok(None);
+    };
 
-            if !buffer.is_empty() && token_count >= min_tokens {
-                passages.push(make_passage(&buffer, &metadata));
-            }
+    let chunking = chunking
+        .or(document.chunking)
+        .unwrap_or(state.config.chunking);
+    let content_hash = compute_hash(text) as i64;
+    if content_hash == document.content_hash
+        && metadata == document.metadata
+        && Some(chunking) == document.chunking
+    {
+        return Ok(Some(DocumentUpdateResponse {
+            document,
+            added_passage_ids: Vec::new(),
+            removed_passage_ids: Vec::new(),
+            near_duplicates: Vec::new(),
+            passages: Vec::new(),
+        }));
+    }
+
+    let existing: HashMap<i64, Passage> = store
+        .get_many(&document.passage_ids)
+        .await?
+        .into_iter()
+        .filter_map(|p| Some((p.hash?, p)))
+        .collect();
+
+    let progress = Progress::default();
+    let mut fresh = Vec::new();
+    let mut slots = Vec::new();
+    let passages = segment_text(text, metadata.clone(), chunking, state).await;
+    for (index, passage) in passages.into_iter().enumerate() {
+        let Some(stored) = existing.get(&passage_hash(&passage)) else {
+            fresh.push((index, passage));
+            slots.push(None);
+            continue;
+        };
+
+        let passage_id = stored.id.map(|id| id.to_hex()).unwrap_or_default();
+        // Shared passages keep the metadata they have, which is also another document's.
+        if stored.metadata != passage.metadata && !store.passage_shared(&passage_id, id).await? {
+            store.set_metadata(&passage_id, passage.metadata).await?;
         }
+        progress.record(PassageOutcome {
+            index,
+            id: Some(passage_id.clone()),
+            status: PassageStatus::Unchanged,
+            tokens: count_tokens(&state.tokenizer, &passage.text),
+            error: None,
+        });
+        slots.push(Some(passage_id));
     }
 
-    if passages.len() < 3 && !text.trim().is_empty() {
-        let aggressive_chunks =
-            split_large_text(&text, tokenizer, max_tokens / 2, overlap_tokens / 2);
-        passages.clear();
-        for chunk in aggressive_chunks {
-            if count_tokens(tokenizer, &chunk) >= min_tokens {
-                passages.push(make_passage(&chunk, &metadata));
-            }
+    // A passage being edited must not be mistaken for a near-duplicate of its old version.
+    let mut added = embed_and_store_at(state, fresh, &document.passage_ids, &progress)
+        .await
+        .into_iter();
+    let passage_ids = unique_ids(
+        slots
+            .into_iter()
+            .filter_map(|slot| slot.or_else(|| added.next().flatten())),
+    );
+
+    let mut removed_passage_ids = Vec::new();
+    for old in &document.passage_ids {
+        if passage_ids.contains(old) || store.passage_shared(old, id).await? {
+            continue;
         }
+        store.delete(old).await?;
+        removed_passage_ids.push(old.clone());
     }
 
-    passages
+    let added_passage_ids = passage_ids
+        .iter()
+        .filter(|p| !document.passage_ids.contains(p))
+        .cloned()
+        .collect();
+
+    document.metadata = metadata;
+    document.content_hash = content_hash;
+    document.passage_ids = passage_ids;
+    document.chunking = Some(chunking);
+    document.updated_at = DateTime::now();
+    store.replace_document(&document).await?;
+
+    Ok(Some(DocumentUpdateResponse {
+        document,
+        added_passage_ids,
+        removed_passage_ids,
+        near_duplicates: progress.near_duplicates(),
+        passages: progress.outcomes(),
+    }))
 }
 
-pub async fn store_passage(
-    mut passage: Passage,
-    client: &Client,
-    db_name: &str,
-    collection_name: &str,
-) -> Result<String, Box<dyn std::error::Error>> {
-    let hash = compute_hash(&passage.text);
+/// Deletes a passage and removes it from the documents listing it.
+pub async fn delete_passage(
+    store: &dyn VectorStore,
+    id: &str,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    // Unlinked first, so that a failed deletion can simply be retried.
+    store.unlink_passage(id).await?;
+    Ok(store.delete(id).await?)
+}
 
-    let docs_collection = client
-        .database(db_name)
-        .collection::<Passage>(collection_name);
+/// Deletes a document along with the passages no other document references.
+pub async fn delete_document(
+    store: &dyn VectorStore,
+    id: &str,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let Some(document) = store.get_document(id).await? else {
+        return Ok(false);
+    };
 
-    if let Some(existing_passage) = docs_collection
-        .find_one(doc! { "hash": hash as i64 })
-        .await?
-    {
-        return Ok(existing_passage.id.unwrap().to_string());
+    for passage_id in &document.passage_ids {
+        if !store.passage_shared(passage_id, id).await? {
+            store.delete(passage_id).await?;
+        }
+    }
+
+    Ok(store.delete_document(id).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn batch(texts: &[&str]) -> Vec<(usize, Passage)> {
+        texts
+            .iter()
+            .map(|text| make_passage(text, &None))
+            .enumerate()
+            .collect()
     }
 
-    passage.hash = Some(hash as i64);
+    #[test]
+    fn near_duplicates_in_a_batch_come_after_their_original() {
+        let original =
+            "le serveur renvoie une erreur lorsque la configuration du proxy est absente";
+        let copy = "Le serveur renvoie une erreur lorsque la configuration du proxy est absente !";
+        let other =
+            "les journaux indiquent un délai dépassé pendant la synchronisation des documents";
 
-    let insert_result = docs_collection.insert_one(&passage).await?;
+        let rounds = near_duplicate_rounds(&batch(&[original, other, copy, copy]), 0.8);
 
-    let id_str = match insert_result.inserted_id {
-        Bson::ObjectId(oid) => oid.to_string(),
-        Bson::String(s) => s,
-        other => return Err(format!("Unexpected inserted_id type: {:?}", other).into()),
-    };
+        assert_eq!(rounds, [0, 0, 1, 2]);
+    }
+
+    #[test]
+    fn distinct_passages_are_stored_together() {
+        let rounds = near_duplicate_rounds(&batch(&["un deux trois", "quatre cinq six", ""]), 0.8);
+        assert_eq!(rounds, [0, 0, 0]);
+    }
 
-    Ok(id_str)
+    #[test]
+    fn clean_text_collapses_blank_lines_outside_code() {
+        let text = "  Titre  \r\n\r\n\r\n Texte\n```\n    code\n\n\n```\n\n";
+        assert_eq!(clean_text(text), "Titre\n\nTexte\n```\n    code\n\n\n```");
+    }
 }
@@ -0,0 +1,163 @@
+use crate::chunking::ChunkingConfig;
+use crate::ingestion::{create_document, Progress};
+use crate::types::{Callback, Job, JobProgress, JobStatus, Metadata};
+use crate::webhook;
+use crate::AppState;
+use actix_web::web;
+use anyhow::{anyhow, Result};
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use futures::StreamExt;
+use mongodb::bson::DateTime;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Queue of background ingestions, processed one at a time by a single worker.
+pub struct JobQueue {
+    sender: UnboundedSender<String>,
+    /// Job being processed and its live counters, which are only persisted at the end.
+    running: Mutex<Option<(String, Arc<Progress>)>>,
+    /// Callback secrets by job id, kept out of the store.
+    secrets: Mutex<HashMap<String, String>>,
+}
+
+/// Receiving end of the queue, handed to the worker.
+pub struct JobReceiver(UnboundedReceiver<String>);
+
+impl JobQueue {
+    pub fn new() -> (Self, JobReceiver) {
+        let (sender, receiver) = unbounded();
+        let queue = Self {
+            sender,
+            running: Mutex::new(None),
+            secrets: Mutex::new(HashMap::new()),
+        };
+        (queue, JobReceiver(receiver))
+    }
+
+    /// Records a job for `text` and queues it, returning its id. The `secret` signing
+    /// its callback is only kept in memory, and lost if the server restarts.
+    pub async fn submit(
+        &self,
+        state: &AppState,
+        text: String,
+        metadata: Option<Metadata>,
+        chunking: ChunkingConfig,
+        callback: Option<Callback>,
+        secret: Option<String>,
+    ) -> Result<String> {
+        let now = DateTime::now();
+        let job = Job {
+            id: None,
+            status: JobStatus::Queued,
+            text,
+            metadata,
+            chunking,
+            progress: JobProgress::default(),
+            errors: Vec::new(),
+            document_id: None,
+            passage_ids: Vec::new(),
+            near_duplicates: Vec::new(),
+            passages: Vec::new(),
+            callback,
+            created_at: now,
+            updated_at: now,
+        };
+        let id = state.store.insert_job(job).await?;
+        if let Some(secret) = secret {
+            self.secrets.lock().unwrap().insert(id.clone(), secret);
+        }
+        self.sender
+            .unbounded_send(id.clone())
+            .map_err(|_| anyhow!("File des tâches fermée"))?;
+        Ok(id)
+    }
+
+    /// Counters of the job if it is the one being processed.
+    pub fn progress(&self, id: &str) -> Option<(JobProgress, Vec<String>)> {
+        let running = self.running.lock().unwrap();
+        running
+            .as_ref()
+            .filter(|(running_id, _)| running_id == id)
+            .map(|(_, progress)| (progress.snapshot(), progress.errors()))
+    }
+}
+
+/// Queues the jobs left unfinished by a previous run, then processes jobs as they come.
+pub async fn run_worker(state: web::Data<AppState>, receiver: JobReceiver) {
+    match state.store.pending_jobs().await {
+        Ok(jobs) => {
+            if !jobs.is_empty() {
+                println!("Reprise de {} tâche(s) d'ingestion", jobs.len());
+            }
+            for job in jobs {
+                if let Some(id) = job.id {
+                    let _ = state.jobs.sender.unbounded_send(id.to_hex());
+                }
+            }
+        }
+        Err(e) => eprintln!("Impossible de lister les tâches en attente: {}", e),
+    }
+
+    let JobReceiver(mut receiver) = receiver;
+    while let Some(id) = receiver.next().await {
+        if let Err(e) = process(&state, &id).await {
+            eprintln!("Erreur lors du traitement de la tâche {}: {}", id, e);
+        }
+    }
+}
+
+async fn process(state: &web::Data<AppState>, id: &str) -> Result<()> {
+    let store = state.store.as_ref();
+    let Some(mut job) = store.get_job(id).await? else {
+        return Ok(());
+    };
+    if job.status.is_finished() {
+        return Ok(());
+    }
+
+    // A job interrupted by a restart is run again from the start; passages it had
+    // already stored are deduplicated on their hash.
+    job.status = JobStatus::Running;
+    job.updated_at = DateTime::now();
+    store.replace_job(&job).await?;
+
+    let progress = Arc::new(Progress::default());
+    *state.jobs.running.lock().unwrap() = Some((id.to_string(), progress.clone()));
+    let result = create_document(
+        state,
+        &job.text,
+        job.metadata.clone(),
+        job.chunking,
+        &progress,
+    )
+    .await
+    .map_err(|e| e.to_string());
+    *state.jobs.running.lock().unwrap() = None;
+
+    job.progress = progress.snapshot();
+    job.errors = progress.errors();
+    job.near_duplicates = progress.near_duplicates();
+    job.passages = progress.outcomes();
+    match result {
+        Ok(document) => {
+            job.status = JobStatus::Completed;
+            job.document_id = document.id.map(|id| id.to_hex());
+            job.passage_ids = document.passage_ids;
+        }
+        Err(e) => {
+            job.status = JobStatus::Failed;
+            job.errors.push(e);
+        }
+    }
+    job.text.clear();
+    job.updated_at = DateTime::now();
+    store.replace_job(&job).await?;
+
+    let secret = state.jobs.secrets.lock().unwrap().remove(id);
+    if job.callback.is_some() {
+        // Delivered on the side, so that retries do not hold up the next job.
+        actix_web::rt::spawn(webhook::notify(state.clone(), job, secret));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+
+const K1: f32 = 1.2;
+const B: f32 = 0.75;
+
+/// Splits text into lowercase terms for BM25.
+///
+/// Alphanumeric runs joined by `-`, `_`, `.` or `/` are kept as one compound
+/// term in addition to their parts, so `ERR-1042` matches both `ERR-1042`
+/// and `1042`.
+pub fn analyze(text: &str) -> Vec<String> {
+    let mut terms = Vec::new();
+    let mut compound = String::new();
+    let mut parts = 0;
+    let mut part = String::new();
+
+    let flush_part = |part: &mut String, terms: &mut Vec<String>, parts: &mut usize| {
+        if !part.is_empty() {
+            terms.push(std::mem::take(part));
+            *parts += 1;
+        }
+    };
+
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_alphanumeric() {
+            for lower in c.to_lowercase() {
+                part.push(lower);
+                compound.push(lower);
+            }
+            continue;
+        }
+
+        let joins = matches!(c, '-' | '_' | '.' | '/')
+            && !part.is_empty()
+            && chars.peek().is_some_and(|n| n.is_alphanumeric());
+        flush_part(&mut part, &mut terms, &mut parts);
+
+        if joins {
+            compound.push(c);
+        } else {
+            if parts > 1 {
+                terms.push(std::mem::take(&mut compound));
+            }
+            compound.clear();
+            parts = 0;
+        }
+    }
+
+    flush_part(&mut part, &mut terms, &mut parts);
+    if parts > 1 {
+        terms.push(compound);
+    }
+
+    terms
+}
+
+/// In-memory BM25 inverted index keyed by passage id.
+#[derive(Default)]
+pub struct Bm25Index {
+    postings: HashMap<String, HashMap<u32, u32>>,
+    doc_ids: Vec<Option<String>>,
+    doc_lens: Vec<u32>,
+    doc_terms: Vec<Vec<String>>,
+    ids: HashMap<String, u32>,
+    total_len: u64,
+}
+
+impl Bm25Index {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn insert(&mut self, id: &str, text: &str) {
+        self.remove(id);
+
+        let terms = analyze(text);
+        let doc = self.doc_ids.len() as u32;
+
+        let mut frequencies: HashMap<String, u32> = HashMap::new();
+        for term in &terms {
+            *frequencies.entry(term.clone()).or_default() += 1;
+        }
+
+        let mut unique = Vec::with_capacity(frequencies.len());
+        for (term, tf) in frequencies {
+            self.postings
+                .entry(term.clone())
+                .or_default()
+                .insert(doc, tf);
+            unique.push(term);
+        }
+
+        self.doc_ids.push(Some(id.to_string()));
+        self.doc_lens.push(terms.len() as u32);
+        self.doc_terms.push(unique);
+        self.ids.insert(id.to_string(), doc);
+        self.total_len += terms.len() as u64;
+    }
+
+    pub fn remove(&mut self, id: &str) -> bool {
+        let Some(doc) = self.ids.remove(id) else {
+            return false;
+        };
+
+        for term in std::mem::take(&mut self.doc_terms[doc as usize]) {
+            if let Some(posting) = self.postings.get_mut(&term) {
+                posting.remove(&doc);
+                if posting.is_empty() {
+                    self.postings.remove(&term);
+                }
+            }
+        }
+
+        self.total_len -= self.doc_lens[doc as usize] as u64;
+        self.doc_ids[doc as usize] = None;
+        true
+    }
+
+    /// Returns up to `k` `(id, BM25 score)` pairs, best first.
+    pub fn search(&self, query: &str, k: usize) -> Vec<(String, f32)> {
+        if self.ids.is_empty() {
+            return Vec::new();
+        }
+
+        let n = self.ids.len() as f32;
+        let avg_len = self.total_len as f32 / n;
+        let mut scores: HashMap<u32, f32> = HashMap::new();
+
+        let mut terms = analyze(query);
+        terms.sort();
+        terms.dedup();
+
+        for term in terms {
+            let Some(posting) = self.postings.get(&term) else {
+                continue;
+            };
+            let df = posting.len() as f32;
+            let idf = ((n - df + 0.5) / (df + 0.5) + 1.0).ln();
+
+            for (&doc, &tf) in posting {
+                let tf = tf as f32;
+                let len = self.doc_lens[doc as usize] as f32;
+                let norm = K1 * (1.0 - B + B * len / avg_len.max(1.0));
+                *scores.entry(doc).or_default() += idf * tf * (K1 + 1.0) / (tf + norm);
+            }
+        }
+
+        let mut ranked: Vec<(u32, f32)> = scores.into_iter().collect();
+        ranked.sort_unstable_by(|a, b| b.1.total_cmp(&a.1));
+
+        ranked
+            .into_iter()
+            .take(k)
+            .filter_map(|(doc, score)| self.doc_ids[doc as usize].clone().map(|id| (id, score)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn analyze_lowercases_and_keeps_compounds() {
+        assert_eq!(
+            analyze("Erreur ERR-1042 sur v2.3"),
+            ["erreur", "err", "1042", "err-1042", "sur", "v2", "3", "v2.3"]
+        );
+    }
+
+    #[test]
+    fn analyze_splits_on_trailing_separators() {
+        assert_eq!(analyze("fin. Début- suite"), ["fin", "début", "suite"]);
+        assert!(analyze(" -- ").is_empty());
+    }
+
+    #[test]
+    fn ranks_rare_terms_higher() {
+        let mut index = Bm25Index::new();
+        index.insert("a", "le proxy renvoie une erreur");
+        index.insert("b", "le serveur démarre");
+        index.insert("c", "le proxy et le serveur");
+
+        let hits = index.search("erreur proxy", 3);
+        assert_eq!(hits[0].0, "a");
+        assert_eq!(hits.len(), 2);
+        assert!(hits[0].1 > hits[1].1);
+    }
+
+    #[test]
+    fn matches_codes_exactly() {
+        let mut index = Bm25Index::new();
+        index.insert("a", "Code ERR-1042: délai dépassé");
+        index.insert("b", "Code ERR-2001: accès refusé");
+
+        assert_eq!(index.search("ERR-1042", 1)[0].0, "a");
+        assert_eq!(index.search("2001", 1)[0].0, "b");
+    }
+
+    #[test]
+    fn remove_and_reinsert() {
+        let mut index = Bm25Index::new();
+        index.insert("a", "proxy");
+        index.insert("b", "serveur");
+
+        assert!(index.remove("a"));
+        assert!(!index.remove("a"));
+        assert_eq!(index.len(), 1);
+        assert!(index.search("proxy", 5).is_empty());
+
+        index.insert("b", "proxy");
+        assert_eq!(index.len(), 1);
+        assert_eq!(index.search("proxy", 5)[0].0, "b");
+        assert!(index.search("serveur", 5).is_empty());
+    }
+}
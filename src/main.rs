@@ -2,35 +2,64 @@ use actix_cors::Cors;
 use actix_web::{web, App, HttpServer};
 use anyhow::Result;
 use candle_core::Device;
-use candle_transformers::models::bert::BertModel;
-use mongodb::bson::doc;
-use mongodb::options::Compressor;
-use mongodb::{options::ClientOptions, Client, IndexModel};
+use clap::Parser;
+use std::sync::Arc;
 use tokenizers::Tokenizer;
 
 mod api;
+mod bulk;
+mod chunking;
+mod cli;
+mod code;
 mod config;
+mod crawl;
+mod embedding;
+mod extract;
+mod filter;
 mod generation;
+mod hnsw;
 mod ingestion;
+mod jobs;
+mod lexical;
+mod markdown;
+mod minhash;
+mod rerank;
 mod retrieval;
+mod sources;
+mod store;
+#[cfg(test)]
+mod testing;
 mod types;
 mod utils;
+mod webhook;
 
+use crate::cli::{Cli, Command};
 use crate::config::Config;
-use crate::types::Passage;
+use crate::embedding::{Embedder, EmbeddingService};
+use crate::jobs::{JobQueue, JobReceiver};
+use crate::rerank::Reranker;
+use crate::store::VectorStore;
 use crate::utils::load_bert_model_and_tokenizer;
-use api::{ask, ingest};
+use api::{
+    ask, create_source, delete_document, delete_passage, delete_source, get_document, get_job,
+    get_passage, get_source, ingest, ingest_file, ingest_url, list_passages, list_sources,
+    sync_source, update_document, update_passage_metadata,
+};
+
+const STORE_FLUSH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
 
 pub struct AppState {
-    pub model: BertModel,
+    pub embedder: EmbeddingService,
     pub tokenizer: Tokenizer,
-    pub device: Device,
-    pub db_client: Client,
+    pub store: Box<dyn VectorStore>,
+    pub reranker: Option<Arc<Reranker>>,
+    pub jobs: JobQueue,
     pub config: Config,
 }
 
 #[actix_web::main]
 async fn main() -> Result<()> {
+    let cli = Cli::parse();
     dotenv::dotenv().ok();
 
     let config = match Config::from_env() {
@@ -48,50 +77,105 @@ async fn main() -> Result<()> {
 
     let device = Device::Cpu;
     let (model, tokenizer) = load_bert_model_and_tokenizer(&device)?;
+    let embedder = EmbeddingService::start(
+        Embedder::new(model, tokenizer.clone(), device.clone()),
+        config.embedding,
+    )?;
+
+    let reranker = match &config.rerank {
+        Some(rerank) => Some(Arc::new(Reranker::load(
+            &rerank.model,
+            rerank.candidates,
+            &device,
+        )?)),
+        None => None,
+    };
+
+    let store = store::from_config(&config).await?;
 
-    let mut client_opts = ClientOptions::parse(&config.cosmos_uri).await?;
-    client_opts.compressors = Some(vec![Compressor::Zstd { level: Some(1) }]);
-    client_opts.max_pool_size = Some(128);
-    client_opts.min_pool_size = Some(16);
-    client_opts.server_selection_timeout = Some(std::time::Duration::from_secs(2));
-    client_opts.app_name = Some("rag-api".into());
-
-    let db_client = Client::with_options(client_opts)?;
-
-    let coll = db_client
-        .database(config.database_name.as_str())
-        .collection::<Passage>(config.collection_name.as_str());
-
-    let index = IndexModel::builder()
-        .keys(doc! { "hash": 1 })
-        .options(Some(
-            mongodb::options::IndexOptions::builder()
-                .unique(true)
-                .build(),
-        ))
-        .build();
-    coll.create_index(index).await?;
+    let (jobs, job_receiver) = JobQueue::new();
 
     let app_state = web::Data::new(AppState {
-        model,
+        embedder,
         tokenizer,
-        device,
-        db_client,
+        store,
+        reranker,
+        jobs,
         config,
     });
 
+    match cli.command {
+        None | Some(Command::Serve) => serve(app_state, job_receiver).await,
+        Some(Command::Ingest(args)) => {
+            let summary = bulk::run(&app_state, *args).await;
+            app_state.store.flush().await?;
+
+            let summary = summary?;
+            println!("{}", summary);
+            if summary.failures > 0 {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Some(Command::Crawl(args)) => {
+            let response = crawl::run(&app_state, *args).await;
+            app_state.store.flush().await?;
+
+            let response = response?;
+            println!("{}", response);
+            if !response.failures.is_empty() {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Some(Command::Bench(args)) => embedding::bench(&app_state, args).await,
+    }
+}
+
+async fn serve(app_state: web::Data<AppState>, job_receiver: JobReceiver) -> Result<()> {
+    actix_web::rt::spawn(jobs::run_worker(app_state.clone(), job_receiver));
+    actix_web::rt::spawn(sources::run_scheduler(app_state.clone()));
+
+    let flush_state = app_state.clone();
+    actix_web::rt::spawn(async move {
+        loop {
+            actix_web::rt::time::sleep(STORE_FLUSH_INTERVAL).await;
+            if let Err(e) = flush_state.store.flush().await {
+                eprintln!("Erreur lors de la sauvegarde de l'index: {}", e);
+            }
+        }
+    });
+
+    let server_state = app_state.clone();
     HttpServer::new(move || {
         let cors = Cors::permissive();
 
         App::new()
             .wrap(cors)
-            .app_data(app_state.clone())
+            .app_data(server_state.clone())
             .service(ingest)
+            .service(ingest_file)
+            .service(ingest_url)
             .service(ask)
+            .service(get_job)
+            .service(create_source)
+            .service(list_sources)
+            .service(get_source)
+            .service(delete_source)
+            .service(sync_source)
+            .service(get_document)
+            .service(update_document)
+            .service(delete_document)
+            .service(list_passages)
+            .service(get_passage)
+            .service(delete_passage)
+            .service(update_passage_metadata)
     })
     .bind("127.0.0.1:8080")?
     .run()
     .await?;
 
+    app_state.store.flush().await?;
+
     Ok(())
 }
@@ -0,0 +1,381 @@
+use crate::chunking::{
+    count_tokens, passage_budget, split_large_text, Chunk, MAX_CHUNK_TOKENS, MAX_CONTEXT_TOKENS,
+};
+use regex::Regex;
+use std::sync::LazyLock;
+use tokenizers::Tokenizer;
+
+/// Code blocks and tables longer than this are split by lines; below it they stay whole
+/// even when over the passage size, leaving room for the breadcrumb within the model limit.
+const MAX_BLOCK_TOKENS: usize = MAX_CHUNK_TOKENS - MAX_CONTEXT_TOKENS;
+
+const BREADCRUMB_SEPARATOR: &str = " > ";
+
+static HEADING: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(#{1,6})\s+(.*?)(?:\s+#+)?\s*$").unwrap());
+
+#[derive(Debug)]
+enum Block {
+    Heading(usize, String),
+    Code(Vec<String>),
+    Table(Vec<String>),
+    Text(String),
+}
+
+fn blocks(text: &str) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    let mut paragraph: Vec<&str> = Vec::new();
+    let mut fence: Option<(String, Vec<String>)> = None;
+
+    let flush_paragraph = |paragraph: &mut Vec<&str>, blocks: &mut Vec<Block>| {
+        if !paragraph.is_empty() {
+            blocks.push(Block::Text(paragraph.join("\n")));
+            paragraph.clear();
+        }
+    };
+
+    for line in text.lines() {
+        let trimmed = line.trim();
+
+        if let Some((marker, lines)) = fence.as_mut() {
+            lines.push(line.trim_end().to_string());
+            if trimmed.starts_with(marker.as_str())
+                && trimmed.trim_start_matches(&marker[..1]).is_empty()
+            {
+                let (_, lines) = fence.take().unwrap();
+                blocks.push(Block::Code(lines));
+            }
+            continue;
+        }
+
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            flush_paragraph(&mut paragraph, &mut blocks);
+            let marker: String = trimmed
+                .chars()
+                .take_while(|c| *c == trimmed.chars().next().unwrap())
+                .collect();
+            fence = Some((marker, vec![trimmed.to_string()]));
+            continue;
+        }
+
+        if let Some(caps) = HEADING.captures(trimmed) {
+            flush_paragraph(&mut paragraph, &mut blocks);
+            blocks.push(Block::Heading(caps[1].len(), caps[2].to_string()));
+            continue;
+        }
+
+        if trimmed.starts_with('|') {
+            flush_paragraph(&mut paragraph, &mut blocks);
+            match blocks.last_mut() {
+                Some(Block::Table(rows)) => rows.push(trimmed.to_string()),
+                _ => blocks.push(Block::Table(vec![trimmed.to_string()])),
+            }
+            continue;
+        }
+
+        if trimmed.is_empty() {
+            flush_paragraph(&mut paragraph, &mut blocks);
+            continue;
+        }
+
+        paragraph.push(trimmed);
+    }
+
+    flush_paragraph(&mut paragraph, &mut blocks);
+    if let Some((_, lines)) = fence {
+        // Unterminated fence: keep the code as it is.
+        blocks.push(Block::Code(lines));
+    }
+
+    blocks
+}
+
+/// Whether the text has Markdown headings outside code blocks.
+pub fn has_headings(text: &str) -> bool {
+    blocks(text).iter().any(|b| matches!(b, Block::Heading(..)))
+}
+
+/// Groups `body` lines into pieces of at most `MAX_BLOCK_TOKENS`, repeating `header`
+/// and `footer` around each so code fences and table headers stay valid.
+fn split_lines(
+    header: &[String],
+    body: &[String],
+    footer: &[String],
+    tokenizer: &Tokenizer,
+) -> Vec<String> {
+    let wrap = |lines: &[String]| {
+        header
+            .iter()
+            .chain(lines)
+            .chain(footer)
+            .cloned()
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    let mut pieces = Vec::new();
+    let mut current: Vec<String> = Vec::new();
+    for line in body {
+        current.push(line.clone());
+        if current.len() > 1 && count_tokens(tokenizer, &wrap(&current)) > MAX_BLOCK_TOKENS {
+            let last = current.pop().unwrap();
+            pieces.push(wrap(&current));
+            current = vec![last];
+        }
+    }
+    if !current.is_empty() {
+        pieces.push(wrap(&current));
+    }
+    pieces
+}
+
+/// Splits a code block or table that exceeds `MAX_BLOCK_TOKENS`, else keeps it whole.
+fn atomic_pieces(block: &Block, tokenizer: &Tokenizer) -> Vec<String> {
+    let (header, body, footer) = match block {
+        Block::Code(lines) => {
+            let text = lines.join("\n");
+            if count_tokens(tokenizer, &text) <= MAX_BLOCK_TOKENS || lines.len() < 3 {
+                return vec![text];
+            }
+            let closed = lines.len() > 1 && lines[0].starts_with(&lines[lines.len() - 1]);
+            let end = if closed { lines.len() - 1 } else { lines.len() };
+            (
+                &lines[..1],
+                &lines[1..end],
+                vec![lines[0]
+                    .chars()
+                    .take_while(|c| *c == '`' || *c == '~')
+                    .collect()],
+            )
+        }
+        Block::Table(rows) => {
+            let text = rows.join("\n");
+            if count_tokens(tokenizer, &text) <= MAX_BLOCK_TOKENS || rows.len() < 2 {
+                return vec![text];
+            }
+            let has_separator = rows
+                .get(1)
+                .is_some_and(|r| r.chars().all(|c| matches!(c, '|' | '-' | ':' | ' ')));
+            let header_len = if has_separator { 2 } else { 1 };
+            (&rows[..header_len], &rows[header_len..], Vec::new())
+        }
+        Block::Heading(..) | Block::Text(_) => {
+            unreachable!("only code blocks and tables are atomic")
+        }
+    };
+
+    split_lines(header, body, &footer, tokenizer)
+}
+
+/// Splits Markdown on its headings into chunks of at most `max_tokens`, never cutting
+/// through a code block or table, and records the heading path of each chunk.
+///
+/// Chunks never span two sections, so a short section yields a short chunk rather
+/// than being merged into its neighbour. Each section's chunks are made smaller by
+/// the size of its breadcrumb, which is embedded with them.
+pub fn chunk(
+    text: &str,
+    tokenizer: &Tokenizer,
+    max_tokens: usize,
+    overlap_tokens: usize,
+) -> Vec<Chunk> {
+    let mut chunks = Vec::new();
+    // Enclosing headings with their levels, outermost first.
+    let mut headings: Vec<(usize, String)> = Vec::new();
+    let mut current = String::new();
+    let mut current_tokens = 0;
+    let (mut budget, mut overlap) = (max_tokens, overlap_tokens);
+
+    let breadcrumb = |headings: &[(usize, String)]| {
+        (!headings.is_empty()).then(|| {
+            headings
+                .iter()
+                .map(|(_, title)| title.as_str())
+                .collect::<Vec<_>>()
+                .join(BREADCRUMB_SEPARATOR)
+        })
+    };
+
+    let flush = |current: &mut String,
+                 current_tokens: &mut usize,
+                 chunks: &mut Vec<Chunk>,
+                 headings: &[(usize, String)]| {
+        if !current.trim().is_empty() {
+            chunks.push(Chunk {
+                text: current.trim().to_string(),
+                breadcrumb: breadcrumb(headings),
+            });
+        }
+        current.clear();
+        *current_tokens = 0;
+    };
+
+    for block in blocks(text) {
+        let pieces = match &block {
+            Block::Heading(level, title) => {
+                flush(&mut current, &mut current_tokens, &mut chunks, &headings);
+                while headings.last().is_some_and(|(last, _)| last >= level) {
+                    headings.pop();
+                }
+                headings.push((*level, title.clone()));
+                budget = passage_budget(
+                    tokenizer,
+                    max_tokens,
+                    &[&breadcrumb(&headings).unwrap_or_default()],
+                );
+                overlap = overlap_tokens * budget / max_tokens;
+                continue;
+            }
+            Block::Text(paragraph) => {
+                if count_tokens(tokenizer, paragraph) > budget {
+                    split_large_text(paragraph, tokenizer, budget, overlap)
+                } else {
+                    vec![paragraph.clone()]
+                }
+            }
+            Block::Code(_) | Block::Table(_) => atomic_pieces(&block, tokenizer),
+        };
+
+        for piece in pieces {
+            let tokens = count_tokens(tokenizer, &piece);
+            if current_tokens > 0 && current_tokens + tokens > budget {
+                flush(&mut current, &mut current_tokens, &mut chunks, &headings);
+            }
+            if !current.is_empty() {
+                current.push_str("\n\n");
+            }
+            current.push_str(&piece);
+            current_tokens += tokens;
+        }
+    }
+
+    flush(&mut current, &mut current_tokens, &mut chunks, &headings);
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunking::tests::{tokenizer, words};
+
+    #[test]
+    fn headings_in_code_blocks_are_ignored() {
+        assert!(has_headings("Intro\n\n## Proxy\n\nTexte"));
+        assert!(!has_headings("```sh\n# commentaire\n```\nTexte"));
+    }
+
+    #[test]
+    fn sections_carry_their_breadcrumb() {
+        let chunks = chunk(
+            "Intro.\n\n# Guide\n\nDébut.\n\n## Réseau\n\n### Proxy\n\nRéglage.\n\n## Disque\n\nPlein.",
+            &tokenizer(),
+            100,
+            0,
+        );
+
+        let found: Vec<(&str, Option<&str>)> = chunks
+            .iter()
+            .map(|c| (c.text.as_str(), c.breadcrumb.as_deref()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("Intro.", None),
+                ("Début.", Some("Guide")),
+                ("Réglage.", Some("Guide > Réseau > Proxy")),
+                ("Plein.", Some("Guide > Disque")),
+            ]
+        );
+    }
+
+    fn breadcrumbs(text: &str) -> Vec<Option<String>> {
+        chunk(text, &tokenizer(), 100, 0)
+            .into_iter()
+            .map(|c| c.breadcrumb)
+            .collect()
+    }
+
+    #[test]
+    fn sibling_headings_replace_each_other() {
+        assert_eq!(
+            breadcrumbs("## A\n\nUn.\n\n## B\n\nDeux.\n\n## C\n\nTrois."),
+            [Some("A".into()), Some("B".into()), Some("C".into())]
+        );
+    }
+
+    #[test]
+    fn shallower_heading_closes_deeper_ones() {
+        assert_eq!(
+            breadcrumbs("## A\n\nUn.\n\n### B\n\nDeux.\n\n## C\n\nTrois."),
+            [Some("A".into()), Some("A > B".into()), Some("C".into())]
+        );
+        assert_eq!(
+            breadcrumbs("### A\n\nUn.\n\n# B\n\nDeux.\n\n### C\n\nTrois."),
+            [Some("A".into()), Some("B".into()), Some("B > C".into())]
+        );
+    }
+
+    #[test]
+    fn code_blocks_stay_whole_up_to_the_block_limit() {
+        let code = format!("```\n{}\n```", words(100));
+        let chunks = chunk(
+            &format!("Avant.\n\n{}\n\nAprès.", code),
+            &tokenizer(),
+            50,
+            0,
+        );
+
+        let texts: Vec<&str> = chunks.iter().map(|c| c.text.as_str()).collect();
+        assert_eq!(texts, ["Avant.", code.as_str(), "Après."]);
+    }
+
+    #[test]
+    fn long_code_blocks_are_split_with_their_fences() {
+        let lines: Vec<String> = (0..100).map(|i| format!("let x{} = {};", i, i)).collect();
+        let text = format!("```rust\n{}\n```", lines.join("\n"));
+        let tokenizer = tokenizer();
+
+        let chunks = chunk(&text, &tokenizer, 200, 0);
+
+        assert!(chunks.len() > 1);
+        for chunk in &chunks {
+            assert!(chunk.text.starts_with("```rust\n") && chunk.text.ends_with("\n```"));
+            assert!(count_tokens(&tokenizer, &chunk.text) <= MAX_BLOCK_TOKENS);
+        }
+        let body: Vec<&str> = chunks
+            .iter()
+            .flat_map(|c| c.text.lines().filter(|l| !l.starts_with("```")))
+            .collect();
+        assert_eq!(body, lines);
+    }
+
+    #[test]
+    fn split_tables_repeat_their_header() {
+        let rows: Vec<String> = (0..200).map(|i| format!("| {} | valeur |", i)).collect();
+        let text = format!("| Clé | Valeur |\n|---|---|\n{}", rows.join("\n"));
+
+        let chunks = chunk(&text, &tokenizer(), 200, 0);
+
+        assert!(chunks.len() > 1);
+        for chunk in &chunks {
+            assert!(chunk.text.starts_with("| Clé | Valeur |\n|---|---|\n| "));
+        }
+    }
+
+    #[test]
+    fn breadcrumb_is_taken_from_the_passage_size() {
+        let tokenizer = tokenizer();
+        let heading = words(100);
+        let text = format!("# {}\n\n{}", heading, words(2000));
+
+        let chunks = chunk(&text, &tokenizer, MAX_CHUNK_TOKENS, 50);
+
+        assert!(chunks.len() > 1);
+        for chunk in &chunks {
+            let breadcrumb = chunk.breadcrumb.as_deref().unwrap();
+            assert_eq!(breadcrumb, heading);
+            let input = format!("{}\n{}", breadcrumb, chunk.text);
+            assert!(count_tokens(&tokenizer, &input) <= MAX_CHUNK_TOKENS);
+        }
+    }
+}
@@ -0,0 +1,202 @@
+use crate::lexical::analyze;
+use std::collections::{HashMap, HashSet};
+use twox_hash::XxHash3_64;
+
+/// Consecutive terms hashed together, so that word order counts without a changed
+/// word altering too large a share of a short passage.
+const SHINGLE_SIZE: usize = 2;
+
+/// Signatures are cut into `BANDS` bands of `ROWS` values; passages sharing a band
+/// are compared. A pair at 0.5 similarity shares one nine times out of ten, at 0.8
+/// almost always, while unrelated passages practically never do.
+const BANDS: usize = 32;
+const ROWS: usize = 4;
+const NUM_HASHES: usize = BANDS * ROWS;
+
+type Signature = [u32; NUM_HASHES];
+
+/// Odd multiplier and offset of each hash function, drawn with SplitMix64.
+fn hash_params() -> [(u64, u64); NUM_HASHES] {
+    let mut seed = 0x9e37_79b9_7f4a_7c15u64;
+    let mut next = || {
+        seed = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = seed;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    };
+    std::array::from_fn(|_| (next() | 1, next()))
+}
+
+/// MinHash signature of the word shingles of `text`, ignoring case, punctuation and
+/// whitespace, or `None` if it has no words.
+pub fn signature(text: &str) -> Option<Signature> {
+    let terms = analyze(text);
+    if terms.is_empty() {
+        return None;
+    }
+
+    let shingles: HashSet<u64> = terms
+        .windows(SHINGLE_SIZE.min(terms.len()))
+        .map(|shingle| XxHash3_64::oneshot(shingle.join(" ").as_bytes()))
+        .collect();
+
+    let params = hash_params();
+    let mut signature = [u32::MAX; NUM_HASHES];
+    for shingle in shingles {
+        for (min, (a, b)) in signature.iter_mut().zip(params) {
+            let hash = (shingle.wrapping_mul(a).wrapping_add(b) >> 32) as u32;
+            *min = (*min).min(hash);
+        }
+    }
+    Some(signature)
+}
+
+/// Estimated Jaccard similarity of the shingle sets behind two signatures.
+pub fn similarity(a: &Signature, b: &Signature) -> f32 {
+    let same = a.iter().zip(b).filter(|(x, y)| x == y).count();
+    same as f32 / NUM_HASHES as f32
+}
+
+fn band_key(signature: &Signature, band: usize) -> u128 {
+    signature[band * ROWS..(band + 1) * ROWS]
+        .iter()
+        .fold(0, |key, row| key << 32 | *row as u128)
+}
+
+/// In-memory MinHash signatures keyed by passage id, bucketed by band for lookups.
+pub struct MinHashIndex {
+    signatures: HashMap<String, Signature>,
+    buckets: Vec<HashMap<u128, Vec<String>>>,
+}
+
+impl Default for MinHashIndex {
+    fn default() -> Self {
+        Self {
+            signatures: HashMap::new(),
+            buckets: vec![HashMap::new(); BANDS],
+        }
+    }
+}
+
+impl MinHashIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.signatures.len()
+    }
+
+    pub fn insert(&mut self, id: &str, text: &str) {
+        self.remove(id);
+        let Some(signature) = signature(text) else {
+            return;
+        };
+
+        for (band, buckets) in self.buckets.iter_mut().enumerate() {
+            buckets
+                .entry(band_key(&signature, band))
+                .or_default()
+                .push(id.to_string());
+        }
+        self.signatures.insert(id.to_string(), signature);
+    }
+
+    pub fn remove(&mut self, id: &str) -> bool {
+        let Some(signature) = self.signatures.remove(id) else {
+            return false;
+        };
+
+        for (band, buckets) in self.buckets.iter_mut().enumerate() {
+            let key = band_key(&signature, band);
+            if let Some(bucket) = buckets.get_mut(&key) {
+                bucket.retain(|other| other != id);
+                if bucket.is_empty() {
+                    buckets.remove(&key);
+                }
+            }
+        }
+        true
+    }
+
+    /// Returns up to `k` `(id, similarity)` pairs at or above `threshold`, closest first.
+    pub fn search(&self, text: &str, threshold: f32, k: usize) -> Vec<(String, f32)> {
+        let Some(query) = signature(text) else {
+            return Vec::new();
+        };
+
+        let candidates: HashSet<&String> = self
+            .buckets
+            .iter()
+            .enumerate()
+            .filter_map(|(band, buckets)| buckets.get(&band_key(&query, band)))
+            .flatten()
+            .collect();
+
+        let mut hits: Vec<(String, f32)> = candidates
+            .into_iter()
+            .map(|id| (id.clone(), similarity(&query, &self.signatures[id])))
+            .filter(|(_, score)| *score >= threshold)
+            .collect();
+
+        hits.sort_by(|a, b| b.1.total_cmp(&a.1));
+        hits.truncate(k);
+        hits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "le serveur renvoie une erreur lorsque la configuration du proxy est \
+                        absente et les journaux indiquent un délai dépassé";
+
+    #[test]
+    fn signature_ignores_case_and_punctuation() {
+        let a = signature(TEXT).unwrap();
+        let b = signature(&format!("{}.", TEXT.to_uppercase())).unwrap();
+        assert_eq!(similarity(&a, &b), 1.0);
+        assert!(signature(" ... ").is_none());
+    }
+
+    #[test]
+    fn similarity_follows_shared_shingles() {
+        let a = signature(TEXT).unwrap();
+        let edited = signature(&TEXT.replace("absente", "manquante")).unwrap();
+        let other =
+            signature("tout autre chose sans le moindre rapport avec ce qui précède").unwrap();
+
+        assert!((0.6..1.0).contains(&similarity(&a, &edited)));
+        assert!(similarity(&a, &other) < 0.1);
+    }
+
+    #[test]
+    fn index_finds_near_duplicates_above_the_threshold() {
+        let mut index = MinHashIndex::new();
+        index.insert("a", TEXT);
+        index.insert("b", &TEXT.replace("absente", "manquante"));
+        index.insert("c", "tout autre chose sans le moindre rapport");
+
+        let hits = index.search(TEXT, 0.5, 10);
+        let ids: Vec<&str> = hits.iter().map(|(id, _)| id.as_str()).collect();
+        assert_eq!(ids, ["a", "b"]);
+        assert_eq!(hits[0].1, 1.0);
+        assert_eq!(index.search(TEXT, 0.5, 1).len(), 1);
+    }
+
+    #[test]
+    fn removed_and_replaced_passages_are_forgotten() {
+        let mut index = MinHashIndex::new();
+        index.insert("a", TEXT);
+        index.insert("a", "tout autre chose sans le moindre rapport");
+        assert_eq!(index.len(), 1);
+        assert!(index.search(TEXT, 0.5, 10).is_empty());
+
+        assert!(index.remove("a"));
+        assert!(!index.remove("a"));
+        assert_eq!(index.len(), 0);
+        assert!(index.buckets.iter().all(HashMap::is_empty));
+    }
+}
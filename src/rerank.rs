@@ -0,0 +1,107 @@
+use crate::types::Passage;
+use anyhow::{anyhow, Result};
+use candle_core::{Device, IndexOp, Tensor};
+use candle_nn::{Linear, Module, VarBuilder};
+use candle_transformers::models::bert::{BertModel, Config, DTYPE};
+use hf_hub::{api::sync::Api, Repo, RepoType};
+use tokenizers::{PaddingParams, Tokenizer, TruncationParams};
+
+/// Cross-encoder scoring `(question, passage)` pairs jointly, e.g. an ms-marco MiniLM.
+///
+/// Expects a `BertForSequenceClassification` checkpoint with a single output logit.
+pub struct Reranker {
+    model: BertModel,
+    pooler: Linear,
+    classifier: Linear,
+    tokenizer: Tokenizer,
+    device: Device,
+    /// Number of first-stage candidates rescored before truncating to k.
+    pub candidates: usize,
+}
+
+impl Reranker {
+    pub fn load(model_id: &str, candidates: usize, device: &Device) -> Result<Self> {
+        let repo = Repo::with_revision(model_id.to_string(), RepoType::Model, "main".into());
+        let api = Api::new()?.repo(repo);
+        let config_file = api.get("config.json")?;
+        let tokenizer_file = api.get("tokenizer.json")?;
+        let weights_file = api.get("model.safetensors")?;
+
+        let config: Config = serde_json::from_str(&std::fs::read_to_string(config_file)?)?;
+
+        let mut tokenizer = Tokenizer::from_file(tokenizer_file).map_err(|e| anyhow!(e))?;
+        tokenizer
+            .with_padding(Some(PaddingParams::default()))
+            .with_truncation(Some(TruncationParams {
+                max_length: config.max_position_embeddings,
+                ..Default::default()
+            }))
+            .map_err(|e| anyhow!(e))?;
+
+        let vb = unsafe { VarBuilder::from_mmaped_safetensors(&[weights_file], DTYPE, device)? };
+        let model = BertModel::load(vb.clone(), &config)?;
+        let pooler = candle_nn::linear(
+            config.hidden_size,
+            config.hidden_size,
+            vb.pp("bert.pooler.dense"),
+        )?;
+        let classifier = candle_nn::linear(config.hidden_size, 1, vb.pp("classifier"))?;
+
+        Ok(Self {
+            model,
+            pooler,
+            classifier,
+            tokenizer,
+            device: device.clone(),
+            candidates,
+        })
+    }
+
+    /// Relevance logit of each passage for the question, in input order.
+    pub fn score(&self, question: &str, passages: &[&str]) -> Result<Vec<f32>> {
+        if passages.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let pairs: Vec<(&str, &str)> = passages.iter().map(|p| (question, *p)).collect();
+        let encodings = self
+            .tokenizer
+            .encode_batch(pairs, true)
+            .map_err(|e| anyhow!(e))?;
+
+        let stack = |f: fn(&tokenizers::Encoding) -> &[u32]| -> Result<Tensor> {
+            let rows = encodings
+                .iter()
+                .map(|e| Tensor::new(f(e), &self.device))
+                .collect::<candle_core::Result<Vec<_>>>()?;
+            Ok(Tensor::stack(&rows, 0)?)
+        };
+        let ids = stack(|e| e.get_ids())?;
+        let type_ids = stack(|e| e.get_type_ids())?;
+        let mask = stack(|e| e.get_attention_mask())?;
+
+        let hidden = self.model.forward(&ids, &type_ids, Some(&mask))?;
+        let cls = hidden.i((.., 0))?;
+        let pooled = self.pooler.forward(&cls)?.tanh()?;
+        let logits = self.classifier.forward(&pooled)?.squeeze(1)?;
+
+        Ok(logits.to_vec1::<f32>()?)
+    }
+
+    /// Rescores `candidates` and keeps the best `k`, with the cross-encoder logit as score.
+    pub fn rerank(
+        &self,
+        question: &str,
+        candidates: Vec<Passage>,
+        k: usize,
+    ) -> Result<Vec<(Passage, f32)>> {
+        let texts: Vec<&str> = candidates.iter().map(|p| p.text.as_str()).collect();
+        let scores = self.score(question, &texts)?;
+
+        let mut scored: Vec<(Passage, f32)> = candidates.into_iter().zip(scores).collect();
+        scored.sort_unstable_by(|a, b| b.1.total_cmp(&a.1));
+        scored.truncate(k);
+
+        Ok(scored)
+    }
+}
@@ -1,9 +1,22 @@
-use crate::types::Passage;
-use mongodb::bson::doc;
-use mongodb::options::FindOptions;
+use crate::filter::MetadataFilter;
+use crate::rerank::Reranker;
+use crate::store::VectorStore;
+use crate::types::{Passage, SearchMode};
+use actix_web::web;
 use rayon::prelude::*;
+use std::collections::HashMap;
+use std::sync::Arc;
 
-fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+/// Rank offset of reciprocal rank fusion; 60 is the value from the original paper.
+const RRF_K: f32 = 60.0;
+
+/// How many candidates each ranking contributes to the fusion, relative to `k`.
+const HYBRID_POOL_FACTOR: usize = 4;
+
+/// How many candidates maximal marginal relevance chooses from, relative to `k`.
+const MMR_POOL_FACTOR: usize = 4;
+
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
     let dot_product: f32 = a.iter().zip(b.iter()).map(|(x, y)| x * y).sum();
     let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
     let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
@@ -15,56 +28,220 @@ fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
     }
 }
 
-pub async fn search_top_k(
+pub fn rank_by_similarity(
+    passages: Vec<Passage>,
     question_embedding: &[f32],
     k: usize,
-    client: &mongodb::Client,
-    db_name: &str,
-    collection_name: &str,
-    fetch_limit: Option<i64>,
-) -> Result<Vec<Passage>, Box<dyn std::error::Error>> {
-    let docs_collection = client
-        .database(db_name)
-        .collection::<Passage>(collection_name);
-
-    let find_opts = FindOptions::builder()
-        .projection(doc! {
-            "text": 1,
-            "embedding": 1,
-            "metadata": 1,
-        })
-        .limit(fetch_limit.unwrap_or(2000))
-        .build();
-
-    let mut cursor = docs_collection
-        .find(doc! {})
-        .with_options(find_opts)
-        .await?;
-    let mut passages = Vec::new();
-
-    use futures::TryStreamExt;
-    while let Some(p) = cursor.try_next().await? {
-        if !p.embedding.is_empty() {
-            passages.push(p);
-        }
-    }
-
+) -> Vec<(Passage, f32)> {
     let mut scored_passages: Vec<_> = passages
-        .par_iter()
+        .into_par_iter()
         .map(|p| {
             let sim = cosine_similarity(question_embedding, &p.embedding);
-            (p.clone(), sim)
+            (p, sim)
         })
         .collect();
 
     scored_passages
         .sort_unstable_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+    scored_passages.truncate(k);
 
-    let top_passages: Vec<Passage> = scored_passages
-        .into_iter()
-        .take(k)
-        .map(|(p, _)| p)
+    scored_passages
+}
+
+/// Merges several rankings with reciprocal rank fusion; the score is the sum of `1 / (RRF_K + rank)`.
+pub fn reciprocal_rank_fusion(rankings: Vec<Vec<(Passage, f32)>>, k: usize) -> Vec<(Passage, f32)> {
+    let mut fused: HashMap<String, (Passage, f32)> = HashMap::new();
+
+    for ranking in rankings {
+        for (rank, (passage, _)) in ranking.into_iter().enumerate() {
+            let Some(id) = passage.id.map(|id| id.to_hex()) else {
+                continue;
+            };
+            let score = 1.0 / (RRF_K + rank as f32 + 1.0);
+            fused.entry(id).or_insert((passage, 0.0)).1 += score;
+        }
+    }
+
+    let mut fused: Vec<(Passage, f32)> = fused.into_values().collect();
+    fused.sort_unstable_by(|a, b| b.1.total_cmp(&a.1));
+    fused.truncate(k);
+    fused
+}
+
+/// Maximal marginal relevance: greedily picks the passage maximising
+/// `lambda * sim(question, p) - (1 - lambda) * max sim(p, already picked)`,
+/// so `lambda = 1` is plain relevance and lower values favour diversity.
+pub fn mmr_select(
+    candidates: Vec<Passage>,
+    question_embedding: &[f32],
+    k: usize,
+    lambda: f32,
+) -> Vec<Passage> {
+    let relevance: Vec<f32> = candidates
+        .iter()
+        .map(|p| cosine_similarity(question_embedding, &p.embedding))
         .collect();
 
-    Ok(top_passages)
+    let mut remaining: Vec<usize> = (0..candidates.len()).collect();
+    let mut selected: Vec<usize> = Vec::with_capacity(k);
+
+    while selected.len() < k && !remaining.is_empty() {
+        let (best, _) = remaining
+            .iter()
+            .enumerate()
+            .map(|(pos, &i)| {
+                let redundancy = selected
+                    .iter()
+                    .map(|&j| cosine_similarity(&candidates[i].embedding, &candidates[j].embedding))
+                    .fold(0.0, f32::max);
+                (pos, lambda * relevance[i] - (1.0 - lambda) * redundancy)
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap();
+        selected.push(remaining.swap_remove(best));
+    }
+
+    let mut candidates: Vec<Option<Passage>> = candidates.into_iter().map(Some).collect();
+    selected
+        .into_iter()
+        .filter_map(|i| candidates[i].take())
+        .collect()
+}
+
+/// Per-question retrieval parameters.
+#[derive(Clone, Copy)]
+pub struct SearchOptions<'a> {
+    pub k: usize,
+    pub mode: SearchMode,
+    pub mmr_lambda: Option<f32>,
+    pub filter: Option<&'a MetadataFilter>,
+    pub fetch_limit: Option<i64>,
+}
+
+pub async fn search_top_k(
+    question: &str,
+    question_embedding: &[f32],
+    options: &SearchOptions<'_>,
+    store: &dyn VectorStore,
+    reranker: Option<&Arc<Reranker>>,
+) -> Result<Vec<Passage>, Box<dyn std::error::Error>> {
+    let SearchOptions {
+        k,
+        mode,
+        mmr_lambda,
+        filter,
+        fetch_limit,
+    } = *options;
+
+    let pool_k = if mmr_lambda.is_some() {
+        k * MMR_POOL_FACTOR
+    } else {
+        k
+    };
+    let first_stage_k = reranker.map_or(pool_k, |r| r.candidates.max(pool_k));
+
+    let scored = match mode {
+        SearchMode::Vector => {
+            store
+                .search(question_embedding, first_stage_k, filter, fetch_limit)
+                .await?
+        }
+        SearchMode::Lexical => store.search_text(question, first_stage_k, filter).await?,
+        SearchMode::Hybrid => {
+            let pool = first_stage_k * HYBRID_POOL_FACTOR;
+            let dense = store
+                .search(question_embedding, pool, filter, fetch_limit)
+                .await?;
+            let lexical = store.search_text(question, pool, filter).await?;
+            reciprocal_rank_fusion(vec![dense, lexical], first_stage_k)
+        }
+    };
+
+    let mut candidates: Vec<Passage> = scored.into_iter().map(|(p, _)| p).collect();
+
+    if let Some(reranker) = reranker {
+        // The cross-encoder runs a full forward pass, kept off the async executor.
+        let (reranker, question) = (reranker.clone(), question.to_string());
+        candidates = web::block(move || reranker.rerank(&question, candidates, pool_k))
+            .await??
+            .into_iter()
+            .map(|(p, _)| p)
+            .collect();
+    }
+
+    match mmr_lambda {
+        Some(lambda) => Ok(mmr_select(candidates, question_embedding, k, lambda)),
+        None => {
+            candidates.truncate(k);
+            Ok(candidates)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::bson::oid::ObjectId;
+
+    fn passage(text: &str, embedding: Vec<f32>) -> Passage {
+        Passage {
+            id: Some(ObjectId::new()),
+            text: text.to_string(),
+            embedding,
+            metadata: None,
+            hash: None,
+        }
+    }
+
+    fn texts(ranking: &[(Passage, f32)]) -> Vec<&str> {
+        ranking.iter().map(|(p, _)| p.text.as_str()).collect()
+    }
+
+    #[test]
+    fn fusion_favours_passages_ranked_by_both() {
+        let (a, b, c) = (
+            passage("a", vec![]),
+            passage("b", vec![]),
+            passage("c", vec![]),
+        );
+        let dense = vec![(a.clone(), 0.9), (b.clone(), 0.8)];
+        let lexical = vec![(c.clone(), 12.0), (b.clone(), 7.0), (a.clone(), 1.0)];
+
+        let fused = reciprocal_rank_fusion(vec![dense, lexical], 3);
+        assert_eq!(texts(&fused), ["a", "b", "c"]);
+        assert!((fused[0].1 - (1.0 / 61.0 + 1.0 / 63.0)).abs() < 1e-6);
+        assert!((fused[2].1 - 1.0 / 61.0).abs() < 1e-6);
+        assert_eq!(reciprocal_rank_fusion(vec![vec![(a, 1.0)]], 0).len(), 0);
+    }
+
+    #[test]
+    fn fusion_skips_passages_without_id() {
+        let mut unsaved = passage("sans id", vec![]);
+        unsaved.id = None;
+        let fused =
+            reciprocal_rank_fusion(vec![vec![(unsaved, 1.0), (passage("a", vec![]), 0.5)]], 5);
+        assert_eq!(texts(&fused), ["a"]);
+    }
+
+    #[test]
+    fn mmr_trades_relevance_for_diversity() {
+        let candidates = || {
+            vec![
+                passage("proche", vec![1.0, 0.0]),
+                passage("copie", vec![0.99, 0.14]),
+                passage("autre", vec![0.6, 0.8]),
+            ]
+        };
+        let question = [1.0, 0.0];
+        let selected = |lambda| -> Vec<String> {
+            mmr_select(candidates(), &question, 2, lambda)
+                .into_iter()
+                .map(|p| p.text)
+                .collect()
+        };
+
+        assert_eq!(selected(1.0), ["proche", "copie"]);
+        assert_eq!(selected(0.3), ["proche", "autre"]);
+        assert_eq!(mmr_select(candidates(), &question, 5, 0.5).len(), 3);
+    }
 }
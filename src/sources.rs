@@ -0,0 +1,499 @@
+use crate::bulk::{spawn_reader, Entry, FileFilter};
+use crate::chunking::ChunkingConfig;
+use crate::crawl::{self, CrawlOptions};
+use crate::extract::{extract, Extracted, FileKind};
+use crate::ingestion::{
+    delete_document, file_metadata, store_extracted, update_document, validate_text, DocumentSink,
+    Progress,
+};
+use crate::types::{
+    Document, Metadata, Source, SourceDocument, SourceKind, SourceRequest, SyncReport,
+};
+use crate::utils::compute_hash;
+use crate::AppState;
+use actix_web::web;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use futures::StreamExt;
+use mongodb::bson::DateTime;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How often the scheduler looks for sources due for synchronization.
+const SCHEDULER_TICK: Duration = Duration::from_secs(30);
+
+/// Longest synchronization interval, a year.
+const MAX_INTERVAL_MINUTES: u64 = 365 * 24 * 60;
+
+/// Errors kept per synchronization; later ones are only counted.
+const MAX_SYNC_ERRORS: usize = 100;
+
+/// Canonical path of a directory source, which must lie under one of `roots`.
+///
+/// Checked at registration and again before each synchronization, as the path may
+/// have been replaced by a link since.
+fn directory_location(roots: &[PathBuf], location: &str) -> Result<PathBuf, String> {
+    if roots.is_empty() {
+        return Err("Les sources de type directory sont désactivées (SOURCE_DIRECTORIES)".into());
+    }
+    let path = Path::new(location)
+        .canonicalize()
+        .map_err(|_| format!("Chemin introuvable: {}", location))?;
+    if !roots.iter().any(|root| path.starts_with(root)) {
+        return Err(format!(
+            "Chemin hors des répertoires autorisés (SOURCE_DIRECTORIES): {}",
+            location
+        ));
+    }
+    Ok(path)
+}
+
+/// Checks a registration and turns it into a source due for synchronization right away.
+pub fn new_source(state: &AppState, mut req: SourceRequest) -> Result<Source, String> {
+    if !(1..=MAX_INTERVAL_MINUTES).contains(&req.interval_minutes) {
+        return Err(format!(
+            "interval_minutes doit être entre 1 et {}",
+            MAX_INTERVAL_MINUTES
+        ));
+    }
+
+    let (depth, max_pages) = match req.kind {
+        SourceKind::Url | SourceKind::Sitemap => {
+            crawl::parse_start_url(&req.location)?;
+            if !req.include.is_empty() || !req.exclude.is_empty() {
+                return Err("include et exclude ne s'appliquent qu'aux répertoires".into());
+            }
+            // A sitemap already lists the pages to crawl.
+            let default_depth = match req.kind {
+                SourceKind::Sitemap => 0,
+                _ => crawl::DEFAULT_DEPTH,
+            };
+            let options = CrawlOptions {
+                depth: req.depth.unwrap_or(default_depth),
+                max_pages: req.max_pages.unwrap_or(crawl::DEFAULT_MAX_PAGES),
+            };
+            options.validate()?;
+            (options.depth, options.max_pages)
+        }
+        SourceKind::Directory => {
+            let path = directory_location(&state.config.source_directories, &req.location)?;
+            req.location = path.to_string_lossy().into_owned();
+            FileFilter::new(&req.include, &req.exclude).map_err(|e| e.to_string())?;
+            (0, 0)
+        }
+    };
+
+    let chunking = req
+        .chunking
+        .unwrap_or_default()
+        .resolve(state.config.chunking)?;
+
+    let now = DateTime::now();
+    Ok(Source {
+        id: None,
+        kind: req.kind,
+        location: req.location,
+        interval_minutes: req.interval_minutes,
+        depth,
+        max_pages,
+        include: req.include,
+        exclude: req.exclude,
+        metadata: req.metadata,
+        chunking,
+        documents: Vec::new(),
+        last_sync: None,
+        next_sync_at: now,
+        created_at: now,
+    })
+}
+
+/// Synchronizes the sources as they fall due, one at a time.
+pub async fn run_scheduler(state: web::Data<AppState>) {
+    loop {
+        match state.store.list_sources().await {
+            Ok(sources) => {
+                for source in sources {
+                    if source.next_sync_at <= DateTime::now() {
+                        run(&state, source).await;
+                    }
+                }
+            }
+            Err(e) => eprintln!("Impossible de lister les sources: {}", e),
+        }
+        actix_web::rt::time::sleep(SCHEDULER_TICK).await;
+    }
+}
+
+async fn run(state: &AppState, source: Source) {
+    let id = source.id.map(|id| id.to_hex()).unwrap_or_default();
+    let (documents, report) = sync(state, &source).await;
+    println!(
+        "Source {} synchronisée: {} ajouté(s), {} mis à jour, {} supprimé(s), {} inchangé(s), {} échec(s)",
+        source.location,
+        report.added,
+        report.updated,
+        report.deleted,
+        report.unchanged,
+        report.failed
+    );
+
+    // The source may have been removed, or asked to synchronize again, in the meantime.
+    let result = match state.store.get_source(&id).await {
+        Ok(Some(mut current)) => {
+            if current.next_sync_at == source.next_sync_at {
+                let interval = current.interval_minutes as i64 * 60_000;
+                current.next_sync_at =
+                    DateTime::from_millis(report.finished_at.timestamp_millis() + interval);
+            }
+            current.documents = documents;
+            current.last_sync = Some(report);
+            state.store.replace_source(&current).await.map(|_| ())
+        }
+        Ok(None) => Ok(()),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        eprintln!("Impossible d'enregistrer la source {}: {}", id, e);
+    }
+}
+
+/// Fetches the source again and brings its documents up to date: new ones are
+/// ingested, changed ones updated, and those no longer found deleted.
+///
+/// Documents that could not be read this time are kept as they were, and nothing
+/// is deleted if the source could not be read at all.
+pub async fn sync(state: &AppState, source: &Source) -> (Vec<SourceDocument>, SyncReport) {
+    let mut sync = SourceSync {
+        source_id: source.id.map(|id| id.to_hex()).unwrap_or_default(),
+        chunking: source.chunking,
+        previous: source
+            .documents
+            .iter()
+            .map(|d| (d.key.clone(), d.clone()))
+            .collect(),
+        documents: Vec::new(),
+        report: SyncReport {
+            added: 0,
+            updated: 0,
+            deleted: 0,
+            unchanged: 0,
+            failed: 0,
+            errors: Vec::new(),
+            finished_at: DateTime::now(),
+        },
+    };
+
+    let result = match source.kind {
+        SourceKind::Url | SourceKind::Sitemap => sync_web(state, source, &mut sync).await,
+        SourceKind::Directory => sync_directory(state, source, &mut sync).await,
+    };
+    let complete = match result {
+        Ok(()) => true,
+        Err(e) => {
+            sync.error(format!("Synchronisation interrompue: {}", e));
+            false
+        }
+    };
+
+    sync.finish(state, complete).await
+}
+
+async fn sync_web(state: &AppState, source: &Source, sync: &mut SourceSync) -> Result<()> {
+    let start = crawl::parse_start_url(&source.location).map_err(|e| anyhow!(e))?;
+    let options = CrawlOptions {
+        depth: source.depth,
+        max_pages: source.max_pages,
+    };
+    let response = crawl::crawl(state, start, options, source.metadata.clone(), sync).await?;
+    for failure in response.failures {
+        sync.fail(&failure.url, failure.error);
+    }
+    Ok(())
+}
+
+async fn sync_directory(state: &AppState, source: &Source, sync: &mut SourceSync) -> Result<()> {
+    let location = directory_location(&state.config.source_directories, &source.location)
+        .map_err(|e| anyhow!(e))?;
+    let (mut rx, reader) = spawn_reader(location, &source.include, &source.exclude)?;
+
+    while let Some(entry) = rx.next().await {
+        match entry {
+            Entry::File { path, data } => {
+                if let Err(e) = sync_file(state, source, sync, &path, &data).await {
+                    sync.fail(&path, e);
+                }
+            }
+            Entry::Failed { path, error } => sync.fail(&path, error),
+            Entry::Total(_) | Entry::Unsupported => {}
+        }
+    }
+
+    reader
+        .join()
+        .map_err(|_| anyhow!("Le thread de lecture a paniqué"))?
+}
+
+async fn sync_file(
+    state: &AppState,
+    source: &Source,
+    sync: &mut SourceSync,
+    path: &str,
+    data: &[u8],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let kind = FileKind::from_name(path).ok_or("Format de fichier non pris en charge")?;
+    let mut extracted = extract(kind, data)?;
+    validate_text(&extracted.text)?;
+    let metadata = file_metadata(path, kind, &mut extracted, source.metadata.clone());
+    sync.store(state, path, extracted, metadata).await?;
+    Ok(())
+}
+
+/// Documents of a source being synchronized, matched on their key against the previous run.
+struct SourceSync {
+    source_id: String,
+    chunking: ChunkingConfig,
+    /// Documents of the previous run not met yet.
+    previous: HashMap<String, SourceDocument>,
+    documents: Vec<SourceDocument>,
+    report: SyncReport,
+}
+
+impl SourceSync {
+    fn error(&mut self, error: String) {
+        if self.report.errors.len() < MAX_SYNC_ERRORS {
+            self.report.errors.push(error);
+        }
+    }
+
+    /// Records a document that could not be read; its previous version is kept.
+    fn fail(&mut self, key: &str, error: impl Display) {
+        self.report.failed += 1;
+        self.error(format!("{}: {}", key, error));
+        if let Some(previous) = self.previous.remove(key) {
+            self.documents.push(previous);
+        }
+    }
+
+    /// Deletes the documents no longer found, unless the synchronization stopped
+    /// early or reached nothing, as when the source is unreachable.
+    async fn finish(
+        mut self,
+        state: &AppState,
+        complete: bool,
+    ) -> (Vec<SourceDocument>, SyncReport) {
+        let reached = self.report.added + self.report.updated + self.report.unchanged > 0;
+        if complete && !reached && !self.previous.is_empty() {
+            self.error("Aucun document atteint, suppressions annulées".into());
+        }
+
+        for (key, previous) in std::mem::take(&mut self.previous) {
+            // Identical pages under two keys share one document.
+            let shared = self
+                .documents
+                .iter()
+                .any(|d| d.document_id == previous.document_id);
+            if !complete || !reached || shared {
+                if !shared {
+                    self.documents.push(previous);
+                }
+                continue;
+            }
+
+            match delete_document(state.store.as_ref(), &previous.document_id).await {
+                Ok(_) => self.report.deleted += 1,
+                Err(e) => {
+                    self.error(format!("{}: suppression impossible: {}", key, e));
+                    self.documents.push(previous);
+                }
+            }
+        }
+
+        self.documents.sort_by(|a, b| a.key.cmp(&b.key));
+        self.report.finished_at = DateTime::now();
+        (self.documents, self.report)
+    }
+
+    /// Records the source on a document it stored, so that later runs may adopt it.
+    async fn claim(
+        &self,
+        state: &AppState,
+        mut document: Document,
+    ) -> Result<Document, Box<dyn std::error::Error>> {
+        if document.source_id.as_deref() != Some(self.source_id.as_str()) {
+            document.source_id = Some(self.source_id.clone());
+            state.store.replace_document(&document).await?;
+        }
+        Ok(document)
+    }
+
+    /// Stores the current version of a document, returning its id, and the document
+    /// unless it is unchanged.
+    async fn store_version(
+        &mut self,
+        state: &AppState,
+        previous: Option<&SourceDocument>,
+        content_hash: i64,
+        extracted: Extracted,
+        metadata: Metadata,
+    ) -> Result<(String, Option<Document>), Box<dyn std::error::Error>> {
+        if let Some(previous) = previous {
+            if previous.content_hash != content_hash {
+                let document = replace_document(
+                    state,
+                    &previous.document_id,
+                    extracted,
+                    metadata,
+                    self.chunking,
+                )
+                .await?;
+                let document = self.claim(state, document).await?;
+                self.report.updated += 1;
+                let id = document.id.map(|id| id.to_hex()).unwrap_or_default();
+                return Ok((id, Some(document)));
+            }
+            // A document removed through the API since is ingested again.
+            if state
+                .store
+                .get_document(&previous.document_id)
+                .await?
+                .is_some()
+            {
+                self.report.unchanged += 1;
+                return Ok((previous.document_id.clone(), None));
+            }
+        }
+
+        // Adopts a document of this source already holding the text, such as the same
+        // page under another URL or one stored by a run interrupted before being recorded.
+        // Documents ingested otherwise are left alone, not to be updated or deleted later.
+        if let Some(existing) = state
+            .store
+            .find_source_document(&self.source_id, content_hash)
+            .await?
+            && let Some(id) = existing.id
+        {
+            self.report.unchanged += 1;
+            return Ok((id.to_hex(), None));
+        }
+
+        let document = store_extracted(
+            state,
+            extracted,
+            metadata,
+            self.chunking,
+            &Progress::default(),
+        )
+        .await?;
+        let document = self.claim(state, document).await?;
+        self.report.added += 1;
+        Ok((
+            document.id.map(|id| id.to_hex()).unwrap_or_default(),
+            Some(document),
+        ))
+    }
+}
+
+/// Updates a stored document with a new version of its text.
+///
+/// Paged text is segmented page by page, which an update does not do, so it is
+/// stored again instead.
+async fn replace_document(
+    state: &AppState,
+    id: &str,
+    extracted: Extracted,
+    metadata: Metadata,
+    chunking: ChunkingConfig,
+) -> Result<Document, Box<dyn std::error::Error>> {
+    if extracted.pages.is_empty() {
+        let updated = update_document(
+            state,
+            id,
+            &extracted.text,
+            Some(metadata.clone()),
+            Some(chunking),
+        )
+        .await?;
+        if let Some(updated) = updated {
+            return Ok(updated.document);
+        }
+    } else {
+        delete_document(state.store.as_ref(), id).await?;
+    }
+    store_extracted(state, extracted, metadata, chunking, &Progress::default()).await
+}
+
+#[async_trait(?Send)]
+impl DocumentSink for SourceSync {
+    async fn store(
+        &mut self,
+        state: &AppState,
+        key: &str,
+        extracted: Extracted,
+        metadata: Metadata,
+    ) -> Result<Option<Document>, Box<dyn std::error::Error>> {
+        let content_hash = compute_hash(&extracted.text) as i64;
+        let previous = self.previous.remove(key);
+
+        let result = self
+            .store_version(state, previous.as_ref(), content_hash, extracted, metadata)
+            .await;
+        match result {
+            Ok((document_id, document)) => {
+                self.documents.push(SourceDocument {
+                    key: key.to_string(),
+                    document_id,
+                    content_hash,
+                });
+                Ok(document)
+            }
+            Err(e) => {
+                // Left for `fail`, which keeps it.
+                if let Some(previous) = previous {
+                    self.previous.insert(key.to_string(), previous);
+                }
+                Err(e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn directories_must_lie_under_a_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("docs");
+        std::fs::create_dir_all(root.join("wiki")).unwrap();
+        std::fs::create_dir(dir.path().join("other")).unwrap();
+        let roots = [root.canonicalize().unwrap()];
+        let location = |path: &Path| path.to_str().unwrap().to_string();
+
+        assert_eq!(
+            directory_location(&roots, &location(&root.join("wiki"))),
+            Ok(roots[0].join("wiki"))
+        );
+        assert!(directory_location(&roots, &location(&dir.path().join("other"))).is_err());
+        assert!(directory_location(&roots, &location(&root.join("../other"))).is_err());
+        assert!(directory_location(&roots, &location(&root.join("missing"))).is_err());
+        assert!(directory_location(&[], &location(&root)).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn links_out_of_a_root_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("docs");
+        std::fs::create_dir(&root).unwrap();
+        std::os::unix::fs::symlink("/etc", root.join("etc")).unwrap();
+
+        let roots = [root.canonicalize().unwrap()];
+        let result = directory_location(&roots, root.join("etc").to_str().unwrap());
+        assert!(result
+            .unwrap_err()
+            .starts_with("Chemin hors des répertoires autorisés"));
+    }
+}
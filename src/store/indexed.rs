@@ -0,0 +1,397 @@
+use super::{Upserted, VectorStore};
+use crate::filter::MetadataFilter;
+use crate::hnsw::{HnswIndex, HnswParams};
+use crate::lexical::Bm25Index;
+use crate::minhash::MinHashIndex;
+use crate::types::{Document, Job, Metadata, Passage, Source};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+
+const LIST_PAGE_SIZE: usize = 1000;
+const MAX_TOMBSTONE_RATIO: f32 = 0.2;
+
+/// Indexes ignore metadata, so filtered searches start with this many times `k`
+/// hits and widen until enough of them match or `MAX_FILTERED_HITS` is reached.
+const FILTER_OVERSAMPLE: usize = 10;
+const MAX_FILTERED_HITS: usize = 10_000;
+
+struct AnnIndex {
+    graph: RwLock<HnswIndex>,
+    path: PathBuf,
+    dirty: AtomicBool,
+}
+
+/// Wraps a backend with in-process indexes: an HNSW graph used for `search`,
+/// a BM25 index used for `search_text` and MinHash signatures used for
+/// `near_duplicates`.
+///
+/// Every write goes through the wrapped store first, then updates the indexes,
+/// so they never reference a passage the backend refused.
+pub struct IndexedStore {
+    inner: Box<dyn VectorStore>,
+    ann: Option<AnnIndex>,
+    lexical: Option<RwLock<Bm25Index>>,
+    shingles: Option<RwLock<MinHashIndex>>,
+}
+
+fn load_graph(params: HnswParams, path: &Path) -> HnswIndex {
+    match HnswIndex::load(path) {
+        Ok(index) if index.tombstone_ratio() <= MAX_TOMBSTONE_RATIO => index,
+        Ok(_) => {
+            println!("Index HNSW trop fragmenté, reconstruction");
+            HnswIndex::new(params)
+        }
+        Err(_) if !path.exists() => HnswIndex::new(params),
+        Err(e) => {
+            eprintln!("Index HNSW illisible, reconstruction: {}", e);
+            HnswIndex::new(params)
+        }
+    }
+}
+
+impl IndexedStore {
+    /// Builds the requested indexes from the backend. A persisted HNSW graph is
+    /// loaded from the path given with its parameters and only caught up with the backend.
+    pub async fn open(
+        inner: Box<dyn VectorStore>,
+        ann: Option<(HnswParams, PathBuf)>,
+        lexical: bool,
+        near_duplicates: bool,
+    ) -> Result<Self> {
+        let mut graph = ann.as_ref().map(|(params, path)| load_graph(*params, path));
+        let mut bm25 = lexical.then(Bm25Index::new);
+        let mut minhash = near_duplicates.then(MinHashIndex::new);
+
+        let mut seen = HashSet::new();
+        let mut added = 0;
+        let mut after: Option<String> = None;
+        loop {
+            let page = inner.list(after.as_deref(), LIST_PAGE_SIZE, None).await?;
+            let Some(last) = page.last() else {
+                break;
+            };
+            after = last.id.map(|id| id.to_hex());
+
+            for p in &page {
+                let Some(id) = p.id.map(|id| id.to_hex()) else {
+                    continue;
+                };
+                if let Some(graph) = graph.as_mut()
+                    && !graph.contains(&id)
+                    && !p.embedding.is_empty()
+                {
+                    graph.insert(&id, &p.embedding);
+                    added += 1;
+                }
+                if let Some(bm25) = bm25.as_mut() {
+                    bm25.insert(&id, &p.text);
+                }
+                if let Some(minhash) = minhash.as_mut() {
+                    minhash.insert(&id, &p.text);
+                }
+                seen.insert(id);
+            }
+
+            if page.len() < LIST_PAGE_SIZE {
+                break;
+            }
+        }
+
+        let mut removed = 0;
+        if let Some(graph) = graph.as_mut() {
+            let stale: Vec<String> = graph
+                .ids()
+                .filter(|id| !seen.contains(*id))
+                .map(str::to_string)
+                .collect();
+            for id in &stale {
+                graph.remove(id);
+            }
+            removed = stale.len();
+
+            println!(
+                "Index HNSW: {} passages ({} ajoutés, {} retirés)",
+                graph.len(),
+                added,
+                removed
+            );
+        }
+        if let Some(bm25) = &bm25 {
+            println!("Index BM25: {} passages", bm25.len());
+        }
+        if let Some(minhash) = &minhash {
+            println!("Index MinHash: {} passages", minhash.len());
+        }
+
+        let store = Self {
+            inner,
+            ann: graph.zip(ann).map(|(graph, (_, path))| AnnIndex {
+                graph: RwLock::new(graph),
+                path,
+                dirty: AtomicBool::new(added > 0 || removed > 0),
+            }),
+            lexical: bm25.map(RwLock::new),
+            shingles: minhash.map(RwLock::new),
+        };
+        store.flush().await?;
+
+        Ok(store)
+    }
+
+    fn index_passage(&self, id: &str, text: &str, embedding: &[f32]) {
+        if let Some(ann) = &self.ann
+            && !embedding.is_empty()
+        {
+            ann.graph.write().unwrap().insert(id, embedding);
+            ann.dirty.store(true, Ordering::Release);
+        }
+        if let Some(lexical) = &self.lexical {
+            lexical.write().unwrap().insert(id, text);
+        }
+        if let Some(shingles) = &self.shingles {
+            shingles.write().unwrap().insert(id, text);
+        }
+    }
+
+    /// Fetches the passages behind index hits, keeping the hit order and scores.
+    async fn resolve(&self, hits: Vec<(String, f32)>) -> Result<Vec<(Passage, f32)>> {
+        let ids: Vec<String> = hits.iter().map(|(id, _)| id.clone()).collect();
+        let mut passages: HashMap<String, Passage> = self
+            .inner
+            .get_many(&ids)
+            .await?
+            .into_iter()
+            .filter_map(|p| p.id.map(|id| (id.to_hex(), p)))
+            .collect();
+
+        Ok(hits
+            .into_iter()
+            .filter_map(|(id, score)| passages.remove(&id).map(|p| (p, score)))
+            .collect())
+    }
+
+    /// Runs an index query, post-filtering the hits on metadata.
+    async fn resolve_filtered(
+        &self,
+        query: impl Fn(usize) -> Vec<(String, f32)> + Send + Sync,
+        k: usize,
+        filter: Option<&MetadataFilter>,
+    ) -> Result<Vec<(Passage, f32)>> {
+        let Some(filter) = filter.filter(|f| !f.is_empty()) else {
+            return self.resolve(query(k)).await;
+        };
+
+        let mut wanted = k * FILTER_OVERSAMPLE;
+        loop {
+            let hits = query(wanted);
+            let exhausted = hits.len() < wanted || wanted >= MAX_FILTERED_HITS;
+
+            let mut matching: Vec<(Passage, f32)> = self
+                .resolve(hits)
+                .await?
+                .into_iter()
+                .filter(|(p, _)| filter.matches(p.metadata.as_ref()))
+                .collect();
+
+            if matching.len() >= k || exhausted {
+                matching.truncate(k);
+                return Ok(matching);
+            }
+            wanted = (wanted * 4).min(MAX_FILTERED_HITS);
+        }
+    }
+}
+
+#[async_trait]
+impl VectorStore for IndexedStore {
+    async fn insert(&self, passage: Passage) -> Result<String> {
+        let (text, embedding) = (passage.text.clone(), passage.embedding.clone());
+        let id = self.inner.insert(passage).await?;
+        self.index_passage(&id, &text, &embedding);
+        Ok(id)
+    }
+
+    async fn upsert_by_hash(&self, passage: Passage) -> Result<Upserted> {
+        let (text, embedding) = (passage.text.clone(), passage.embedding.clone());
+        let upserted = self.inner.upsert_by_hash(passage).await?;
+        if let Upserted::Inserted(id) = &upserted {
+            self.index_passage(id, &text, &embedding);
+        }
+        Ok(upserted)
+    }
+
+    async fn delete(&self, id: &str) -> Result<bool> {
+        let removed = self.inner.delete(id).await?;
+        if let Some(ann) = &self.ann
+            && ann.graph.write().unwrap().remove(id)
+        {
+            ann.dirty.store(true, Ordering::Release);
+        }
+        if let Some(lexical) = &self.lexical {
+            lexical.write().unwrap().remove(id);
+        }
+        if let Some(shingles) = &self.shingles {
+            shingles.write().unwrap().remove(id);
+        }
+        Ok(removed)
+    }
+
+    async fn set_metadata(&self, id: &str, metadata: Option<Metadata>) -> Result<bool> {
+        self.inner.set_metadata(id, metadata).await
+    }
+
+    async fn search(
+        &self,
+        embedding: &[f32],
+        k: usize,
+        filter: Option<&MetadataFilter>,
+        fetch_limit: Option<i64>,
+    ) -> Result<Vec<(Passage, f32)>> {
+        let Some(ann) = self
+            .ann
+            .as_ref()
+            .filter(|ann| ann.graph.read().unwrap().len() > 0)
+        else {
+            return self.inner.search(embedding, k, filter, fetch_limit).await;
+        };
+
+        self.resolve_filtered(
+            |n| ann.graph.read().unwrap().search(embedding, n),
+            k,
+            filter,
+        )
+        .await
+    }
+
+    async fn search_text(
+        &self,
+        query: &str,
+        k: usize,
+        filter: Option<&MetadataFilter>,
+    ) -> Result<Vec<(Passage, f32)>> {
+        let Some(lexical) = &self.lexical else {
+            return self.inner.search_text(query, k, filter).await;
+        };
+
+        self.resolve_filtered(|n| lexical.read().unwrap().search(query, n), k, filter)
+            .await
+    }
+
+    async fn near_duplicates(
+        &self,
+        text: &str,
+        threshold: f32,
+        k: usize,
+    ) -> Result<Vec<(Passage, f32)>> {
+        let Some(shingles) = &self.shingles else {
+            return self.inner.near_duplicates(text, threshold, k).await;
+        };
+
+        let hits = shingles.read().unwrap().search(text, threshold, k);
+        self.resolve(hits).await
+    }
+
+    async fn list(
+        &self,
+        after: Option<&str>,
+        limit: usize,
+        filter: Option<&MetadataFilter>,
+    ) -> Result<Vec<Passage>> {
+        self.inner.list(after, limit, filter).await
+    }
+
+    async fn get_many(&self, ids: &[String]) -> Result<Vec<Passage>> {
+        self.inner.get_many(ids).await
+    }
+
+    async fn insert_document(&self, document: Document) -> Result<String> {
+        self.inner.insert_document(document).await
+    }
+
+    async fn get_document(&self, id: &str) -> Result<Option<Document>> {
+        self.inner.get_document(id).await
+    }
+
+    async fn find_document_by_hash(&self, content_hash: i64) -> Result<Option<Document>> {
+        self.inner.find_document_by_hash(content_hash).await
+    }
+
+    async fn find_source_document(
+        &self,
+        source_id: &str,
+        content_hash: i64,
+    ) -> Result<Option<Document>> {
+        self.inner
+            .find_source_document(source_id, content_hash)
+            .await
+    }
+
+    async fn replace_document(&self, document: &Document) -> Result<bool> {
+        self.inner.replace_document(document).await
+    }
+
+    async fn delete_document(&self, id: &str) -> Result<bool> {
+        self.inner.delete_document(id).await
+    }
+
+    async fn passage_shared(&self, passage_id: &str, document_id: &str) -> Result<bool> {
+        self.inner.passage_shared(passage_id, document_id).await
+    }
+
+    async fn unlink_passage(&self, passage_id: &str) -> Result<usize> {
+        self.inner.unlink_passage(passage_id).await
+    }
+
+    async fn insert_job(&self, job: Job) -> Result<String> {
+        self.inner.insert_job(job).await
+    }
+
+    async fn get_job(&self, id: &str) -> Result<Option<Job>> {
+        self.inner.get_job(id).await
+    }
+
+    async fn replace_job(&self, job: &Job) -> Result<bool> {
+        self.inner.replace_job(job).await
+    }
+
+    async fn pending_jobs(&self) -> Result<Vec<Job>> {
+        self.inner.pending_jobs().await
+    }
+
+    async fn insert_source(&self, source: Source) -> Result<String> {
+        self.inner.insert_source(source).await
+    }
+
+    async fn get_source(&self, id: &str) -> Result<Option<Source>> {
+        self.inner.get_source(id).await
+    }
+
+    async fn list_sources(&self) -> Result<Vec<Source>> {
+        self.inner.list_sources().await
+    }
+
+    async fn replace_source(&self, source: &Source) -> Result<bool> {
+        self.inner.replace_source(source).await
+    }
+
+    async fn delete_source(&self, id: &str) -> Result<bool> {
+        self.inner.delete_source(id).await
+    }
+
+    async fn flush(&self) -> Result<()> {
+        if let Some(ann) = &self.ann
+            && ann.dirty.swap(false, Ordering::AcqRel)
+        {
+            let saved = ann.graph.read().unwrap().save(&ann.path);
+            if let Err(e) = saved {
+                ann.dirty.store(true, Ordering::Release);
+                return Err(anyhow!("Sauvegarde de l'index HNSW impossible: {}", e));
+            }
+        }
+        self.inner.flush().await
+    }
+}
@@ -0,0 +1,566 @@
+use super::{Upserted, VectorStore};
+use crate::filter::MetadataFilter;
+use crate::retrieval::rank_by_similarity;
+use crate::types::{Document, Job, Metadata, Passage, Source};
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, RwLock};
+
+#[derive(Default)]
+struct State {
+    passages: BTreeMap<ObjectId, Passage>,
+    /// Passage id by `hash`, for deduplication.
+    hashes: HashMap<i64, ObjectId>,
+    documents: BTreeMap<ObjectId, Document>,
+    jobs: BTreeMap<ObjectId, Job>,
+    sources: BTreeMap<ObjectId, Source>,
+}
+
+/// On-disk layout of the persisted state.
+#[derive(Serialize, Deserialize)]
+struct Snapshot<P, D, J, S> {
+    #[serde(default = "Vec::new")]
+    passages: Vec<P>,
+    #[serde(default = "Vec::new")]
+    documents: Vec<D>,
+    #[serde(default = "Vec::new")]
+    jobs: Vec<J>,
+    #[serde(default = "Vec::new")]
+    sources: Vec<S>,
+}
+
+/// In-process store, optionally persisted as JSON so a local run survives restarts.
+///
+/// Writes only mark the state dirty; the snapshot is rewritten on `flush`, which
+/// the server runs periodically and on shutdown.
+pub struct MemoryStore {
+    state: RwLock<State>,
+    path: Option<PathBuf>,
+    dirty: AtomicBool,
+    /// Serializes concurrent flushes, which share the temporary file.
+    flushing: Mutex<()>,
+}
+
+impl MemoryStore {
+    pub fn open(path: Option<&str>) -> Result<Self> {
+        let path = path.map(PathBuf::from);
+
+        let mut state = State::default();
+        if let Some(path) = path.as_deref().filter(|p| p.exists()) {
+            let data = std::fs::read(path)
+                .with_context(|| format!("Lecture de {} impossible", path.display()))?;
+            let snapshot: Snapshot<Passage, Document, Job, Source> = serde_json::from_slice(&data)?;
+            for p in snapshot.passages {
+                let id = p.id.ok_or_else(|| anyhow!("Passage persisté sans _id"))?;
+                if let Some(hash) = p.hash {
+                    state.hashes.insert(hash, id);
+                }
+                state.passages.insert(id, p);
+            }
+            for d in snapshot.documents {
+                let id = d.id.ok_or_else(|| anyhow!("Document persisté sans _id"))?;
+                state.documents.insert(id, d);
+            }
+            for j in snapshot.jobs {
+                let id = j.id.ok_or_else(|| anyhow!("Tâche persistée sans _id"))?;
+                state.jobs.insert(id, j);
+            }
+            for s in snapshot.sources {
+                let id = s.id.ok_or_else(|| anyhow!("Source persistée sans _id"))?;
+                state.sources.insert(id, s);
+            }
+        }
+
+        Ok(Self {
+            state: RwLock::new(state),
+            path,
+            dirty: AtomicBool::new(false),
+            flushing: Mutex::new(()),
+        })
+    }
+
+    fn insert_locked(&self, state: &mut State, mut passage: Passage) -> String {
+        let id = *passage.id.get_or_insert_with(ObjectId::new);
+        if let Some(hash) = passage.hash {
+            state.hashes.insert(hash, id);
+        }
+        state.passages.insert(id, passage);
+        self.touch();
+        id.to_hex()
+    }
+
+    fn touch(&self) {
+        self.dirty.store(true, Ordering::Release);
+    }
+}
+
+fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, data)
+        .with_context(|| format!("Écriture de {} impossible", tmp.display()))?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+fn parse_id(id: &str) -> Result<ObjectId> {
+    ObjectId::parse_str(id).map_err(|e| anyhow!("Identifiant invalide {}: {}", id, e))
+}
+
+#[async_trait]
+impl VectorStore for MemoryStore {
+    async fn insert(&self, passage: Passage) -> Result<String> {
+        let mut state = self.state.write().unwrap();
+        Ok(self.insert_locked(&mut state, passage))
+    }
+
+    async fn upsert_by_hash(&self, passage: Passage) -> Result<Upserted> {
+        let hash = passage.hash.ok_or_else(|| anyhow!("Passage sans hash"))?;
+
+        let mut state = self.state.write().unwrap();
+        if let Some(id) = state.hashes.get(&hash) {
+            return Ok(Upserted::Existing(id.to_hex()));
+        }
+
+        Ok(Upserted::Inserted(self.insert_locked(&mut state, passage)))
+    }
+
+    async fn delete(&self, id: &str) -> Result<bool> {
+        let id = parse_id(id)?;
+
+        let mut state = self.state.write().unwrap();
+        let Some(passage) = state.passages.remove(&id) else {
+            return Ok(false);
+        };
+        if let Some(hash) = passage.hash
+            && state.hashes.get(&hash) == Some(&id)
+        {
+            state.hashes.remove(&hash);
+        }
+        self.touch();
+
+        Ok(true)
+    }
+
+    async fn set_metadata(&self, id: &str, metadata: Option<Metadata>) -> Result<bool> {
+        let id = parse_id(id)?;
+
+        let mut state = self.state.write().unwrap();
+        let Some(passage) = state.passages.get_mut(&id) else {
+            return Ok(false);
+        };
+        passage.metadata = metadata;
+        self.touch();
+
+        Ok(true)
+    }
+
+    async fn search(
+        &self,
+        embedding: &[f32],
+        k: usize,
+        filter: Option<&MetadataFilter>,
+        _fetch_limit: Option<i64>,
+    ) -> Result<Vec<(Passage, f32)>> {
+        let passages: Vec<Passage> = self
+            .state
+            .read()
+            .unwrap()
+            .passages
+            .values()
+            .filter(|p| !p.embedding.is_empty())
+            .filter(|p| filter.is_none_or(|f| f.matches(p.metadata.as_ref())))
+            .cloned()
+            .collect();
+
+        Ok(rank_by_similarity(passages, embedding, k))
+    }
+
+    async fn list(
+        &self,
+        after: Option<&str>,
+        limit: usize,
+        filter: Option<&MetadataFilter>,
+    ) -> Result<Vec<Passage>> {
+        let state = self.state.read().unwrap();
+
+        let iter: Box<dyn Iterator<Item = &Passage>> = match after {
+            Some(id) => {
+                let id = parse_id(id)?;
+                Box::new(
+                    state
+                        .passages
+                        .range((std::ops::Bound::Excluded(id), std::ops::Bound::Unbounded))
+                        .map(|(_, p)| p),
+                )
+            }
+            None => Box::new(state.passages.values()),
+        };
+
+        Ok(iter
+            .filter(|p| filter.is_none_or(|f| f.matches(p.metadata.as_ref())))
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    async fn get_many(&self, ids: &[String]) -> Result<Vec<Passage>> {
+        let state = self.state.read().unwrap();
+
+        ids.iter()
+            .map(|id| parse_id(id).map(|id| state.passages.get(&id).cloned()))
+            .filter_map(Result::transpose)
+            .collect()
+    }
+
+    async fn insert_document(&self, mut document: Document) -> Result<String> {
+        let id = *document.id.get_or_insert_with(ObjectId::new);
+
+        let mut state = self.state.write().unwrap();
+        state.documents.insert(id, document);
+        self.touch();
+
+        Ok(id.to_hex())
+    }
+
+    async fn get_document(&self, id: &str) -> Result<Option<Document>> {
+        let id = parse_id(id)?;
+        Ok(self.state.read().unwrap().documents.get(&id).cloned())
+    }
+
+    async fn find_document_by_hash(&self, content_hash: i64) -> Result<Option<Document>> {
+        Ok(self
+            .state
+            .read()
+            .unwrap()
+            .documents
+            .values()
+            .find(|d| d.content_hash == content_hash)
+            .cloned())
+    }
+
+    async fn find_source_document(
+        &self,
+        source_id: &str,
+        content_hash: i64,
+    ) -> Result<Option<Document>> {
+        Ok(self
+            .state
+            .read()
+            .unwrap()
+            .documents
+            .values()
+            .find(|d| d.content_hash == content_hash && d.source_id.as_deref() == Some(source_id))
+            .cloned())
+    }
+
+    async fn replace_document(&self, document: &Document) -> Result<bool> {
+        let id = document.id.ok_or_else(|| anyhow!("Document sans _id"))?;
+
+        let mut state = self.state.write().unwrap();
+        let Some(existing) = state.documents.get_mut(&id) else {
+            return Ok(false);
+        };
+        *existing = document.clone();
+        self.touch();
+
+        Ok(true)
+    }
+
+    async fn delete_document(&self, id: &str) -> Result<bool> {
+        let id = parse_id(id)?;
+
+        let mut state = self.state.write().unwrap();
+        let removed = state.documents.remove(&id).is_some();
+        if removed {
+            self.touch();
+        }
+
+        Ok(removed)
+    }
+
+    async fn passage_shared(&self, passage_id: &str, document_id: &str) -> Result<bool> {
+        let document_id = parse_id(document_id)?;
+
+        Ok(self
+            .state
+            .read()
+            .unwrap()
+            .documents
+            .iter()
+            .any(|(id, d)| *id != document_id && d.passage_ids.iter().any(|p| p == passage_id)))
+    }
+
+    async fn unlink_passage(&self, passage_id: &str) -> Result<usize> {
+        let mut state = self.state.write().unwrap();
+        let mut unlinked = 0;
+        for document in state.documents.values_mut() {
+            let before = document.passage_ids.len();
+            document.passage_ids.retain(|p| p != passage_id);
+            if document.passage_ids.len() < before {
+                unlinked += 1;
+            }
+        }
+        if unlinked > 0 {
+            self.touch();
+        }
+
+        Ok(unlinked)
+    }
+
+    async fn insert_job(&self, mut job: Job) -> Result<String> {
+        let id = *job.id.get_or_insert_with(ObjectId::new);
+
+        let mut state = self.state.write().unwrap();
+        state.jobs.insert(id, job);
+        self.touch();
+
+        Ok(id.to_hex())
+    }
+
+    async fn get_job(&self, id: &str) -> Result<Option<Job>> {
+        let id = parse_id(id)?;
+        Ok(self.state.read().unwrap().jobs.get(&id).cloned())
+    }
+
+    async fn replace_job(&self, job: &Job) -> Result<bool> {
+        let id = job.id.ok_or_else(|| anyhow!("Tâche sans _id"))?;
+
+        let mut state = self.state.write().unwrap();
+        let Some(existing) = state.jobs.get_mut(&id) else {
+            return Ok(false);
+        };
+        *existing = job.clone();
+        self.touch();
+
+        Ok(true)
+    }
+
+    async fn pending_jobs(&self) -> Result<Vec<Job>> {
+        Ok(self
+            .state
+            .read()
+            .unwrap()
+            .jobs
+            .values()
+            .filter(|j| !j.status.is_finished())
+            .cloned()
+            .collect())
+    }
+
+    async fn insert_source(&self, mut source: Source) -> Result<String> {
+        let id = *source.id.get_or_insert_with(ObjectId::new);
+
+        let mut state = self.state.write().unwrap();
+        state.sources.insert(id, source);
+        self.touch();
+
+        Ok(id.to_hex())
+    }
+
+    async fn get_source(&self, id: &str) -> Result<Option<Source>> {
+        let id = parse_id(id)?;
+        Ok(self.state.read().unwrap().sources.get(&id).cloned())
+    }
+
+    async fn list_sources(&self) -> Result<Vec<Source>> {
+        Ok(self
+            .state
+            .read()
+            .unwrap()
+            .sources
+            .values()
+            .cloned()
+            .collect())
+    }
+
+    async fn replace_source(&self, source: &Source) -> Result<bool> {
+        let id = source.id.ok_or_else(|| anyhow!("Source sans _id"))?;
+
+        let mut state = self.state.write().unwrap();
+        let Some(existing) = state.sources.get_mut(&id) else {
+            return Ok(false);
+        };
+        *existing = source.clone();
+        self.touch();
+
+        Ok(true)
+    }
+
+    async fn delete_source(&self, id: &str) -> Result<bool> {
+        let id = parse_id(id)?;
+
+        let mut state = self.state.write().unwrap();
+        let removed = state.sources.remove(&id).is_some();
+        if removed {
+            self.touch();
+        }
+
+        Ok(removed)
+    }
+
+    async fn flush(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let _flushing = self.flushing.lock().unwrap();
+        if !self.dirty.swap(false, Ordering::AcqRel) {
+            return Ok(());
+        }
+
+        // Serialized under the read lock, written once it is released.
+        let data = {
+            let state = self.state.read().unwrap();
+            serde_json::to_vec(&Snapshot {
+                passages: state.passages.values().collect(),
+                documents: state.documents.values().collect(),
+                jobs: state.jobs.values().collect(),
+                sources: state.sources.values().collect(),
+            })
+        };
+        let written = data
+            .map_err(anyhow::Error::from)
+            .and_then(|data| write_atomic(path, &data));
+        if written.is_err() {
+            self.dirty.store(true, Ordering::Release);
+        }
+        written
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn passage(text: &str, hash: i64) -> Passage {
+        Passage {
+            id: None,
+            text: text.to_string(),
+            embedding: vec![1.0, 0.0],
+            metadata: Some(Metadata {
+                source: Some("guide".into()),
+                ..Default::default()
+            }),
+            hash: Some(hash),
+        }
+    }
+
+    #[actix_web::test]
+    async fn snapshot_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("store.json");
+        let path = path.to_str().unwrap();
+
+        let store = MemoryStore::open(Some(path)).unwrap();
+        let id = store
+            .insert(passage("Redémarrer le proxy", 1))
+            .await
+            .unwrap();
+        store.flush().await.unwrap();
+
+        let reopened = MemoryStore::open(Some(path)).unwrap();
+        let passages = reopened.get_many(std::slice::from_ref(&id)).await.unwrap();
+        assert_eq!(passages.len(), 1);
+        assert_eq!(passages[0].text, "Redémarrer le proxy");
+        assert_eq!(passages[0].embedding, vec![1.0, 0.0]);
+        assert_eq!(passages[0].metadata, passage("", 1).metadata);
+
+        // The hash index is rebuilt from the snapshot.
+        let upserted = reopened.upsert_by_hash(passage("copie", 1)).await.unwrap();
+        assert!(matches!(upserted, Upserted::Existing(existing) if existing == id));
+    }
+
+    #[actix_web::test]
+    async fn writes_are_persisted_on_flush_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("store.json");
+
+        let store = MemoryStore::open(path.to_str()).unwrap();
+        store.insert(passage("a", 1)).await.unwrap();
+        assert!(!path.exists());
+
+        store.flush().await.unwrap();
+        let written = std::fs::metadata(&path).unwrap().modified().unwrap();
+        store.flush().await.unwrap();
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().modified().unwrap(),
+            written
+        );
+    }
+
+    #[actix_web::test]
+    async fn upsert_deduplicates_on_hash() {
+        let store = MemoryStore::open(None).unwrap();
+
+        let first = store.upsert_by_hash(passage("a", 7)).await.unwrap();
+        let Upserted::Inserted(id) = first else {
+            panic!("premier passage non inséré");
+        };
+        let second = store.upsert_by_hash(passage("a", 7)).await.unwrap();
+        assert!(matches!(second, Upserted::Existing(ref existing) if *existing == id));
+        assert_eq!(store.list(None, 10, None).await.unwrap().len(), 1);
+
+        // Once deleted, the hash no longer points at it.
+        assert!(store.delete(&id).await.unwrap());
+        let third = store.upsert_by_hash(passage("a", 7)).await.unwrap();
+        assert!(matches!(third, Upserted::Inserted(ref new) if *new != id));
+    }
+
+    #[actix_web::test]
+    async fn unlink_passage_from_documents() {
+        let store = MemoryStore::open(None).unwrap();
+        let document = |passage_ids: Vec<String>| Document {
+            id: None,
+            metadata: None,
+            content_hash: 0,
+            passage_ids,
+            chunking: None,
+            source_id: None,
+            created_at: mongodb::bson::DateTime::now(),
+            updated_at: mongodb::bson::DateTime::now(),
+        };
+        let a = store.insert(passage("a", 1)).await.unwrap();
+        let b = store.insert(passage("b", 2)).await.unwrap();
+        let first = store
+            .insert_document(document(vec![a.clone(), b.clone()]))
+            .await
+            .unwrap();
+        let second = store
+            .insert_document(document(vec![a.clone()]))
+            .await
+            .unwrap();
+
+        assert_eq!(store.unlink_passage(&a).await.unwrap(), 2);
+        let first = store.get_document(&first).await.unwrap().unwrap();
+        assert_eq!(first.passage_ids, vec![b]);
+        let second = store.get_document(&second).await.unwrap().unwrap();
+        assert!(second.passage_ids.is_empty());
+        assert_eq!(store.unlink_passage(&a).await.unwrap(), 0);
+    }
+
+    #[actix_web::test]
+    async fn source_documents_are_found_by_source() {
+        let store = MemoryStore::open(None).unwrap();
+        let document = |source_id: Option<&str>| Document {
+            id: None,
+            metadata: None,
+            content_hash: 7,
+            passage_ids: Vec::new(),
+            chunking: None,
+            source_id: source_id.map(str::to_string),
+            created_at: mongodb::bson::DateTime::now(),
+            updated_at: mongodb::bson::DateTime::now(),
+        };
+        store.insert_document(document(None)).await.unwrap();
+        store.insert_document(document(Some("a"))).await.unwrap();
+
+        let found = store.find_source_document("a", 7).await.unwrap().unwrap();
+        assert_eq!(found.source_id.as_deref(), Some("a"));
+        assert!(store.find_source_document("b", 7).await.unwrap().is_none());
+        assert!(store.find_source_document("a", 8).await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,196 @@
+mod indexed;
+mod memory;
+mod mongo;
+
+use crate::config::{Config, StoreBackend};
+use crate::filter::MetadataFilter;
+use crate::types::{Document, Job, Metadata, Passage, Source};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+
+pub use indexed::IndexedStore;
+pub use memory::MemoryStore;
+pub use mongo::MongoStore;
+
+/// Result of an upsert keyed on the passage hash.
+#[derive(Debug, Clone)]
+pub enum Upserted {
+    Inserted(String),
+    Existing(String),
+}
+
+impl Upserted {
+    pub fn into_id(self) -> String {
+        match self {
+            Upserted::Inserted(id) | Upserted::Existing(id) => id,
+        }
+    }
+}
+
+/// Storage backend for passages and their embeddings.
+///
+/// Ids are the hex representation of the passage `ObjectId`, whatever the backend.
+#[async_trait]
+pub trait VectorStore: Send + Sync {
+    /// Inserts the passage as-is and returns its id.
+    async fn insert(&self, passage: Passage) -> Result<String>;
+
+    /// Inserts the passage unless one with the same `hash` is already stored.
+    async fn upsert_by_hash(&self, passage: Passage) -> Result<Upserted>;
+
+    /// Removes a passage, returning `false` if it did not exist.
+    async fn delete(&self, id: &str) -> Result<bool>;
+
+    /// Replaces the metadata of a passage, returning `false` if it does not exist.
+    async fn set_metadata(&self, id: &str, metadata: Option<Metadata>) -> Result<bool>;
+
+    /// Returns the `k` passages closest to `embedding` along with their cosine similarity,
+    /// restricted to those matching `filter`.
+    ///
+    /// `fetch_limit` bounds how many candidates a backend that has to pull embeddings
+    /// over the wire scans before ranking.
+    async fn search(
+        &self,
+        embedding: &[f32],
+        k: usize,
+        filter: Option<&MetadataFilter>,
+        fetch_limit: Option<i64>,
+    ) -> Result<Vec<(Passage, f32)>>;
+
+    /// Returns the `k` passages that best match `query` lexically, with their BM25 score.
+    async fn search_text(
+        &self,
+        query: &str,
+        k: usize,
+        filter: Option<&MetadataFilter>,
+    ) -> Result<Vec<(Passage, f32)>> {
+        let _ = (query, k, filter);
+        Err(anyhow!("Recherche lexicale non disponible sans index BM25"))
+    }
+
+    /// Returns up to `k` passages whose word shingles overlap those of `text` with
+    /// a Jaccard similarity of at least `threshold`, closest first, with that similarity.
+    async fn near_duplicates(
+        &self,
+        text: &str,
+        threshold: f32,
+        k: usize,
+    ) -> Result<Vec<(Passage, f32)>> {
+        let _ = (text, threshold, k);
+        Err(anyhow!(
+            "Détection des quasi-doublons non disponible sans index MinHash"
+        ))
+    }
+
+    /// Lists passages ordered by id, starting strictly after `after` when given,
+    /// restricted to those matching `filter`.
+    async fn list(
+        &self,
+        after: Option<&str>,
+        limit: usize,
+        filter: Option<&MetadataFilter>,
+    ) -> Result<Vec<Passage>>;
+
+    /// Fetches the given passages; unknown ids are skipped and order is not preserved.
+    async fn get_many(&self, ids: &[String]) -> Result<Vec<Passage>>;
+
+    async fn insert_document(&self, document: Document) -> Result<String>;
+
+    async fn get_document(&self, id: &str) -> Result<Option<Document>>;
+
+    /// A document whose text has the given `content_hash`, if any.
+    async fn find_document_by_hash(&self, content_hash: i64) -> Result<Option<Document>>;
+
+    /// A document stored by the source `source_id` whose text has the given `content_hash`, if any.
+    async fn find_source_document(
+        &self,
+        source_id: &str,
+        content_hash: i64,
+    ) -> Result<Option<Document>>;
+
+    /// Overwrites the stored document with the same id, returning `false` if there is none.
+    async fn replace_document(&self, document: &Document) -> Result<bool>;
+
+    async fn delete_document(&self, id: &str) -> Result<bool>;
+
+    /// Whether a document other than `document_id` also lists the passage.
+    ///
+    /// Passages are deduplicated on their hash, so identical text ingested in
+    /// two documents is stored once and must outlive the first deletion.
+    async fn passage_shared(&self, passage_id: &str, document_id: &str) -> Result<bool>;
+
+    /// Removes the passage from every document listing it, returning how many did.
+    async fn unlink_passage(&self, passage_id: &str) -> Result<usize>;
+
+    async fn insert_job(&self, job: Job) -> Result<String>;
+
+    async fn get_job(&self, id: &str) -> Result<Option<Job>>;
+
+    /// Overwrites the stored job with the same id, returning `false` if there is none.
+    async fn replace_job(&self, job: &Job) -> Result<bool>;
+
+    /// Queued and running jobs, oldest first.
+    async fn pending_jobs(&self) -> Result<Vec<Job>>;
+
+    async fn insert_source(&self, source: Source) -> Result<String>;
+
+    async fn get_source(&self, id: &str) -> Result<Option<Source>>;
+
+    /// Registered sources, oldest first.
+    async fn list_sources(&self) -> Result<Vec<Source>>;
+
+    /// Overwrites the stored source with the same id, returning `false` if there is none.
+    async fn replace_source(&self, source: &Source) -> Result<bool>;
+
+    async fn delete_source(&self, id: &str) -> Result<bool>;
+
+    /// Writes any state kept in memory to disk.
+    async fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+pub async fn from_config(config: &Config) -> Result<Box<dyn VectorStore>> {
+    let store = open_backend(config).await?;
+
+    if config.hnsw.is_none() && !config.lexical_index && config.near_duplicates.is_none() {
+        return Ok(store);
+    }
+
+    let ann = config
+        .hnsw
+        .as_ref()
+        .map(|hnsw| (hnsw.params, hnsw.path.clone().into()));
+    let store = IndexedStore::open(
+        store,
+        ann,
+        config.lexical_index,
+        config.near_duplicates.is_some(),
+    )
+    .await?;
+    Ok(Box::new(store))
+}
+
+async fn open_backend(config: &Config) -> Result<Box<dyn VectorStore>> {
+    match config.store_backend {
+        StoreBackend::Mongo => {
+            let uri = config
+                .cosmos_uri
+                .as_deref()
+                .ok_or_else(|| anyhow!("COSMOS_URI est requis pour le backend mongo"))?;
+            let store = MongoStore::connect(
+                uri,
+                &config.database_name,
+                &config.collection_name,
+                config.vector_search,
+                config.embedding_dimensions,
+            )
+            .await?;
+            Ok(Box::new(store))
+        }
+        StoreBackend::Memory => {
+            let store = MemoryStore::open(config.memory_store_path.as_deref())?;
+            Ok(Box::new(store))
+        }
+    }
+}
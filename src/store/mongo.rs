@@ -0,0 +1,557 @@
+use super::{Upserted, VectorStore};
+use crate::config::VectorSearchMode;
+use crate::filter::MetadataFilter;
+use crate::retrieval::rank_by_similarity;
+use crate::types::{self, Metadata, Passage};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use futures::TryStreamExt;
+use mongodb::bson::oid::ObjectId;
+use mongodb::bson::{doc, Bson, Document};
+use mongodb::error::{ErrorKind, WriteFailure};
+use mongodb::options::{ClientOptions, Compressor, FindOptions, IndexOptions};
+use mongodb::{Client, Collection, Database, IndexModel, SearchIndexModel, SearchIndexType};
+
+const VECTOR_INDEX_NAME: &str = "embedding_vector";
+
+/// Server-side vector stages don't know about metadata filters, so they return
+/// this many times `k` candidates before the `$match` stage, widened while too few match.
+const FILTER_OVERSAMPLE: usize = 10;
+
+/// Most candidates a vector stage may return; Atlas rejects more than 10,000.
+const MAX_NATIVE_CANDIDATES: usize = 10_000;
+
+/// Server-side vector search flavour detected at startup.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum NativeSearch {
+    /// Azure Cosmos DB for MongoDB `cosmosSearch` index.
+    Cosmos,
+    /// MongoDB Atlas `$vectorSearch` index.
+    Atlas,
+}
+
+pub struct MongoStore {
+    passages: Collection<Passage>,
+    documents: Collection<types::Document>,
+    jobs: Collection<types::Job>,
+    sources: Collection<types::Source>,
+    native_search: Option<NativeSearch>,
+}
+
+impl MongoStore {
+    pub async fn connect(
+        uri: &str,
+        db_name: &str,
+        collection_name: &str,
+        vector_search: VectorSearchMode,
+        dimensions: usize,
+    ) -> Result<Self> {
+        let mut client_opts = ClientOptions::parse(uri).await?;
+        client_opts.compressors = Some(vec![Compressor::Zstd { level: Some(1) }]);
+        client_opts.max_pool_size = Some(128);
+        client_opts.min_pool_size = Some(16);
+        client_opts.server_selection_timeout = Some(std::time::Duration::from_secs(2));
+        client_opts.app_name = Some("rag-api".into());
+
+        let client = Client::with_options(client_opts)?;
+
+        let db = client.database(db_name);
+        let passages = db.collection::<Passage>(collection_name);
+
+        let index = IndexModel::builder()
+            .keys(doc! { "hash": 1 })
+            .options(Some(IndexOptions::builder().unique(true).build()))
+            .build();
+        passages.create_index(index).await?;
+
+        let documents = db.collection::<types::Document>(&format!("{}_documents", collection_name));
+        documents
+            .create_index(
+                IndexModel::builder()
+                    .keys(doc! { "passage_ids": 1 })
+                    .build(),
+            )
+            .await?;
+        documents
+            .create_index(
+                IndexModel::builder()
+                    .keys(doc! { "content_hash": 1 })
+                    .build(),
+            )
+            .await?;
+
+        let jobs = db.collection::<types::Job>(&format!("{}_jobs", collection_name));
+        jobs.create_index(IndexModel::builder().keys(doc! { "status": 1 }).build())
+            .await?;
+
+        let sources = db.collection::<types::Source>(&format!("{}_sources", collection_name));
+
+        let native_search = match vector_search {
+            VectorSearchMode::Off => None,
+            VectorSearchMode::Cosmos => {
+                create_cosmos_index(&db, collection_name, dimensions).await?;
+                Some(NativeSearch::Cosmos)
+            }
+            VectorSearchMode::Atlas => {
+                create_atlas_index(&passages, dimensions).await?;
+                Some(NativeSearch::Atlas)
+            }
+            VectorSearchMode::Auto => {
+                if create_cosmos_index(&db, collection_name, dimensions)
+                    .await
+                    .is_ok()
+                {
+                    Some(NativeSearch::Cosmos)
+                } else if create_atlas_index(&passages, dimensions).await.is_ok() {
+                    Some(NativeSearch::Atlas)
+                } else {
+                    None
+                }
+            }
+        };
+
+        match native_search {
+            Some(kind) => println!("Recherche vectorielle native: {:?}", kind),
+            None => println!("Recherche vectorielle native indisponible, scan côté client"),
+        }
+
+        Ok(Self {
+            passages,
+            documents,
+            jobs,
+            sources,
+            native_search,
+        })
+    }
+
+    /// Server-side vector search, or `None` when a selective filter leaves fewer than `k`
+    /// matches among the most candidates the index returns, for a client-side scan to find.
+    async fn native_search(
+        &self,
+        kind: NativeSearch,
+        embedding: &[f32],
+        k: usize,
+        filter: Option<&MetadataFilter>,
+    ) -> Result<Option<Vec<(Passage, f32)>>> {
+        let Some(filter) = filter.filter(|f| !f.is_empty()) else {
+            return Ok(Some(self.vector_stage(kind, embedding, k, k, None).await?));
+        };
+
+        let total = self.passages.estimated_document_count().await? as usize;
+        let mut candidates = (k * FILTER_OVERSAMPLE).min(MAX_NATIVE_CANDIDATES);
+        loop {
+            let results = self
+                .vector_stage(kind, embedding, candidates, k, Some(filter))
+                .await?;
+            if results.len() >= k || candidates >= total {
+                return Ok(Some(results));
+            }
+            if candidates >= MAX_NATIVE_CANDIDATES {
+                return Ok(None);
+            }
+            candidates = (candidates * 4).min(MAX_NATIVE_CANDIDATES);
+        }
+    }
+
+    /// Runs the vector stage for `candidates` nearest passages, keeping the first `k`
+    /// that match `filter`.
+    async fn vector_stage(
+        &self,
+        kind: NativeSearch,
+        embedding: &[f32],
+        candidates: usize,
+        k: usize,
+        filter: Option<&MetadataFilter>,
+    ) -> Result<Vec<(Passage, f32)>> {
+        let vector: Vec<f64> = embedding.iter().map(|&x| x as f64).collect();
+
+        let mut pipeline = match kind {
+            NativeSearch::Cosmos => vec![
+                doc! {
+                    "$search": {
+                        "cosmosSearch": {
+                            "vector": vector,
+                            "path": "embedding",
+                            "k": candidates as i64,
+                        },
+                        "returnStoredSource": true,
+                    }
+                },
+                doc! {
+                    "$project": {
+                        "score": { "$meta": "searchScore" },
+                        "document": "$$ROOT",
+                    }
+                },
+            ],
+            NativeSearch::Atlas => vec![
+                doc! {
+                    "$vectorSearch": {
+                        "index": VECTOR_INDEX_NAME,
+                        "path": "embedding",
+                        "queryVector": vector,
+                        "numCandidates": (candidates * 20).min(MAX_NATIVE_CANDIDATES) as i64,
+                        "limit": candidates as i64,
+                    }
+                },
+                doc! {
+                    "$project": {
+                        "score": { "$meta": "vectorSearchScore" },
+                        "document": "$$ROOT",
+                    }
+                },
+            ],
+        };
+
+        if let Some(filter) = filter {
+            let mut matched = doc! {};
+            for (field, condition) in filter.to_bson() {
+                matched.insert(format!("document.{}", field), condition);
+            }
+            pipeline.push(doc! { "$match": matched });
+            pipeline.push(doc! { "$limit": k as i64 });
+        }
+
+        let mut cursor = self.passages.aggregate(pipeline).await?;
+        let mut results = Vec::new();
+
+        while let Some(hit) = cursor.try_next().await? {
+            let score = hit.get_f64("score").unwrap_or_default() as f32;
+            // Atlas reports cosine similarity rescaled to [0, 1].
+            let score = match kind {
+                NativeSearch::Cosmos => score,
+                NativeSearch::Atlas => score * 2.0 - 1.0,
+            };
+            let document = hit.get_document("document")?.clone();
+            results.push((mongodb::bson::from_document(document)?, score));
+        }
+
+        Ok(results)
+    }
+}
+
+/// Creates the `cosmosSearch` HNSW index; idempotent when the same index exists.
+async fn create_cosmos_index(
+    db: &Database,
+    collection_name: &str,
+    dimensions: usize,
+) -> Result<()> {
+    db.run_command(doc! {
+        "createIndexes": collection_name,
+        "indexes": [{
+            "name": VECTOR_INDEX_NAME,
+            "key": { "embedding": "cosmosSearch" },
+            "cosmosSearchOptions": {
+                "kind": "vector-hnsw",
+                "m": 16,
+                "efConstruction": 64,
+                "similarity": "COS",
+                "dimensions": dimensions as i64,
+            },
+        }],
+    })
+    .await?;
+    Ok(())
+}
+
+async fn create_atlas_index(passages: &Collection<Passage>, dimensions: usize) -> Result<()> {
+    let existing: Vec<Document> = passages
+        .list_search_indexes()
+        .name(VECTOR_INDEX_NAME)
+        .await?
+        .try_collect()
+        .await?;
+    if !existing.is_empty() {
+        return Ok(());
+    }
+
+    let model = SearchIndexModel::builder()
+        .name(VECTOR_INDEX_NAME.to_string())
+        .index_type(SearchIndexType::VectorSearch)
+        .definition(doc! {
+            "fields": [{
+                "type": "vector",
+                "path": "embedding",
+                "numDimensions": dimensions as i64,
+                "similarity": "cosine",
+            }],
+        })
+        .build();
+    passages.create_search_index(model).await?;
+    Ok(())
+}
+
+/// Whether `error` is a violation of a unique index.
+fn is_duplicate_key(error: &mongodb::error::Error) -> bool {
+    const DUPLICATE_KEY: i32 = 11000;
+    match error.kind.as_ref() {
+        ErrorKind::Write(WriteFailure::WriteError(e)) => e.code == DUPLICATE_KEY,
+        ErrorKind::Command(e) => e.code == DUPLICATE_KEY,
+        _ => false,
+    }
+}
+
+fn parse_id(id: &str) -> Result<ObjectId> {
+    ObjectId::parse_str(id).map_err(|e| anyhow!("Identifiant invalide {}: {}", id, e))
+}
+
+#[async_trait]
+impl VectorStore for MongoStore {
+    async fn insert(&self, passage: Passage) -> Result<String> {
+        let insert_result = self.passages.insert_one(&passage).await?;
+
+        match insert_result.inserted_id {
+            Bson::ObjectId(oid) => Ok(oid.to_hex()),
+            Bson::String(s) => Ok(s),
+            other => Err(anyhow!("Unexpected inserted_id type: {:?}", other)),
+        }
+    }
+
+    async fn upsert_by_hash(&self, passage: Passage) -> Result<Upserted> {
+        let hash = passage.hash.ok_or_else(|| anyhow!("Passage sans hash"))?;
+
+        if let Some(existing) = self.passages.find_one(doc! { "hash": hash }).await? {
+            let id = existing
+                .id
+                .ok_or_else(|| anyhow!("Passage existant sans _id"))?;
+            return Ok(Upserted::Existing(id.to_hex()));
+        }
+
+        match self.passages.insert_one(&passage).await {
+            Ok(result) => match result.inserted_id {
+                Bson::ObjectId(oid) => Ok(Upserted::Inserted(oid.to_hex())),
+                other => Err(anyhow!("Unexpected inserted_id type: {:?}", other)),
+            },
+            // An identical passage stored concurrently won the unique index on `hash`.
+            Err(e) if is_duplicate_key(&e) => {
+                let existing = self
+                    .passages
+                    .find_one(doc! { "hash": hash })
+                    .await?
+                    .and_then(|p| p.id)
+                    .ok_or_else(|| anyhow!("Passage en double introuvable: {}", e))?;
+                Ok(Upserted::Existing(existing.to_hex()))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn delete(&self, id: &str) -> Result<bool> {
+        let result = self
+            .passages
+            .delete_one(doc! { "_id": parse_id(id)? })
+            .await?;
+        Ok(result.deleted_count > 0)
+    }
+
+    async fn set_metadata(&self, id: &str, metadata: Option<Metadata>) -> Result<bool> {
+        let update = match metadata {
+            Some(metadata) => doc! { "$set": { "metadata": mongodb::bson::to_bson(&metadata)? } },
+            None => doc! { "$unset": { "metadata": "" } },
+        };
+        let result = self
+            .passages
+            .update_one(doc! { "_id": parse_id(id)? }, update)
+            .await?;
+        Ok(result.matched_count > 0)
+    }
+
+    async fn search(
+        &self,
+        embedding: &[f32],
+        k: usize,
+        filter: Option<&MetadataFilter>,
+        fetch_limit: Option<i64>,
+    ) -> Result<Vec<(Passage, f32)>> {
+        if let Some(kind) = self.native_search {
+            match self.native_search(kind, embedding, k, filter).await {
+                Ok(Some(results)) => return Ok(results),
+                Ok(None) => eprintln!(
+                    "Filtre trop sélectif pour la recherche vectorielle native, scan côté client"
+                ),
+                Err(e) => eprintln!(
+                    "Recherche vectorielle native en échec, scan côté client: {}",
+                    e
+                ),
+            }
+        }
+
+        let find_opts = FindOptions::builder()
+            .projection(doc! {
+                "text": 1,
+                "embedding": 1,
+                "metadata": 1,
+            })
+            .limit(fetch_limit.unwrap_or(2000))
+            .build();
+
+        let query = filter.map(MetadataFilter::to_bson).unwrap_or_default();
+        let mut cursor = self.passages.find(query).with_options(find_opts).await?;
+        let mut passages = Vec::new();
+
+        while let Some(p) = cursor.try_next().await? {
+            if !p.embedding.is_empty() {
+                passages.push(p);
+            }
+        }
+
+        Ok(rank_by_similarity(passages, embedding, k))
+    }
+
+    async fn list(
+        &self,
+        after: Option<&str>,
+        limit: usize,
+        filter: Option<&MetadataFilter>,
+    ) -> Result<Vec<Passage>> {
+        let mut query = filter.map(MetadataFilter::to_bson).unwrap_or_default();
+        if let Some(id) = after {
+            query.insert("_id", doc! { "$gt": parse_id(id)? });
+        }
+
+        let find_opts = FindOptions::builder()
+            .sort(doc! { "_id": 1 })
+            .limit(limit as i64)
+            .build();
+
+        let cursor = self.passages.find(query).with_options(find_opts).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn get_many(&self, ids: &[String]) -> Result<Vec<Passage>> {
+        let ids = ids
+            .iter()
+            .map(|id| parse_id(id))
+            .collect::<Result<Vec<_>>>()?;
+
+        let cursor = self.passages.find(doc! { "_id": { "$in": ids } }).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn insert_document(&self, document: types::Document) -> Result<String> {
+        match self.documents.insert_one(&document).await?.inserted_id {
+            Bson::ObjectId(oid) => Ok(oid.to_hex()),
+            other => Err(anyhow!("Unexpected inserted_id type: {:?}", other)),
+        }
+    }
+
+    async fn get_document(&self, id: &str) -> Result<Option<types::Document>> {
+        Ok(self
+            .documents
+            .find_one(doc! { "_id": parse_id(id)? })
+            .await?)
+    }
+
+    async fn find_document_by_hash(&self, content_hash: i64) -> Result<Option<types::Document>> {
+        Ok(self
+            .documents
+            .find_one(doc! { "content_hash": content_hash })
+            .await?)
+    }
+
+    async fn find_source_document(
+        &self,
+        source_id: &str,
+        content_hash: i64,
+    ) -> Result<Option<types::Document>> {
+        Ok(self
+            .documents
+            .find_one(doc! { "content_hash": content_hash, "source_id": source_id })
+            .await?)
+    }
+
+    async fn replace_document(&self, document: &types::Document) -> Result<bool> {
+        let id = document.id.ok_or_else(|| anyhow!("Document sans _id"))?;
+        let result = self
+            .documents
+            .replace_one(doc! { "_id": id }, document)
+            .await?;
+        Ok(result.matched_count > 0)
+    }
+
+    async fn delete_document(&self, id: &str) -> Result<bool> {
+        let result = self
+            .documents
+            .delete_one(doc! { "_id": parse_id(id)? })
+            .await?;
+        Ok(result.deleted_count > 0)
+    }
+
+    async fn passage_shared(&self, passage_id: &str, document_id: &str) -> Result<bool> {
+        let other = self
+            .documents
+            .find_one(doc! {
+                "_id": { "$ne": parse_id(document_id)? },
+                "passage_ids": passage_id,
+            })
+            .await?;
+        Ok(other.is_some())
+    }
+
+    async fn unlink_passage(&self, passage_id: &str) -> Result<usize> {
+        let result = self
+            .documents
+            .update_many(
+                doc! { "passage_ids": passage_id },
+                doc! { "$pull": { "passage_ids": passage_id } },
+            )
+            .await?;
+        Ok(result.modified_count as usize)
+    }
+
+    async fn insert_job(&self, job: types::Job) -> Result<String> {
+        match self.jobs.insert_one(&job).await?.inserted_id {
+            Bson::ObjectId(oid) => Ok(oid.to_hex()),
+            other => Err(anyhow!("Unexpected inserted_id type: {:?}", other)),
+        }
+    }
+
+    async fn get_job(&self, id: &str) -> Result<Option<types::Job>> {
+        Ok(self.jobs.find_one(doc! { "_id": parse_id(id)? }).await?)
+    }
+
+    async fn replace_job(&self, job: &types::Job) -> Result<bool> {
+        let id = job.id.ok_or_else(|| anyhow!("Tâche sans _id"))?;
+        let result = self.jobs.replace_one(doc! { "_id": id }, job).await?;
+        Ok(result.matched_count > 0)
+    }
+
+    async fn pending_jobs(&self) -> Result<Vec<types::Job>> {
+        let cursor = self
+            .jobs
+            .find(doc! { "status": { "$in": ["queued", "running"] } })
+            .sort(doc! { "_id": 1 })
+            .await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn insert_source(&self, source: types::Source) -> Result<String> {
+        match self.sources.insert_one(&source).await?.inserted_id {
+            Bson::ObjectId(oid) => Ok(oid.to_hex()),
+            other => Err(anyhow!("Unexpected inserted_id type: {:?}", other)),
+        }
+    }
+
+    async fn get_source(&self, id: &str) -> Result<Option<types::Source>> {
+        Ok(self.sources.find_one(doc! { "_id": parse_id(id)? }).await?)
+    }
+
+    async fn list_sources(&self) -> Result<Vec<types::Source>> {
+        let cursor = self.sources.find(doc! {}).sort(doc! { "_id": 1 }).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn replace_source(&self, source: &types::Source) -> Result<bool> {
+        let id = source.id.ok_or_else(|| anyhow!("Source sans _id"))?;
+        let result = self.sources.replace_one(doc! { "_id": id }, source).await?;
+        Ok(result.matched_count > 0)
+    }
+
+    async fn delete_source(&self, id: &str) -> Result<bool> {
+        let result = self
+            .sources
+            .delete_one(doc! { "_id": parse_id(id)? })
+            .await?;
+        Ok(result.deleted_count > 0)
+    }
+}
@@ -0,0 +1,183 @@
+//! Helpers shared by the unit tests.
+
+use crate::chunking::{ChunkingConfig, ChunkingStrategy, DEFAULT_BREAKPOINT_PERCENTILE};
+use crate::config::{Config, EmbeddingConfig, StoreBackend, VectorSearchMode};
+use crate::embedding::{Embedder, EmbeddingService};
+use crate::jobs::JobQueue;
+use crate::store::MemoryStore;
+use crate::AppState;
+use candle_core::{DType, Device};
+use candle_nn::{VarBuilder, VarMap};
+use candle_transformers::models::bert::{self, BertModel};
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Application state with an in-memory store, the test tokenizer and a tiny randomly
+/// initialized BERT, whose embeddings are valid vectors but carry no meaning.
+pub fn app_state() -> AppState {
+    let device = Device::Cpu;
+    let model_config = bert::Config {
+        vocab_size: 3,
+        hidden_size: 8,
+        num_hidden_layers: 1,
+        num_attention_heads: 2,
+        intermediate_size: 16,
+        ..Default::default()
+    };
+    let vars = VarMap::new();
+    let model = BertModel::load(
+        VarBuilder::from_varmap(&vars, DType::F32, &device),
+        &model_config,
+    )
+    .unwrap();
+    let tokenizer = crate::chunking::tests::tokenizer();
+
+    let embedding = EmbeddingConfig {
+        max_batch_size: 8,
+        max_wait: Duration::from_millis(1),
+        workers: 1,
+    };
+    let config = Config {
+        database_name: "test".into(),
+        collection_name: "passages".into(),
+        llm_uri: "http://127.0.0.1:9".into(),
+        cosmos_uri: None,
+        store_backend: StoreBackend::Memory,
+        memory_store_path: None,
+        hnsw: None,
+        lexical_index: false,
+        vector_search: VectorSearchMode::Off,
+        embedding_dimensions: model_config.hidden_size,
+        embedding,
+        rerank: None,
+        near_duplicates: None,
+        chunking: ChunkingConfig {
+            strategy: ChunkingStrategy::Auto,
+            max_tokens: 200,
+            overlap_tokens: 30,
+            min_tokens: 0,
+            breakpoint_percentile: DEFAULT_BREAKPOINT_PERCENTILE,
+        },
+        source_directories: Vec::new(),
+        crawl_private_addresses: true,
+    };
+
+    AppState {
+        embedder: EmbeddingService::start(
+            Embedder::new(model, tokenizer.clone(), device),
+            embedding,
+        )
+        .unwrap(),
+        tokenizer,
+        store: Box::new(MemoryStore::open(None).unwrap()),
+        reranker: None,
+        jobs: JobQueue::new().0,
+        config,
+    }
+}
+
+/// Request received by a `FixtureServer`.
+#[derive(Clone, Debug)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    /// Header names are lowercased.
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// HTTP/1.1 server on a local port, answering each request with what `respond`
+/// returns for it, one connection at a time.
+pub struct FixtureServer {
+    pub url: String,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl FixtureServer {
+    pub fn start(respond: impl Fn(&Request) -> Vec<u8> + Send + 'static) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let received = requests.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { continue };
+                let Some(request) = read_request(&mut BufReader::new(&stream)) else {
+                    continue;
+                };
+                let response = respond(&request);
+                received.lock().unwrap().push(request);
+                let _ = stream.write_all(&response);
+            }
+        });
+
+        Self { url, requests }
+    }
+
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+fn read_request(reader: &mut impl BufRead) -> Option<Request> {
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let mut parts = line.split_whitespace();
+    let (method, path) = (parts.next()?.to_string(), parts.next()?.to_string());
+
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).ok()?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line.split_once(':')?;
+        headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+    }
+
+    let length = headers
+        .iter()
+        .find(|(n, _)| n == "content-length")
+        .and_then(|(_, v)| v.parse().ok())
+        .unwrap_or(0);
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).ok()?;
+
+    Some(Request {
+        method,
+        path,
+        headers,
+        body,
+    })
+}
+
+/// Raw HTTP response closing the connection.
+pub fn response(status: u16, headers: &[(&str, &str)], body: &[u8]) -> Vec<u8> {
+    let mut response = format!(
+        "HTTP/1.1 {} Fixture\r\nContent-Length: {}\r\nConnection: close\r\n",
+        status,
+        body.len()
+    );
+    for (name, value) in headers {
+        response.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    response.push_str("\r\n");
+
+    let mut response = response.into_bytes();
+    response.extend_from_slice(body);
+    response
+}
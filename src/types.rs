@@ -1,5 +1,10 @@
-use mongodb::bson::{doc, oid::ObjectId};
+use crate::chunking::{ChunkingConfig, ChunkingStrategy};
+use crate::filter::MetadataFilter;
+use mongodb::bson::{doc, oid::ObjectId, Bson, DateTime};
 use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::str::FromStr;
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct Passage {
@@ -16,7 +21,34 @@ pub struct Passage {
     pub hash: Option<i64>,
 }
 
+/// Ingested text as a whole, grouping the passages it was segmented into.
 #[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Document {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Metadata>,
+
+    pub content_hash: i64,
+    pub passage_ids: Vec<String>,
+
+    /// How the text was cut, reused when the document is updated.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunking: Option<ChunkingConfig>,
+
+    /// Source whose synchronization stored the document, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_id: Option<String>,
+
+    #[serde(with = "mongodb::bson::serde_helpers::bson_datetime_as_rfc3339_string")]
+    pub created_at: DateTime,
+
+    #[serde(with = "mongodb::bson::serde_helpers::bson_datetime_as_rfc3339_string")]
+    pub updated_at: DateTime,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
 pub struct Metadata {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub title: Option<String>,
@@ -29,6 +61,127 @@ pub struct Metadata {
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub url: Option<String>,
+
+    /// Page of the source file the passage comes from, numbered from 1.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page: Option<u32>,
+
+    /// Headings the passage sits under, e.g. `Install > Linux > Proxy`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub breadcrumb: Option<String>,
+
+    /// Path of the source file in its repository, for ingested code.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+
+    /// Function, type or impl the code passage defines, e.g. `Store::get`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub symbol: Option<String>,
+
+    /// First and last lines of the code passage in its file, numbered from 1.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line_start: Option<u32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line_end: Option<u32>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+
+    /// User-defined fields such as product, version, language or department.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub extra: BTreeMap<String, FieldValue>,
+}
+
+impl Metadata {
+    /// Value of a filterable field by name; user-defined fields are addressed as `extra.<name>`.
+    pub fn field(&self, name: &str) -> Option<FieldValue> {
+        let text = |value: &Option<String>| value.clone().map(FieldValue::Text);
+        match name {
+            "title" => text(&self.title),
+            "source" => text(&self.source),
+            "date" => text(&self.date),
+            "url" => text(&self.url),
+            "page" => self.page.map(|page| FieldValue::Integer(page.into())),
+            "breadcrumb" => text(&self.breadcrumb),
+            "path" => text(&self.path),
+            "symbol" => text(&self.symbol),
+            "line_start" => self.line_start.map(|line| FieldValue::Integer(line.into())),
+            "line_end" => self.line_end.map(|line| FieldValue::Integer(line.into())),
+            // Empty tags are not stored, so they count as absent as they do in MongoDB.
+            "tags" => (!self.tags.is_empty()).then(|| {
+                FieldValue::List(self.tags.iter().cloned().map(FieldValue::Text).collect())
+            }),
+            _ => name
+                .strip_prefix("extra.")
+                .and_then(|key| self.extra.get(key))
+                .cloned(),
+        }
+    }
+
+    /// Fills in the descriptive fields this metadata lacks from `other` and adds its
+    /// tags and user-defined fields. Location fields stay as they are, since they
+    /// describe where this passage sits and go into its embedding.
+    pub fn merge(&mut self, other: &Metadata) {
+        for (field, value) in [
+            (&mut self.title, &other.title),
+            (&mut self.source, &other.source),
+            (&mut self.date, &other.date),
+            (&mut self.url, &other.url),
+        ] {
+            if field.is_none() {
+                field.clone_from(value);
+            }
+        }
+        for tag in &other.tags {
+            if !self.tags.contains(tag) {
+                self.tags.push(tag.clone());
+            }
+        }
+        for (key, value) in &other.extra {
+            self.extra
+                .entry(key.clone())
+                .or_insert_with(|| value.clone());
+        }
+    }
+}
+
+/// Typed value of a user-defined metadata field.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum FieldValue {
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    Text(String),
+    List(Vec<FieldValue>),
+}
+
+impl FieldValue {
+    /// Orders scalars of compatible types; integers and floats compare numerically.
+    pub fn compare(&self, other: &FieldValue) -> Option<Ordering> {
+        match (self, other) {
+            (FieldValue::Bool(a), FieldValue::Bool(b)) => Some(a.cmp(b)),
+            (FieldValue::Integer(a), FieldValue::Integer(b)) => Some(a.cmp(b)),
+            (FieldValue::Integer(a), FieldValue::Float(b)) => (*a as f64).partial_cmp(b),
+            (FieldValue::Float(a), FieldValue::Integer(b)) => a.partial_cmp(&(*b as f64)),
+            (FieldValue::Float(a), FieldValue::Float(b)) => a.partial_cmp(b),
+            (FieldValue::Text(a), FieldValue::Text(b)) => Some(a.cmp(b)),
+            _ => None,
+        }
+    }
+}
+
+impl From<&FieldValue> for Bson {
+    fn from(value: &FieldValue) -> Self {
+        match value {
+            FieldValue::Bool(b) => Bson::Boolean(*b),
+            FieldValue::Integer(i) => Bson::Int64(*i),
+            FieldValue::Float(f) => Bson::Double(*f),
+            FieldValue::Text(s) => Bson::String(s.clone()),
+            FieldValue::List(values) => Bson::Array(values.iter().map(Bson::from).collect()),
+        }
+    }
 }
 
 #[derive(Deserialize)]
@@ -37,19 +190,640 @@ pub struct IngestRequest {
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub metadata: Option<Metadata>,
+
+    #[serde(default)]
+    pub chunking: Option<ChunkingOptions>,
+
+    /// Queues the ingestion and answers `202 Accepted` with a job id right away.
+    #[serde(default, rename = "async")]
+    pub background: bool,
+
+    /// URL POSTed the job summary when an asynchronous ingestion finishes.
+    #[serde(default)]
+    pub callback_url: Option<String>,
+
+    /// Key signing the callback body with HMAC-SHA256.
+    #[serde(default)]
+    pub callback_secret: Option<String>,
+}
+
+/// Per-request overrides of the server's chunking defaults.
+#[derive(Deserialize, Clone, Copy, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ChunkingOptions {
+    pub strategy: Option<ChunkingStrategy>,
+    pub max_tokens: Option<usize>,
+    pub overlap_tokens: Option<usize>,
+    pub min_tokens: Option<usize>,
+    pub breakpoint_percentile: Option<u8>,
+}
+
+impl ChunkingOptions {
+    /// Applies the overrides on top of `defaults` and checks the result.
+    pub fn resolve(&self, defaults: ChunkingConfig) -> Result<ChunkingConfig, String> {
+        let config = ChunkingConfig {
+            strategy: self.strategy.unwrap_or(defaults.strategy),
+            max_tokens: self.max_tokens.unwrap_or(defaults.max_tokens),
+            overlap_tokens: self.overlap_tokens.unwrap_or(defaults.overlap_tokens),
+            min_tokens: self.min_tokens.unwrap_or(defaults.min_tokens),
+            breakpoint_percentile: self
+                .breakpoint_percentile
+                .unwrap_or(defaults.breakpoint_percentile),
+        };
+        config.validate()?;
+        Ok(config)
+    }
 }
 
 #[derive(Serialize)]
 pub struct IngestResponse {
+    pub document_id: String,
     pub passage_ids: Vec<String>,
     pub count: usize,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub near_duplicates: Vec<NearDuplicate>,
+
+    /// Outcome of every passage the text was segmented into.
+    pub passages: Vec<PassageOutcome>,
+}
+
+impl IngestResponse {
+    /// Whether some passages could not be stored.
+    pub fn is_partial(&self) -> bool {
+        self.passages
+            .iter()
+            .any(|p| p.status == PassageStatus::Failed)
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PassageStatus {
+    /// Embedded and stored.
+    Inserted,
+    /// Already stored, identically or as the near-duplicate it was merged into.
+    Duplicate,
+    /// Left out as a near-duplicate.
+    Skipped,
+    /// Kept from the previous version of the document.
+    Unchanged,
+    Failed,
+}
+
+/// What became of one passage of an ingested text.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct PassageOutcome {
+    /// Position of the passage among those the text was segmented into.
+    pub index: usize,
+
+    /// Stored passage it ended up as, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+
+    pub status: PassageStatus,
+
+    /// Length of its text in tokens of the embedding model's tokenizer.
+    pub tokens: usize,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// What ingestion does with a passage nearly identical to one already stored.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NearDuplicatePolicy {
+    /// Leave the passage out of the document.
+    Skip,
+    /// Reference the stored passage instead, adding the new metadata to it.
+    Merge,
+    /// Store the passage anyway; it is only reported.
+    Keep,
+}
+
+impl FromStr for NearDuplicatePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(NearDuplicatePolicy::Skip),
+            "merge" => Ok(NearDuplicatePolicy::Merge),
+            "keep" => Ok(NearDuplicatePolicy::Keep),
+            other => Err(format!(
+                "politique inconnue: {} (skip, merge ou keep)",
+                other
+            )),
+        }
+    }
+}
+
+/// Passage found nearly identical to a stored one while ingesting.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct NearDuplicate {
+    /// Position of the passage among those the text was segmented into.
+    pub index: usize,
+    /// Stored passage it resembles.
+    pub duplicate_of: String,
+    pub similarity: f32,
+    pub action: NearDuplicatePolicy,
+}
+
+#[derive(Serialize)]
+pub struct JobAccepted {
+    pub job_id: String,
+}
+
+#[derive(Deserialize)]
+pub struct UrlIngestRequest {
+    /// Page or sitemap the crawl starts from.
+    pub url: String,
+
+    /// Link hops followed from the start page; 0 ingests it alone.
+    #[serde(default)]
+    pub depth: Option<usize>,
+
+    /// Bound on the pages fetched, sitemaps aside.
+    #[serde(default)]
+    pub max_pages: Option<usize>,
+
+    /// Added to every page, whose `url` and `title` are filled in from the page itself.
+    #[serde(default)]
+    pub metadata: Option<Metadata>,
+
+    #[serde(default)]
+    pub chunking: Option<ChunkingOptions>,
+}
+
+/// Outcome of a crawl, page by page.
+#[derive(Serialize, Default)]
+pub struct CrawlResponse {
+    pub pages: Vec<CrawledPage>,
+    pub skipped: Vec<SkippedPage>,
+    pub failures: Vec<CrawlFailure>,
+}
+
+#[derive(Serialize)]
+pub struct CrawledPage {
+    pub url: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+
+    pub document_id: String,
+    pub count: usize,
+}
+
+#[derive(Serialize)]
+pub struct SkippedPage {
+    pub url: String,
+    pub reason: SkipReason,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    /// Excluded by robots.txt.
+    Disallowed,
+    /// Marked `noindex` by the page.
+    Noindex,
+    /// Text already stored as a document.
+    Unchanged,
+    /// Content type that cannot be extracted.
+    Unsupported,
+    /// Redirected to another domain.
+    OffDomain,
+    /// Answered 404 Not Found or 410 Gone.
+    Gone,
+}
+
+#[derive(Serialize)]
+pub struct CrawlFailure {
+    pub url: String,
+    pub error: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Completed,
+    Failed,
+}
+
+impl JobStatus {
+    pub fn is_finished(self) -> bool {
+        matches!(self, JobStatus::Completed | JobStatus::Failed)
+    }
+}
+
+/// Passages of a job: segmented, then embedded and stored, failed at either step, or
+/// skipped as near-duplicates.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default)]
+pub struct JobProgress {
+    pub total: usize,
+    pub embedded: usize,
+    pub stored: usize,
+    pub failed: usize,
+
+    /// Near-duplicates left out of the store, whether skipped or merged.
+    #[serde(default)]
+    pub skipped: usize,
+}
+
+/// Ingestion run in the background, persisted so that it resumes after a restart.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Job {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+
+    pub status: JobStatus,
+
+    /// Text to ingest, dropped once the job is finished.
+    #[serde(default)]
+    pub text: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Metadata>,
+
+    pub chunking: ChunkingConfig,
+
+    #[serde(default)]
+    pub progress: JobProgress,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub document_id: Option<String>,
+
+    #[serde(default)]
+    pub passage_ids: Vec<String>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub near_duplicates: Vec<NearDuplicate>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub passages: Vec<PassageOutcome>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub callback: Option<Callback>,
+
+    #[serde(with = "mongodb::bson::serde_helpers::bson_datetime_as_rfc3339_string")]
+    pub created_at: DateTime,
+
+    #[serde(with = "mongodb::bson::serde_helpers::bson_datetime_as_rfc3339_string")]
+    pub updated_at: DateTime,
+}
+
+/// Webhook notified when a job finishes. Its secret is held by the job queue, in
+/// memory only.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Callback {
+    pub url: String,
+
+    /// Whether the notification is to be signed.
+    #[serde(default)]
+    pub signed: bool,
+
+    /// Outcome of the notification, once its attempts are over.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delivery: Option<CallbackDelivery>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct CallbackDelivery {
+    pub delivered: bool,
+    pub attempts: u32,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Body POSTed to a job's callback URL.
+#[derive(Serialize)]
+pub struct JobNotification {
+    pub job_id: String,
+    pub status: JobStatus,
+
+    /// Document and passages, for a completed job.
+    #[serde(flatten)]
+    pub result: Option<IngestResponse>,
+
+    /// Passages that could not be embedded or stored.
+    pub failed: usize,
+    pub errors: Vec<String>,
+}
+
+/// Job as exposed by `GET /jobs/{id}`, without its text.
+#[derive(Serialize)]
+pub struct JobResponse {
+    pub id: String,
+    pub status: JobStatus,
+    pub progress: JobProgress,
+    pub errors: Vec<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub document_id: Option<String>,
+
+    pub passage_ids: Vec<String>,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub near_duplicates: Vec<NearDuplicate>,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub passages: Vec<PassageOutcome>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub callback_url: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub callback_delivery: Option<CallbackDelivery>,
+
+    #[serde(with = "mongodb::bson::serde_helpers::bson_datetime_as_rfc3339_string")]
+    pub created_at: DateTime,
+
+    #[serde(with = "mongodb::bson::serde_helpers::bson_datetime_as_rfc3339_string")]
+    pub updated_at: DateTime,
+}
+
+impl From<Job> for JobResponse {
+    fn from(job: Job) -> Self {
+        Self {
+            id: job.id.map(|id| id.to_hex()).unwrap_or_default(),
+            status: job.status,
+            progress: job.progress,
+            errors: job.errors,
+            document_id: job.document_id,
+            passage_ids: job.passage_ids,
+            near_duplicates: job.near_duplicates,
+            passages: job.passages,
+            callback_url: job.callback.as_ref().map(|c| c.url.clone()),
+            callback_delivery: job.callback.and_then(|c| c.delivery),
+            created_at: job.created_at,
+            updated_at: job.updated_at,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SourceKind {
+    /// Web page crawled along its same-domain links.
+    Url,
+    /// Sitemap whose listed pages are crawled.
+    Sitemap,
+    /// Directory or archive on the server's file system.
+    Directory,
+}
+
+/// Document ingested from a source, under its URL or relative path.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct SourceDocument {
+    pub key: String,
+    pub document_id: String,
+    pub content_hash: i64,
+}
+
+/// Outcome of one synchronization of a source, in documents.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct SyncReport {
+    pub added: usize,
+    pub updated: usize,
+    pub deleted: usize,
+    pub unchanged: usize,
+    pub failed: usize,
+    pub errors: Vec<String>,
+
+    #[serde(with = "mongodb::bson::serde_helpers::bson_datetime_as_rfc3339_string")]
+    pub finished_at: DateTime,
+}
+
+/// Registered location re-ingested on a schedule.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Source {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+
+    pub kind: SourceKind,
+
+    /// URL of the page or sitemap, or path of the directory or archive.
+    pub location: String,
+
+    pub interval_minutes: u64,
+
+    /// Crawl bounds, for web sources.
+    pub depth: usize,
+    pub max_pages: usize,
+
+    /// Globs selecting files, for directories.
+    #[serde(default)]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Metadata>,
+
+    pub chunking: ChunkingConfig,
+
+    #[serde(default)]
+    pub documents: Vec<SourceDocument>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_sync: Option<SyncReport>,
+
+    #[serde(with = "mongodb::bson::serde_helpers::bson_datetime_as_rfc3339_string")]
+    pub next_sync_at: DateTime,
+
+    #[serde(with = "mongodb::bson::serde_helpers::bson_datetime_as_rfc3339_string")]
+    pub created_at: DateTime,
+}
+
+#[derive(Deserialize)]
+pub struct SourceRequest {
+    pub kind: SourceKind,
+    pub location: String,
+    pub interval_minutes: u64,
+
+    #[serde(default)]
+    pub depth: Option<usize>,
+    #[serde(default)]
+    pub max_pages: Option<usize>,
+
+    #[serde(default)]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+
+    #[serde(default)]
+    pub metadata: Option<Metadata>,
+
+    #[serde(default)]
+    pub chunking: Option<ChunkingOptions>,
+}
+
+/// Source as exposed by the API, with the number of its documents rather than their list.
+#[derive(Serialize)]
+pub struct SourceResponse {
+    pub id: String,
+    pub kind: SourceKind,
+    pub location: String,
+    pub interval_minutes: u64,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub depth: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_pages: Option<usize>,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Metadata>,
+
+    pub chunking: ChunkingConfig,
+    pub documents: usize,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_sync: Option<SyncReport>,
+
+    #[serde(with = "mongodb::bson::serde_helpers::bson_datetime_as_rfc3339_string")]
+    pub next_sync_at: DateTime,
+
+    #[serde(with = "mongodb::bson::serde_helpers::bson_datetime_as_rfc3339_string")]
+    pub created_at: DateTime,
+}
+
+impl From<Source> for SourceResponse {
+    fn from(source: Source) -> Self {
+        let web = source.kind != SourceKind::Directory;
+        Self {
+            id: source.id.map(|id| id.to_hex()).unwrap_or_default(),
+            kind: source.kind,
+            location: source.location,
+            interval_minutes: source.interval_minutes,
+            depth: web.then_some(source.depth),
+            max_pages: web.then_some(source.max_pages),
+            include: source.include,
+            exclude: source.exclude,
+            metadata: source.metadata,
+            chunking: source.chunking,
+            documents: source.documents.len(),
+            last_sync: source.last_sync,
+            next_sync_at: source.next_sync_at,
+            created_at: source.created_at,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct DocumentUpdateResponse {
+    pub document: Document,
+    pub added_passage_ids: Vec<String>,
+    pub removed_passage_ids: Vec<String>,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub near_duplicates: Vec<NearDuplicate>,
+
+    /// Outcome of every passage the new text was segmented into.
+    pub passages: Vec<PassageOutcome>,
+}
+
+impl DocumentUpdateResponse {
+    /// Whether some new passages could not be stored.
+    pub fn is_partial(&self) -> bool {
+        self.passages
+            .iter()
+            .any(|p| p.status == PassageStatus::Failed)
+    }
+}
+
+/// Passage as exposed by the inspection endpoints, with a plain string id.
+#[derive(Serialize)]
+pub struct PassageResponse {
+    pub id: String,
+    pub text: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Metadata>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash: Option<i64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub embedding: Option<Vec<f32>>,
+}
+
+impl PassageResponse {
+    pub fn new(passage: Passage, include_embedding: bool) -> Self {
+        Self {
+            id: passage.id.map(|id| id.to_hex()).unwrap_or_default(),
+            text: passage.text,
+            metadata: passage.metadata,
+            hash: passage.hash,
+            embedding: include_embedding.then_some(passage.embedding),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PassageQuery {
+    #[serde(default)]
+    pub include_embedding: bool,
+}
+
+#[derive(Deserialize)]
+pub struct PassageListQuery {
+    pub source: Option<String>,
+
+    /// `next_cursor` of the previous page.
+    pub cursor: Option<String>,
+
+    pub limit: Option<usize>,
+
+    #[serde(default)]
+    pub include_embedding: bool,
+}
+
+#[derive(Serialize)]
+pub struct PassageListResponse {
+    pub passages: Vec<PassageResponse>,
+
+    /// Present when more passages may follow; pass it as `cursor` to get them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchMode {
+    #[default]
+    Vector,
+    Lexical,
+    Hybrid,
 }
 
 #[derive(Deserialize)]
 pub struct QuestionRequest {
     pub question: String,
+
+    #[serde(default)]
+    pub mode: SearchMode,
+
+    /// Enables maximal marginal relevance; 1.0 is pure relevance, 0.0 pure diversity.
+    #[serde(default)]
+    pub mmr_lambda: Option<f32>,
+
+    /// Restricts the search to passages whose metadata matches.
+    #[serde(default)]
+    pub filter: Option<MetadataFilter>,
 }
 
+#[allow(dead_code)]
 #[derive(Serialize)]
 pub struct AnswerResponse {
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -75,6 +849,7 @@ pub struct LLMStreamResponse {
 }
 
 #[derive(Deserialize, Debug)]
+#[allow(dead_code)]
 pub struct LLMChoice {
     pub index: usize,
     pub finish_reason: Option<String>,
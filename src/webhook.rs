@@ -0,0 +1,246 @@
+use crate::types::{CallbackDelivery, IngestResponse, Job, JobNotification, JobStatus};
+use crate::AppState;
+use actix_web::web;
+use hmac::{Hmac, KeyInit, Mac};
+use reqwest::StatusCode;
+use sha2::Sha256;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Attempts per notification, the first included.
+const MAX_ATTEMPTS: u32 = 6;
+
+/// Wait before the first retry, doubled after each failure.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Header carrying `sha256=<hex HMAC of "<timestamp>.<body>">` when the callback has a secret.
+const SIGNATURE_HEADER: &str = "X-Signature-256";
+
+/// Header carrying the signed timestamp, in seconds since the Unix epoch, so that
+/// receivers can reject replayed notifications.
+const TIMESTAMP_HEADER: &str = "X-Signature-Timestamp";
+
+/// Checks that a callback URL is an absolute http(s) URL.
+pub fn validate_url(url: &str) -> Result<(), String> {
+    let parsed = reqwest::Url::parse(url).map_err(|e| format!("callback_url invalide: {}", e))?;
+    match parsed.scheme() {
+        "http" | "https" => Ok(()),
+        scheme => Err(format!(
+            "callback_url invalide: schéma {} non pris en charge",
+            scheme
+        )),
+    }
+}
+
+fn signature(secret: &str, timestamp: u64, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepte toute clé");
+    mac.update(format!("{}.", timestamp).as_bytes());
+    mac.update(body);
+    let digest = mac.finalize().into_bytes();
+    let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("sha256={}", hex)
+}
+
+/// Whether a failed attempt may succeed later: server errors and rate limiting are
+/// retried, other statuses are final.
+fn is_transient(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+/// POSTs `body` until the endpoint answers with a success status, backing off
+/// exponentially between attempts after network errors and transient statuses.
+async fn deliver(url: &str, secret: Option<&str>, body: Vec<u8>) -> CallbackDelivery {
+    let client = reqwest::Client::new();
+    let mut backoff = INITIAL_BACKOFF;
+    let mut error = None;
+
+    for attempt in 1..=MAX_ATTEMPTS {
+        let mut request = client
+            .post(url)
+            .timeout(REQUEST_TIMEOUT)
+            .header("Content-Type", "application/json")
+            .body(body.clone());
+        if let Some(secret) = secret {
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            request = request
+                .header(TIMESTAMP_HEADER, timestamp)
+                .header(SIGNATURE_HEADER, signature(secret, timestamp, &body));
+        }
+
+        match request.send().await {
+            Ok(response) if response.status().is_success() => {
+                return CallbackDelivery {
+                    delivered: true,
+                    attempts: attempt,
+                    error: None,
+                };
+            }
+            Ok(response) if !is_transient(response.status()) => {
+                return CallbackDelivery {
+                    delivered: false,
+                    attempts: attempt,
+                    error: Some(format!("Statut {}", response.status())),
+                };
+            }
+            Ok(response) => error = Some(format!("Statut {}", response.status())),
+            Err(e) => error = Some(e.to_string()),
+        }
+
+        if attempt < MAX_ATTEMPTS {
+            actix_web::rt::time::sleep(backoff).await;
+            backoff *= 2;
+        }
+    }
+
+    CallbackDelivery {
+        delivered: false,
+        attempts: MAX_ATTEMPTS,
+        error,
+    }
+}
+
+/// Sends the summary of a finished job to its callback, signed with `secret`, then
+/// records the outcome on the job.
+pub async fn notify(state: web::Data<AppState>, job: Job, secret: Option<String>) {
+    let (Some(id), Some(callback)) = (job.id, job.callback.clone()) else {
+        return;
+    };
+    let job_id = id.to_hex();
+
+    let notification = JobNotification {
+        job_id: job_id.clone(),
+        status: job.status,
+        result: (job.status == JobStatus::Completed).then(|| IngestResponse {
+            document_id: job.document_id.clone().unwrap_or_default(),
+            count: job.passage_ids.len(),
+            passage_ids: job.passage_ids.clone(),
+            near_duplicates: job.near_duplicates.clone(),
+            passages: job.passages.clone(),
+        }),
+        failed: job.progress.failed,
+        errors: job.errors.clone(),
+    };
+    let body = match serde_json::to_vec(&notification) {
+        Ok(body) => body,
+        Err(e) => {
+            eprintln!("Impossible de sérialiser la notification {}: {}", job_id, e);
+            return;
+        }
+    };
+
+    let delivery = if callback.signed && secret.is_none() {
+        // The secret was only held in memory: rather not send the summary unsigned.
+        CallbackDelivery {
+            delivered: false,
+            attempts: 0,
+            error: Some("Clé de signature perdue au redémarrage du serveur".into()),
+        }
+    } else {
+        deliver(&callback.url, secret.as_deref(), body).await
+    };
+    if let Some(error) = &delivery.error {
+        eprintln!(
+            "Notification de la tâche {} à {} abandonnée après {} tentatives: {}",
+            job_id, callback.url, delivery.attempts, error
+        );
+    }
+
+    let result = match state.store.get_job(&job_id).await {
+        Ok(Some(mut job)) => {
+            if let Some(callback) = job.callback.as_mut() {
+                callback.delivery = Some(delivery);
+            }
+            state.store.replace_job(&job).await.map(|_| ())
+        }
+        Ok(None) => Ok(()),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        eprintln!(
+            "Impossible d'enregistrer la notification de la tâche {}: {}",
+            job_id, e
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{response, FixtureServer};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Answers with `statuses` in turn, then with the last one.
+    fn server(statuses: &'static [u16]) -> FixtureServer {
+        let calls = AtomicUsize::new(0);
+        FixtureServer::start(move |_| {
+            let call = calls.fetch_add(1, Ordering::Relaxed);
+            response(statuses[call.min(statuses.len() - 1)], &[], b"")
+        })
+    }
+
+    #[test]
+    fn signature_covers_timestamp_and_body() {
+        let signed = signature("s3cr3t", 1_700_000_000, b"{}");
+        assert_eq!(
+            signed,
+            "sha256=dd8508e44d9a9f82f2690fb7dff1da8a6ae99700d98a23a4e7e1c307af3cb6cb"
+        );
+        assert_ne!(signed, signature("s3cr3t", 1_700_000_001, b"{}"));
+        assert_ne!(signed, signature("s3cr3t", 1_700_000_000, b"[]"));
+        assert_ne!(signed, signature("autre", 1_700_000_000, b"{}"));
+    }
+
+    #[actix_web::test]
+    async fn signed_delivery() {
+        let server = server(&[200]);
+
+        let url = format!("{}/hooks/ingest", server.url);
+
+        let delivery = deliver(&url, Some("s3cr3t"), b"{\"a\":1}".to_vec()).await;
+
+        assert!(delivery.delivered);
+        assert_eq!(delivery.attempts, 1);
+        let request = &server.requests()[0];
+        assert_eq!(
+            (request.method.as_str(), request.path.as_str()),
+            ("POST", "/hooks/ingest")
+        );
+        assert_eq!(request.body, b"{\"a\":1}");
+        let timestamp: u64 = request
+            .header("x-signature-timestamp")
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(
+            request.header("x-signature-256"),
+            Some(signature("s3cr3t", timestamp, b"{\"a\":1}").as_str())
+        );
+    }
+
+    #[actix_web::test]
+    async fn client_errors_are_not_retried() {
+        let server = server(&[404]);
+
+        let delivery = deliver(&server.url, None, b"{}".to_vec()).await;
+
+        assert!(!delivery.delivered);
+        assert_eq!(delivery.attempts, 1);
+        assert_eq!(server.requests().len(), 1);
+        assert!(server.requests()[0].header("x-signature-256").is_none());
+    }
+
+    #[actix_web::test]
+    async fn server_errors_and_rate_limits_are_retried() {
+        let server = server(&[503, 429, 204]);
+
+        let delivery = deliver(&server.url, None, b"{}".to_vec()).await;
+
+        assert!(delivery.delivered);
+        assert_eq!(delivery.attempts, 3);
+    }
+}